@@ -0,0 +1,445 @@
+//! Integer arithmetic for `$((...))` and `((...))`.
+//!
+//! Expressions use 64-bit signed integers with wrapping overflow and the C
+//! operators and precedence, plus `**`. Variables are referenced by name; an
+//! unset or empty variable is 0, and other values are evaluated in turn.
+
+use thiserror::Error;
+
+use crate::variables::Variables;
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("{0}")]
+pub struct ArithError(String);
+
+/// How deeply variables whose values are expressions may refer to each other.
+const MAX_DEPTH: usize = 32;
+
+/// Evaluates `expression`, reading and assigning variables in `variables`.
+pub fn evaluate(expression: &str, variables: &mut Variables) -> Result<i64, ArithError> {
+    evaluate_nested(expression, variables, 0)
+}
+
+fn evaluate_nested(
+    expression: &str,
+    variables: &mut Variables,
+    depth: usize,
+) -> Result<i64, ArithError> {
+    if depth > MAX_DEPTH {
+        return Err(ArithError(
+            "expression recursion level exceeded".to_string(),
+        ));
+    }
+    let tokens = tokenize(expression)?;
+    if tokens.is_empty() {
+        return Ok(0);
+    }
+    let mut parser = Parser { tokens, pos: 0 };
+    let expr = parser.parse_comma()?;
+    if let Some(token) = parser.tokens.get(parser.pos) {
+        return Err(ArithError(format!(
+            "syntax error: invalid arithmetic operator (error token is \"{}\")",
+            token
+        )));
+    }
+    Evaluator { variables, depth }.eval(&expr)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Op(&'static str),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "{}", n),
+            Token::Name(name) => write!(f, "{}", name),
+            Token::Op(op) => write!(f, "{}", op),
+        }
+    }
+}
+
+/// Operators, longest first so that the tokenizer is greedy.
+const OPERATORS: [&str; 39] = [
+    "<<=", ">>=", "**", "++", "--", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+=", "-=",
+    "*=", "/=", "%=", "&=", "^=", "|=", "+", "-", "*", "/", "%", "<", ">", "=", "!", "~", "&", "^",
+    "|", "?", ":", "(", ")", ",",
+];
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, ArithError> {
+    let mut tokens = Vec::new();
+    let mut rest = expression.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let len = if c.is_ascii_digit() {
+            let len = rest
+                .find(|c: char| !c.is_ascii_alphanumeric())
+                .unwrap_or(rest.len());
+            tokens.push(Token::Number(parse_number(&rest[..len])?));
+            len
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let len = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            tokens.push(Token::Name(rest[..len].to_string()));
+            len
+        } else {
+            let op = OPERATORS
+                .iter()
+                .find(|op| rest.starts_with(**op))
+                .ok_or_else(|| {
+                    ArithError(format!(
+                        "syntax error: invalid arithmetic operator (error token is \"{}\")",
+                        rest
+                    ))
+                })?;
+            tokens.push(Token::Op(op));
+            op.len()
+        };
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+/// Parses a decimal, `0x` hexadecimal or `0` octal constant.
+fn parse_number(text: &str) -> Result<i64, ArithError> {
+    let (digits, radix) =
+        if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+            (hex, 16)
+        } else if text.len() > 1 && text.starts_with('0') {
+            (&text[1..], 8)
+        } else {
+            (text, 10)
+        };
+    u64::from_str_radix(digits, radix)
+        .map(|n| n as i64)
+        .map_err(|_| {
+            ArithError(format!(
+                "value too great for base (error token is \"{}\")",
+                text
+            ))
+        })
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Number(i64),
+    Variable(String),
+    /// `-x`, `+x`, `!x` or `~x`.
+    Unary(&'static str, Box<Expr>),
+    /// `++x` or `--x` (`postfix` false), `x++` or `x--` (`postfix` true).
+    Increment {
+        name: String,
+        delta: i64,
+        postfix: bool,
+    },
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    /// `=` or a compound assignment such as `+=`, with the binary operator it applies.
+    Assign(String, Option<&'static str>, Box<Expr>),
+    Conditional(Box<Expr>, Box<Expr>, Box<Expr>),
+}
+
+/// Binary operators from lowest to highest precedence, all left-associative.
+const BINARY_LEVELS: [&[&str]; 10] = [
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<", "<=", ">", ">="],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek_op(&self) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Op(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn eat_op(&mut self, op: &str) -> bool {
+        if self.peek_op() == Some(op) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_op(&mut self, op: &str) -> Result<(), ArithError> {
+        if self.eat_op(op) {
+            Ok(())
+        } else {
+            Err(self.operand_expected())
+        }
+    }
+
+    fn operand_expected(&self) -> ArithError {
+        match self.tokens.get(self.pos) {
+            Some(token) => ArithError(format!(
+                "syntax error: operand expected (error token is \"{}\")",
+                token
+            )),
+            None => ArithError("syntax error: operand expected".to_string()),
+        }
+    }
+
+    fn parse_comma(&mut self) -> Result<Expr, ArithError> {
+        let mut expr = self.parse_assignment()?;
+        while self.eat_op(",") {
+            let right = self.parse_assignment()?;
+            expr = Expr::Binary(",", Box::new(expr), Box::new(right));
+        }
+        Ok(expr)
+    }
+
+    fn parse_assignment(&mut self) -> Result<Expr, ArithError> {
+        if let (Some(Token::Name(name)), Some(Token::Op(op))) =
+            (self.tokens.get(self.pos), self.tokens.get(self.pos + 1))
+        {
+            let operator = match *op {
+                "=" => Some(None),
+                "+=" => Some(Some("+")),
+                "-=" => Some(Some("-")),
+                "*=" => Some(Some("*")),
+                "/=" => Some(Some("/")),
+                "%=" => Some(Some("%")),
+                "<<=" => Some(Some("<<")),
+                ">>=" => Some(Some(">>")),
+                "&=" => Some(Some("&")),
+                "^=" => Some(Some("^")),
+                "|=" => Some(Some("|")),
+                _ => None,
+            };
+            if let Some(operator) = operator {
+                let name = name.clone();
+                self.pos += 2;
+                let value = self.parse_assignment()?;
+                return Ok(Expr::Assign(name, operator, Box::new(value)));
+            }
+        }
+        let expr = self.parse_conditional()?;
+        if matches!(self.peek_op(), Some(op) if op.ends_with('=') && !matches!(op, "==" | "!=" | "<=" | ">="))
+        {
+            return Err(ArithError(
+                "attempted assignment to non-variable".to_string(),
+            ));
+        }
+        Ok(expr)
+    }
+
+    fn parse_conditional(&mut self) -> Result<Expr, ArithError> {
+        let condition = self.parse_binary(0)?;
+        if !self.eat_op("?") {
+            return Ok(condition);
+        }
+        let then = self.parse_comma()?;
+        self.expect_op(":")?;
+        let otherwise = self.parse_conditional()?;
+        Ok(Expr::Conditional(
+            Box::new(condition),
+            Box::new(then),
+            Box::new(otherwise),
+        ))
+    }
+
+    fn parse_binary(&mut self, level: usize) -> Result<Expr, ArithError> {
+        if level == BINARY_LEVELS.len() {
+            return self.parse_power();
+        }
+        let mut expr = self.parse_binary(level + 1)?;
+        while let Some(op) = self
+            .peek_op()
+            .filter(|op| BINARY_LEVELS[level].contains(op))
+        {
+            self.pos += 1;
+            let right = self.parse_binary(level + 1)?;
+            expr = Expr::Binary(op, Box::new(expr), Box::new(right));
+        }
+        Ok(expr)
+    }
+
+    /// `**` binds tighter than `*` and associates to the right.
+    fn parse_power(&mut self) -> Result<Expr, ArithError> {
+        let base = self.parse_unary()?;
+        if self.eat_op("**") {
+            let exponent = self.parse_power()?;
+            return Ok(Expr::Binary("**", Box::new(base), Box::new(exponent)));
+        }
+        Ok(base)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, ArithError> {
+        match self.peek_op() {
+            Some(op @ ("++" | "--")) => {
+                self.pos += 1;
+                let Some(Token::Name(name)) = self.tokens.get(self.pos).cloned() else {
+                    return Err(self.operand_expected());
+                };
+                self.pos += 1;
+                Ok(Expr::Increment {
+                    name,
+                    delta: if op == "++" { 1 } else { -1 },
+                    postfix: false,
+                })
+            }
+            Some(op @ ("-" | "+" | "!" | "~")) => {
+                self.pos += 1;
+                Ok(Expr::Unary(op, Box::new(self.parse_unary()?)))
+            }
+            _ => self.parse_postfix(),
+        }
+    }
+
+    fn parse_postfix(&mut self) -> Result<Expr, ArithError> {
+        match self.tokens.get(self.pos).cloned() {
+            Some(Token::Number(n)) => {
+                self.pos += 1;
+                Ok(Expr::Number(n))
+            }
+            Some(Token::Name(name)) => {
+                self.pos += 1;
+                match self.peek_op() {
+                    Some(op @ ("++" | "--")) => {
+                        self.pos += 1;
+                        Ok(Expr::Increment {
+                            name,
+                            delta: if op == "++" { 1 } else { -1 },
+                            postfix: true,
+                        })
+                    }
+                    _ => Ok(Expr::Variable(name)),
+                }
+            }
+            Some(Token::Op("(")) => {
+                self.pos += 1;
+                let expr = self.parse_comma()?;
+                self.expect_op(")")?;
+                Ok(expr)
+            }
+            _ => Err(self.operand_expected()),
+        }
+    }
+}
+
+struct Evaluator<'a> {
+    variables: &'a mut Variables,
+    depth: usize,
+}
+
+impl Evaluator<'_> {
+    fn eval(&mut self, expr: &Expr) -> Result<i64, ArithError> {
+        Ok(match expr {
+            Expr::Number(n) => *n,
+            Expr::Variable(name) => self.variable(name)?,
+            Expr::Unary(op, operand) => {
+                let value = self.eval(operand)?;
+                match *op {
+                    "-" => value.wrapping_neg(),
+                    "!" => i64::from(value == 0),
+                    "~" => !value,
+                    _ => value,
+                }
+            }
+            Expr::Increment {
+                name,
+                delta,
+                postfix,
+            } => {
+                let old = self.variable(name)?;
+                let new = old.wrapping_add(*delta);
+                self.assign(name, new)?;
+                if *postfix {
+                    old
+                } else {
+                    new
+                }
+            }
+            Expr::Binary("&&", left, right) => {
+                i64::from(self.eval(left)? != 0 && self.eval(right)? != 0)
+            }
+            Expr::Binary("||", left, right) => {
+                i64::from(self.eval(left)? != 0 || self.eval(right)? != 0)
+            }
+            Expr::Binary(op, left, right) => {
+                let left = self.eval(left)?;
+                let right = self.eval(right)?;
+                apply(op, left, right)?
+            }
+            Expr::Assign(name, op, value) => {
+                let value = self.eval(value)?;
+                let value = match op {
+                    Some(op) => apply(op, self.variable(name)?, value)?,
+                    None => value,
+                };
+                self.assign(name, value)?;
+                value
+            }
+            Expr::Conditional(condition, then, otherwise) => {
+                if self.eval(condition)? != 0 {
+                    self.eval(then)?
+                } else {
+                    self.eval(otherwise)?
+                }
+            }
+        })
+    }
+
+    fn variable(&mut self, name: &str) -> Result<i64, ArithError> {
+        let value = self
+            .variables
+            .get(name)
+            .unwrap_or_default()
+            .trim()
+            .to_string();
+        if value.is_empty() {
+            return Ok(0);
+        }
+        evaluate_nested(&value, self.variables, self.depth + 1)
+    }
+
+    fn assign(&mut self, name: &str, value: i64) -> Result<(), ArithError> {
+        self.variables
+            .set(name, value.to_string())
+            .map_err(|e| ArithError(e.to_string()))
+    }
+}
+
+fn apply(op: &str, left: i64, right: i64) -> Result<i64, ArithError> {
+    let division_by_zero = || ArithError("division by 0".to_string());
+    Ok(match op {
+        "," => right,
+        "|" => left | right,
+        "^" => left ^ right,
+        "&" => left & right,
+        "==" => i64::from(left == right),
+        "!=" => i64::from(left != right),
+        "<" => i64::from(left < right),
+        "<=" => i64::from(left <= right),
+        ">" => i64::from(left > right),
+        ">=" => i64::from(left >= right),
+        "<<" => left.wrapping_shl(right as u32),
+        ">>" => left.wrapping_shr(right as u32),
+        "+" => left.wrapping_add(right),
+        "-" => left.wrapping_sub(right),
+        "*" => left.wrapping_mul(right),
+        "/" if right == 0 => return Err(division_by_zero()),
+        "/" => left.wrapping_div(right),
+        "%" if right == 0 => return Err(division_by_zero()),
+        "%" => left.wrapping_rem(right),
+        "**" if right < 0 => return Err(ArithError("exponent less than 0".to_string())),
+        "**" => left.wrapping_pow(right.min(u32::MAX as i64) as u32),
+        _ => unreachable!("{} is not a binary operator", op),
+    })
+}
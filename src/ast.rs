@@ -0,0 +1,180 @@
+//! Syntax tree produced by the parser.
+//!
+//! Words keep their source text, quotes included; expansion happens when a
+//! command runs so it sees the shell state at that moment.
+
+use std::rc::Rc;
+
+/// A byte range in the parsed source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+}
+
+/// A sequence of and-or lists separated by `;`, `&` or newlines.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct List {
+    pub items: Vec<ListItem>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListItem {
+    pub and_or: AndOr,
+    /// Terminated by `&` rather than `;` or a newline.
+    pub background: bool,
+}
+
+/// Pipelines joined by `&&` and `||`, evaluated left to right.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AndOr {
+    pub first: Pipeline,
+    pub rest: Vec<(Connector, Pipeline)>,
+    /// The source text, as `jobs` shows it.
+    pub text: String,
+}
+
+/// How a pipeline in an and-or list depends on the one before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Connector {
+    /// `&&`: runs only if the previous status was zero.
+    And,
+    /// `||`: runs only if the previous status was non-zero.
+    Or,
+}
+
+/// Commands joined by `|`, optionally negated with a leading `!`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pipeline {
+    pub negated: bool,
+    pub commands: Vec<Command>,
+    /// The source text, as `jobs` shows it.
+    pub text: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Simple(SimpleCommand),
+    Compound(CompoundCommand, Vec<Redirect>),
+    /// Defines a function; the definition is shared with the shell's
+    /// function table rather than copied into it.
+    Function(Rc<FunctionDefinition>),
+}
+
+/// `name() compound-command [redirections]` or `function name compound-command`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionDefinition {
+    pub name: String,
+    pub body: CompoundCommand,
+    /// Applied each time the function is called.
+    pub redirects: Vec<Redirect>,
+    /// The source text, as `type` shows it.
+    pub text: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompoundCommand {
+    /// `{ list; }`: runs in the current shell.
+    BraceGroup(List),
+    /// `( list )`: runs in a copy of the shell whose changes are discarded.
+    Subshell(List),
+    /// `(( expression ))`: succeeds when the expression is non-zero.
+    Arithmetic(Word),
+    /// `if list; then list; [elif list; then list;]... [else list;] fi`
+    If(IfCommand),
+    /// `while list; do list; done`
+    While(Loop),
+    /// `until list; do list; done`: loops while the condition fails.
+    Until(Loop),
+    /// `for name [in word...]; do list; done`
+    For(ForLoop),
+    /// `case word in [(]pattern[|pattern]...) list;; ... esac`
+    Case(CaseCommand),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IfCommand {
+    /// The `if` and `elif` conditions with the bodies they guard, tried in order.
+    pub branches: Vec<(List, List)>,
+    pub else_body: Option<List>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Loop {
+    pub condition: List,
+    pub body: List,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForLoop {
+    pub name: String,
+    /// The words after `in`, or `None` to loop over the positional parameters.
+    pub words: Option<Vec<Word>>,
+    pub body: List,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaseCommand {
+    pub word: Word,
+    pub items: Vec<CaseItem>,
+}
+
+/// One `pattern|pattern) list;;` of a `case` command.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaseItem {
+    pub patterns: Vec<Word>,
+    pub body: List,
+}
+
+/// `NAME=value ... word ...` with redirections anywhere in between.
+///
+/// `words` is empty for commands made only of assignments or redirections.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SimpleCommand {
+    pub assignments: Vec<Assignment>,
+    pub words: Vec<Word>,
+    pub redirects: Vec<Redirect>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assignment {
+    pub name: String,
+    pub value: Word,
+}
+
+/// A word as written in the source, before expansion and quote removal.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Word {
+    pub text: String,
+    pub span: Span,
+}
+
+/// A file descriptor redirection, applied left to right.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Redirect {
+    pub fd: u32,
+    pub kind: RedirectKind,
+    pub target: Word,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedirectKind {
+    /// `<`
+    Read,
+    /// `>`
+    Write,
+    /// `>>`
+    Append,
+    /// `>&`: the target is another file descriptor.
+    Duplicate,
+    /// `&>`: stdout and stderr both go to the target.
+    WriteBoth,
+    /// `&>>`
+    AppendBoth,
+}
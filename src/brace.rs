@@ -0,0 +1,235 @@
+//! Brace expansion, the first step of word expansion.
+//!
+//! `pre{a,b}post` becomes `preapost prebpost`, and `{x..y[..step]}` counts
+//! from `x` to `y`, where both are integers or both single letters. Integers
+//! written with a leading zero are padded to the same width. Braces inside
+//! quotes or parameter, command or arithmetic expansions are left alone, and
+//! a brace pair with neither a top-level comma nor a valid sequence is kept
+//! as it is.
+
+/// Expands the braces in the source text of a word into the texts of the
+/// words it stands for, in order.
+pub fn expand(text: &str) -> Vec<String> {
+    let chars: Vec<char> = text.chars().collect();
+    expand_chars(&chars)
+}
+
+fn expand_chars(chars: &[char]) -> Vec<String> {
+    let mut search = 0;
+    while let Some(open) = find_open(chars, search) {
+        let Some((close, alternatives)) = find_close(chars, open + 1)
+            .and_then(|close| Some((close, alternatives(&chars[open + 1..close])?)))
+        else {
+            search = open + 1;
+            continue;
+        };
+
+        let prefix: String = chars[..open].iter().collect();
+        let suffixes = expand_chars(&chars[close + 1..]);
+        let mut words = Vec::new();
+        for alternative in alternatives {
+            for middle in alternative {
+                for suffix in &suffixes {
+                    words.push(format!("{}{}{}", prefix, middle, suffix));
+                }
+            }
+        }
+        return words;
+    }
+    vec![chars.iter().collect()]
+}
+
+/// The words a brace body stands for, each expanded in turn, or `None` if
+/// the body is neither a list nor a sequence.
+fn alternatives(body: &[char]) -> Option<Vec<Vec<String>>> {
+    let commas = top_level_commas(body);
+    if commas.is_empty() {
+        let body: String = body.iter().collect();
+        return sequence(&body).map(|words| words.into_iter().map(|word| vec![word]).collect());
+    }
+    let mut start = 0;
+    let mut alternatives = Vec::new();
+    for end in commas.into_iter().chain([body.len()]) {
+        alternatives.push(expand_chars(&body[start..end]));
+        start = end + 1;
+    }
+    Some(alternatives)
+}
+
+/// Expands `x..y` or `x..y..step` into the words it counts through.
+fn sequence(body: &str) -> Option<Vec<String>> {
+    let parts: Vec<&str> = body.split("..").collect();
+    let (first, last, step) = match parts[..] {
+        [first, last] => (first, last, 1),
+        [first, last, step] => (first, last, step.parse::<i64>().ok()?),
+        _ => return None,
+    };
+    // The sign of the step is ignored: the direction comes from the endpoints.
+    let step = step.unsigned_abs().max(1);
+
+    if let (Ok(start), Ok(end)) = (first.parse::<i64>(), last.parse::<i64>()) {
+        let width = if has_leading_zero(first) || has_leading_zero(last) {
+            first.len().max(last.len())
+        } else {
+            0
+        };
+        let numbers = count(i128::from(start), i128::from(end), step);
+        return Some(numbers.map(|n| pad(n, width)).collect());
+    }
+
+    let (mut first_chars, mut last_chars) = (first.chars(), last.chars());
+    match (
+        first_chars.next(),
+        first_chars.next(),
+        last_chars.next(),
+        last_chars.next(),
+    ) {
+        (Some(start), None, Some(end), None)
+            if start.is_ascii_alphabetic() && end.is_ascii_alphabetic() =>
+        {
+            let letters = count(i128::from(start as u8), i128::from(end as u8), step);
+            Some(letters.map(|c| char::from(c as u8).to_string()).collect())
+        }
+        _ => None,
+    }
+}
+
+/// Counts from `start` towards `end` inclusive, in steps of `step`.
+fn count(start: i128, end: i128, step: u64) -> impl Iterator<Item = i128> {
+    let step = i128::from(step);
+    let (step, steps) = if start <= end {
+        (step, (end - start) / step)
+    } else {
+        (-step, (start - end) / step)
+    };
+    (0..=steps).map(move |i| start + i * step)
+}
+
+fn has_leading_zero(number: &str) -> bool {
+    let digits = number.strip_prefix('-').unwrap_or(number);
+    digits.len() > 1 && digits.starts_with('0')
+}
+
+/// Formats `n` zero-padded to `width` characters, counting any minus sign.
+fn pad(n: i128, width: usize) -> String {
+    if n < 0 {
+        format!("-{:0>1$}", -n, width.saturating_sub(1))
+    } else {
+        format!("{:0>1$}", n, width)
+    }
+}
+
+/// Finds the next `{` at or after `start` that can open a brace expansion.
+fn find_open(chars: &[char], start: usize) -> Option<usize> {
+    let mut i = start;
+    while i < chars.len() {
+        match chars[i] {
+            '{' => return Some(i),
+            _ => i = skip(chars, i)?,
+        }
+    }
+    None
+}
+
+/// Finds the `}` matching a `{` just before `start`.
+fn find_close(chars: &[char], start: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = start;
+    while i < chars.len() {
+        match chars[i] {
+            '{' => {
+                depth += 1;
+                i += 1;
+            }
+            '}' if depth == 0 => return Some(i),
+            '}' => {
+                depth -= 1;
+                i += 1;
+            }
+            _ => i = skip(chars, i)?,
+        }
+    }
+    None
+}
+
+/// The positions of the commas in `body` outside nested braces.
+fn top_level_commas(body: &[char]) -> Vec<usize> {
+    let mut commas = Vec::new();
+    let mut depth = 0;
+    let mut i = 0;
+    while i < body.len() {
+        match body[i] {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            ',' if depth == 0 => commas.push(i),
+            _ => {
+                i = skip(body, i).unwrap_or(body.len());
+                continue;
+            }
+        }
+        i += 1;
+    }
+    commas
+}
+
+/// The index just past the quoted string, escape, expansion or plain
+/// character at `i`, or `None` if it runs off the end of the word.
+fn skip(chars: &[char], i: usize) -> Option<usize> {
+    match chars[i] {
+        '\\' => (i + 2 <= chars.len()).then_some(i + 2),
+        '\'' => Some(find(chars, i + 1, '\'')? + 1),
+        '`' => Some(find(chars, i + 1, '`')? + 1),
+        '"' => {
+            let mut j = i + 1;
+            while j < chars.len() {
+                match chars[j] {
+                    '"' => return Some(j + 1),
+                    '$' | '`' | '\\' => j = skip(chars, j)?,
+                    _ => j += 1,
+                }
+            }
+            None
+        }
+        '$' => match chars.get(i + 1) {
+            Some('{') => Some(find_matching(chars, i + 2, '{', '}')? + 1),
+            Some('(') => Some(find_matching(chars, i + 2, '(', ')')? + 1),
+            _ => Some(i + 1),
+        },
+        _ => Some(i + 1),
+    }
+}
+
+/// Finds the next unescaped `target` at or after `start`.
+fn find(chars: &[char], start: usize, target: char) -> Option<usize> {
+    let mut i = start;
+    while i < chars.len() {
+        match chars[i] {
+            c if c == target => return Some(i),
+            '\\' if target == '`' => i += 2,
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+/// Finds the `close` matching an `open` just before `start`, skipping quotes
+/// and nested expansions.
+fn find_matching(chars: &[char], start: usize, open: char, close: char) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = start;
+    while i < chars.len() {
+        match chars[i] {
+            c if c == close && depth == 0 => return Some(i),
+            c if c == close => {
+                depth -= 1;
+                i += 1;
+            }
+            c if c == open => {
+                depth += 1;
+                i += 1;
+            }
+            _ => i = skip(chars, i)?,
+        }
+    }
+    None
+}
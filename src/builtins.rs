@@ -0,0 +1,828 @@
+use std::env;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::complete::CompletionSpec;
+use crate::helpers;
+use crate::jobs::{self, JobState};
+use crate::parser;
+use crate::shell::{Control, Shell, ShellOptions};
+use crate::signals;
+use crate::streams::Io;
+use crate::trap::Condition;
+use crate::variables::{Variable, Variables};
+
+pub const BUILT_INS: [&str; 22] = [
+    "exit", "version", "echo", "type", "pwd", "cd", "export", "unset", "readonly", "shopt",
+    "history", "complete", "jobs", "fg", "bg", "wait", "disown", "trap", "break", "continue",
+    "local", "return",
+];
+
+pub fn is_builtin(name: &str) -> bool {
+    BUILT_INS.contains(&name)
+}
+
+/// Runs the builtin `name` with its output going to `io`, returning its exit status.
+pub fn run(shell: &mut Shell, name: &str, args: &[String], io: &mut Io) -> io::Result<i32> {
+    let status = match name {
+        "exit" => return exit(shell, args, io),
+        "version" => {
+            writeln!(io.out(), "Simple Shell v0.1.0")?;
+            0
+        }
+        "echo" => echo(args, io)?,
+        "type" => type_builtin(shell, args, io)?,
+        "pwd" => match env::current_dir() {
+            Ok(path) => {
+                writeln!(io.out(), "{}", path.display())?;
+                0
+            }
+            Err(e) => {
+                writeln!(io.err(), "pwd: {}", e)?;
+                1
+            }
+        },
+        "cd" => cd(shell, args, io)?,
+        "export" => export(shell, args, io)?,
+        "unset" => unset(shell, args, io)?,
+        "readonly" => readonly(shell, args, io)?,
+        "shopt" => shopt(shell, args, io)?,
+        "history" => history(shell, args, io)?,
+        "complete" => complete(shell, args, io)?,
+        "jobs" => jobs(shell, args, io)?,
+        "fg" => fg(shell, args, io)?,
+        "bg" => bg(shell, args, io)?,
+        "wait" => wait(shell, args, io)?,
+        "disown" => disown(shell, args, io)?,
+        "trap" => trap(shell, args, io)?,
+        "break" | "continue" => loop_control(shell, name, args, io)?,
+        "local" => local(shell, args, io)?,
+        "return" => return return_builtin(shell, args, io),
+        _ => unreachable!("{} is not a builtin", name),
+    };
+    Ok(status)
+}
+
+/// `exit [N]`: leaves the shell with status N, or with `$?` when N is omitted.
+fn exit(shell: &mut Shell, args: &[String], io: &mut Io) -> io::Result<i32> {
+    let code = match args {
+        [] => shell.last_status,
+        [code] => match code.parse::<i64>() {
+            // Like other shells, only the low eight bits survive.
+            Ok(code) => (code & 0xff) as i32,
+            Err(_) => {
+                writeln!(io.err(), "exit: {}: numeric argument required", code)?;
+                2
+            }
+        },
+        _ => {
+            writeln!(io.err(), "exit: too many arguments")?;
+            return Ok(1);
+        }
+    };
+    shell.control = Some(Control::Exit(code));
+    Ok(code)
+}
+
+/// `break [N]` and `continue [N]`: leave the N innermost loops, or go on
+/// with the next iteration of the Nth one.
+fn loop_control(shell: &mut Shell, name: &str, args: &[String], io: &mut Io) -> io::Result<i32> {
+    let count = match args {
+        [] => 1,
+        [count] => match count.parse::<usize>() {
+            Ok(0) => {
+                writeln!(io.err(), "{}: {}: loop count out of range", name, count)?;
+                return Ok(1);
+            }
+            Ok(count) => count,
+            Err(_) => {
+                writeln!(io.err(), "{}: {}: numeric argument required", name, count)?;
+                return Ok(1);
+            }
+        },
+        _ => {
+            writeln!(io.err(), "{}: too many arguments", name)?;
+            return Ok(1);
+        }
+    };
+    if shell.loop_depth == 0 {
+        writeln!(
+            io.err(),
+            "{}: only meaningful in a `for', `while', or `until' loop",
+            name
+        )?;
+        return Ok(0);
+    }
+    // Asking to leave more loops than there are leaves them all.
+    let count = count.min(shell.loop_depth);
+    shell.control = Some(if name == "break" {
+        Control::Break(count)
+    } else {
+        Control::Continue(count)
+    });
+    Ok(0)
+}
+
+/// `return [N]`: leaves the running function with status N, or with `$?`
+/// when N is omitted.
+fn return_builtin(shell: &mut Shell, args: &[String], io: &mut Io) -> io::Result<i32> {
+    if shell.function_depth == 0 {
+        writeln!(io.err(), "return: can only `return' from a function")?;
+        return Ok(1);
+    }
+    let code = match args {
+        [] => shell.last_status,
+        [code] => match code.parse::<i64>() {
+            Ok(code) => (code & 0xff) as i32,
+            Err(_) => {
+                writeln!(io.err(), "return: {}: numeric argument required", code)?;
+                2
+            }
+        },
+        _ => {
+            writeln!(io.err(), "return: too many arguments")?;
+            return Ok(1);
+        }
+    };
+    shell.control = Some(Control::Return(code));
+    Ok(code)
+}
+
+/// `local NAME[=value]...`: gives the running function its own copies of
+/// variables, which the functions it calls see too.
+fn local(shell: &mut Shell, args: &[String], io: &mut Io) -> io::Result<i32> {
+    if shell.function_depth == 0 {
+        writeln!(io.err(), "local: can only be used in a function")?;
+        return Ok(1);
+    }
+    let mut status = 0;
+    for arg in args {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (arg.as_str(), None),
+        };
+        if !parser::is_name(name) {
+            writeln!(io.err(), "local: `{}': not a valid identifier", arg)?;
+            status = 1;
+            continue;
+        }
+        if let Err(e) = shell.variables.make_local(name) {
+            writeln!(io.err(), "local: {}", e)?;
+            status = 1;
+            continue;
+        }
+        if let Some(value) = value {
+            // The variable was just checked not to be readonly.
+            let _ = shell.variables.set(name, value);
+        }
+    }
+    Ok(status)
+}
+
+fn echo(args: &[String], io: &mut Io) -> io::Result<i32> {
+    if args.is_empty() {
+        writeln!(io.err(), "echo: no arguments provided")?;
+        return Ok(1);
+    }
+    writeln!(io.out(), "{}", args.join(" "))?;
+    Ok(0)
+}
+
+fn type_builtin(shell: &Shell, args: &[String], io: &mut Io) -> io::Result<i32> {
+    if args.is_empty() {
+        writeln!(io.err(), "type: no arguments provided")?;
+        return Ok(1);
+    }
+    if args.len() != 1 {
+        writeln!(io.err(), "type: expected exactly one argument")?;
+        return Ok(1);
+    }
+    let executable = &args[0];
+    if let Some(function) = shell.functions.get(executable) {
+        writeln!(io.out(), "{} is a function", executable)?;
+        writeln!(io.out(), "{}", function.text)?;
+        return Ok(0);
+    }
+    if is_builtin(executable) {
+        writeln!(io.out(), "{} is a shell builtin", executable)?;
+        return Ok(0);
+    }
+
+    let search_path = shell.variables.get("PATH").unwrap_or_default();
+    match helpers::find_in_path(executable, search_path) {
+        Some(full_path) => {
+            writeln!(io.out(), "{} is {}", executable, full_path.display())?;
+            Ok(0)
+        }
+        None => {
+            writeln!(io.err(), "{}: not found", executable)?;
+            Ok(1)
+        }
+    }
+}
+
+/// `cd [DIR]` or `cd -`: changes directory, to `$HOME` without `DIR`,
+/// keeping `$PWD` and `$OLDPWD` up to date.
+fn cd(shell: &mut Shell, args: &[String], io: &mut Io) -> io::Result<i32> {
+    if args.len() > 1 {
+        writeln!(io.err(), "cd: too many arguments")?;
+        return Ok(1);
+    }
+    let path_str = if args.is_empty() {
+        match shell.variables.get("HOME") {
+            Some(home) => home.to_string(),
+            None => {
+                writeln!(io.err(), "cd: HOME not set")?;
+                return Ok(1);
+            }
+        }
+    } else if args[0] == "-" {
+        match shell.variables.get("OLDPWD") {
+            Some(old) => old.to_string(),
+            None => {
+                writeln!(io.err(), "cd: OLDPWD not set")?;
+                return Ok(1);
+            }
+        }
+    } else {
+        args[0].clone()
+    };
+    let old_pwd = env::current_dir().ok();
+    let path = Path::new(&path_str);
+    match env::set_current_dir(path) {
+        Ok(()) => {
+            if let Some(old_pwd) = old_pwd {
+                let _ = shell
+                    .variables
+                    .set("OLDPWD", old_pwd.to_string_lossy().into_owned());
+            }
+            if let Ok(new_pwd) = env::current_dir() {
+                let _ = shell
+                    .variables
+                    .set("PWD", new_pwd.to_string_lossy().into_owned());
+                if args.first().is_some_and(|arg| arg == "-") {
+                    writeln!(io.out(), "{}", new_pwd.display())?;
+                }
+            }
+            Ok(0)
+        }
+        Err(e) => {
+            // Extract just the error kind and create a simpler message
+            let msg = match e.kind() {
+                io::ErrorKind::NotFound => "No such file or directory",
+                io::ErrorKind::PermissionDenied => "Permission denied",
+                _ => "Error changing directory",
+            };
+            writeln!(io.err(), "cd: {}: {}", path_str, msg)?;
+            Ok(1)
+        }
+    }
+}
+
+/// `export [-p] [NAME[=value]...]`: marks variables for child processes,
+/// or lists the exported ones.
+fn export(shell: &mut Shell, args: &[String], io: &mut Io) -> io::Result<i32> {
+    declare(
+        shell,
+        "export",
+        args,
+        io,
+        |variable| variable.exported,
+        Variables::export,
+    )
+}
+
+/// `readonly [-p] [NAME[=value]...]`: forbids further changes to variables,
+/// or lists the readonly ones.
+fn readonly(shell: &mut Shell, args: &[String], io: &mut Io) -> io::Result<i32> {
+    declare(
+        shell,
+        "readonly",
+        args,
+        io,
+        |variable| variable.readonly,
+        Variables::set_readonly,
+    )
+}
+
+/// Shared body of `export` and `readonly`, which differ only in the attribute they set.
+fn declare(
+    shell: &mut Shell,
+    builtin: &str,
+    args: &[String],
+    io: &mut Io,
+    has_attribute: fn(&Variable) -> bool,
+    set_attribute: fn(&mut Variables, &str),
+) -> io::Result<i32> {
+    if args.is_empty() || args == ["-p"] {
+        for (name, variable) in shell.variables.sorted() {
+            if !has_attribute(variable) {
+                continue;
+            }
+            match &variable.value {
+                Some(value) => writeln!(io.out(), "{} {}={}", builtin, name, quote(value))?,
+                None => writeln!(io.out(), "{} {}", builtin, name)?,
+            }
+        }
+        return Ok(0);
+    }
+
+    let mut status = 0;
+    for arg in args {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (arg.as_str(), None),
+        };
+        if !parser::is_name(name) {
+            writeln!(io.err(), "{}: `{}': not a valid identifier", builtin, arg)?;
+            status = 1;
+            continue;
+        }
+        if let Some(value) = value {
+            if let Err(e) = shell.variables.set(name, value) {
+                writeln!(io.err(), "{}: {}", builtin, e)?;
+                status = 1;
+                continue;
+            }
+        }
+        set_attribute(&mut shell.variables, name);
+    }
+    Ok(status)
+}
+
+/// `unset [-v] NAME...` or `unset -f NAME...`: removes shell variables or functions.
+fn unset(shell: &mut Shell, args: &[String], io: &mut Io) -> io::Result<i32> {
+    let (names, functions) = match args {
+        [flag, names @ ..] if flag == "-v" => (names, false),
+        [flag, names @ ..] if flag == "-f" => (names, true),
+        names => (names, false),
+    };
+    let mut status = 0;
+    for name in names {
+        if functions {
+            shell.functions.remove(name);
+        } else if !parser::is_name(name) {
+            writeln!(io.err(), "unset: `{}': not a valid identifier", name)?;
+            status = 1;
+        } else if shell.variables.unset(name).is_err() {
+            writeln!(io.err(), "unset: {}: cannot unset: readonly variable", name)?;
+            status = 1;
+        }
+    }
+    Ok(status)
+}
+
+/// `shopt [-s|-u] [NAME...]`: sets (`-s`) or unsets (`-u`) shell options.
+///
+/// Without a flag, shows the named options, or all of them, and succeeds only
+/// if all the named ones are on.
+fn shopt(shell: &mut Shell, args: &[String], io: &mut Io) -> io::Result<i32> {
+    let (setting, names) = match args {
+        [flag, names @ ..] if flag == "-s" => (Some(true), names),
+        [flag, names @ ..] if flag == "-u" => (Some(false), names),
+        [flag, ..] if flag.starts_with('-') => {
+            writeln!(io.err(), "shopt: {}: invalid option", flag)?;
+            writeln!(io.err(), "shopt: usage: shopt [-s|-u] [optname ...]")?;
+            return Ok(2);
+        }
+        names => (None, names),
+    };
+    let names: Vec<&str> = if names.is_empty() {
+        ShellOptions::NAMES.to_vec()
+    } else {
+        names.iter().map(String::as_str).collect()
+    };
+
+    let mut status = 0;
+    for name in names {
+        let Some(option) = shell.options.get_mut(name) else {
+            writeln!(io.err(), "shopt: {}: invalid shell option name", name)?;
+            status = 1;
+            continue;
+        };
+        match setting {
+            Some(value) => *option = value,
+            None => {
+                writeln!(io.out(), "{}\t{}", name, if *option { "on" } else { "off" })?;
+                if !*option && !args.is_empty() {
+                    status = 1;
+                }
+            }
+        }
+    }
+    Ok(status)
+}
+
+/// `history [N]`, `history -c`, `history -d N` or `history -w|-r [FILE]`:
+/// lists the last N commands, clears the history, deletes one entry, or
+/// writes it to or reads it from FILE, by default `$HISTFILE`.
+fn history(shell: &mut Shell, args: &[String], io: &mut Io) -> io::Result<i32> {
+    match args {
+        [] => list_history(shell, None, io),
+        [count] if !count.starts_with('-') => match count.parse::<usize>() {
+            Ok(count) => list_history(shell, Some(count), io),
+            Err(_) => {
+                writeln!(io.err(), "history: {}: numeric argument required", count)?;
+                Ok(1)
+            }
+        },
+        [flag] if flag == "-c" => {
+            shell.history.clear();
+            Ok(0)
+        }
+        [flag, offset] if flag == "-d" => {
+            let deleted = offset
+                .parse()
+                .is_ok_and(|number| shell.history.delete(number));
+            if !deleted {
+                writeln!(
+                    io.err(),
+                    "history: {}: history position out of range",
+                    offset
+                )?;
+                return Ok(1);
+            }
+            Ok(0)
+        }
+        [flag, file @ ..] if (flag == "-w" || flag == "-r") && file.len() <= 1 => {
+            let Some(path) = file
+                .first()
+                .map(PathBuf::from)
+                .or_else(|| shell.history_file())
+            else {
+                writeln!(io.err(), "history: HISTFILE not set")?;
+                return Ok(1);
+            };
+            let result = if flag == "-w" {
+                shell.history.write_file(&path)
+            } else {
+                let size = shell.history_size();
+                shell.history.read_file(&path, size)
+            };
+            if let Err(e) = result {
+                writeln!(
+                    io.err(),
+                    "history: {}: {}",
+                    path.display(),
+                    helpers::describe_io_error(&e)
+                )?;
+                return Ok(1);
+            }
+            Ok(0)
+        }
+        _ => {
+            writeln!(
+                io.err(),
+                "history: usage: history [-c] [-d offset] [n] or history -w|-r [filename]"
+            )?;
+            Ok(2)
+        }
+    }
+}
+
+/// Prints the last `count` history entries, or all of them, with their numbers.
+fn list_history(shell: &Shell, count: Option<usize>, io: &mut Io) -> io::Result<i32> {
+    let entries: Vec<(usize, &str)> = shell.history.numbered().collect();
+    let skip = count.map_or(0, |count| entries.len().saturating_sub(count));
+    for (number, entry) in &entries[skip..] {
+        writeln!(io.out(), "{:5}  {}", number, entry)?;
+    }
+    Ok(0)
+}
+
+/// `complete [-W WORDLIST] [-F FUNCTION] NAME...`, `complete -r [NAME...]` or
+/// `complete [-p] [NAME...]`: sets, removes or prints how the arguments of
+/// the named commands are completed.
+fn complete(shell: &mut Shell, args: &[String], io: &mut Io) -> io::Result<i32> {
+    let usage = |io: &mut Io| {
+        writeln!(
+            io.err(),
+            "complete: usage: complete [-pr] [-W wordlist] [-F function] [name ...]"
+        )?;
+        Ok(2)
+    };
+    let mut spec = CompletionSpec::default();
+    let (mut print, mut remove) = (false, false);
+    let mut args = args.iter();
+    let mut names = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-p" => print = true,
+            "-r" => remove = true,
+            "-W" | "-F" => {
+                let Some(value) = args.next() else {
+                    writeln!(io.err(), "complete: {}: option requires an argument", arg)?;
+                    return usage(io);
+                };
+                if arg == "-W" {
+                    spec.words = Some(value.clone());
+                } else {
+                    spec.function = Some(value.clone());
+                }
+            }
+            "--" => names.extend(args.by_ref().cloned()),
+            flag if flag.starts_with('-') && flag.len() > 1 => {
+                writeln!(io.err(), "complete: {}: invalid option", flag)?;
+                return usage(io);
+            }
+            name => names.push(name.to_string()),
+        }
+    }
+
+    let defining = spec != CompletionSpec::default();
+    if defining && !print && !remove {
+        if names.is_empty() {
+            return usage(io);
+        }
+        for name in names {
+            shell.completions.insert(name, spec.clone());
+        }
+        return Ok(0);
+    }
+    let mut missing = Vec::new();
+    if remove {
+        if names.is_empty() {
+            shell.completions.clear();
+        }
+        for name in names {
+            if shell.completions.remove(&name).is_none() {
+                missing.push(name);
+            }
+        }
+    } else {
+        if names.is_empty() {
+            names = shell.completions.keys().cloned().collect();
+        }
+        for name in names {
+            match shell.completions.get(&name) {
+                Some(spec) => writeln!(io.out(), "{}", spec.to_command(&name))?,
+                None => missing.push(name),
+            }
+        }
+    }
+    for name in &missing {
+        writeln!(io.err(), "complete: {}: no completion specification", name)?;
+    }
+    Ok(i32::from(!missing.is_empty()))
+}
+
+/// `jobs [-l|-p] [JOB...]`: lists the given jobs, or all of them, with
+/// their state, also with their process IDs (`-l`), or only the latter (`-p`).
+fn jobs(shell: &mut Shell, args: &[String], io: &mut Io) -> io::Result<i32> {
+    let (mut long, mut pids_only) = (false, false);
+    let mut specs = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "-l" => long = true,
+            "-p" => pids_only = true,
+            flag if flag.starts_with('-') && flag.len() > 1 => {
+                writeln!(io.err(), "jobs: {}: invalid option", flag)?;
+                writeln!(io.err(), "jobs: usage: jobs [-lp] [jobspec ...]")?;
+                return Ok(2);
+            }
+            spec => specs.push(spec),
+        }
+    }
+
+    shell.jobs.update()?;
+    let mut status = 0;
+    let ids = if specs.is_empty() {
+        shell.jobs.ids()
+    } else {
+        let mut ids = Vec::new();
+        for spec in specs {
+            match shell.jobs.find(Some(spec)) {
+                Ok(id) => ids.push(id),
+                Err(e) => {
+                    writeln!(io.err(), "jobs: {}", e)?;
+                    status = 1;
+                }
+            }
+        }
+        ids
+    };
+    for id in ids {
+        match shell.jobs.get(id) {
+            Some(job) if pids_only => writeln!(io.out(), "{}", job.pgid)?,
+            Some(_) => shell.jobs.report(id, long, io.out())?,
+            None => {}
+        }
+    }
+    Ok(status)
+}
+
+/// `fg [JOB]`: resumes a job, by default the current one, in the foreground
+/// and waits for it to finish or stop again.
+fn fg(shell: &mut Shell, args: &[String], io: &mut Io) -> io::Result<i32> {
+    if !shell.is_interactive() {
+        writeln!(io.err(), "fg: no job control")?;
+        return Ok(1);
+    }
+    let id = match shell.jobs.find(args.first().map(String::as_str)) {
+        Ok(id) => id,
+        Err(e) => {
+            writeln!(io.err(), "fg: {}", e)?;
+            return Ok(1);
+        }
+    };
+    let Some(job) = shell.jobs.get_mut(id) else {
+        return Ok(1);
+    };
+    writeln!(io.out(), "{}", job.command)?;
+    io.out().flush()?;
+    if shell.owns_terminal {
+        jobs::give_terminal(Some(job.pgid));
+    }
+    job.resume();
+    let state = shell.jobs.wait(id, io.out())?;
+    if shell.owns_terminal {
+        jobs::give_terminal(None);
+    }
+    if let JobState::Stopped(_) = state {
+        writeln!(io.out())?;
+        writeln!(io.out(), "{}", shell.jobs.format(id, false))?;
+        shell.control = Some(Control::Suspend);
+    } else {
+        jobs::finish_foreground(shell, state, io.err())?;
+    }
+    Ok(state.status())
+}
+
+/// `bg [JOB...]`: resumes stopped jobs, by default the current one, in the background.
+fn bg(shell: &mut Shell, args: &[String], io: &mut Io) -> io::Result<i32> {
+    if !shell.is_interactive() {
+        writeln!(io.err(), "bg: no job control")?;
+        return Ok(1);
+    }
+    let specs: Vec<Option<&str>> = if args.is_empty() {
+        vec![None]
+    } else {
+        args.iter().map(|arg| Some(arg.as_str())).collect()
+    };
+    let mut status = 0;
+    for spec in specs {
+        let id = match shell.jobs.find(spec) {
+            Ok(id) => id,
+            Err(e) => {
+                writeln!(io.err(), "bg: {}", e)?;
+                status = 1;
+                continue;
+            }
+        };
+        let Some(job) = shell.jobs.get_mut(id) else {
+            continue;
+        };
+        if job.state() == JobState::Running {
+            writeln!(io.err(), "bg: job {} already in background", id)?;
+            continue;
+        }
+        job.resume();
+        shell.jobs.touch(id);
+        let job = shell.jobs.get(id).expect("job was just found");
+        writeln!(
+            io.out(),
+            "[{}]{} {} &",
+            id,
+            shell.jobs.marker(id),
+            job.command
+        )?;
+    }
+    Ok(status)
+}
+
+/// `wait [ID...]`: waits for the given jobs or process IDs, or for all
+/// jobs, returning the status of the last one given.
+fn wait(shell: &mut Shell, args: &[String], io: &mut Io) -> io::Result<i32> {
+    if args.is_empty() {
+        for id in shell.jobs.ids() {
+            shell.jobs.wait(id, io.out())?;
+        }
+        return Ok(0);
+    }
+    let mut status = 0;
+    for arg in args {
+        status = match shell.jobs.find(Some(arg)) {
+            Ok(id) => shell.jobs.wait(id, io.out())?.status(),
+            Err(e) if arg.starts_with('%') => {
+                writeln!(io.err(), "wait: {}", e)?;
+                127
+            }
+            Err(_) => {
+                let finished = arg
+                    .parse()
+                    .ok()
+                    .and_then(|pid| shell.jobs.take_finished(pid));
+                match finished {
+                    Some(status) => status,
+                    None => {
+                        writeln!(io.err(), "wait: pid {} is not a child of this shell", arg)?;
+                        127
+                    }
+                }
+            }
+        };
+    }
+    Ok(status)
+}
+
+/// `disown [-a] [JOB...]`: removes the given jobs, by default the current
+/// one, or all jobs (`-a`) from the job table.
+fn disown(shell: &mut Shell, args: &[String], io: &mut Io) -> io::Result<i32> {
+    if args == ["-a"] {
+        for id in shell.jobs.ids() {
+            shell.jobs.remove(id);
+        }
+        return Ok(0);
+    }
+    let specs: Vec<Option<&str>> = if args.is_empty() {
+        vec![None]
+    } else {
+        args.iter().map(|arg| Some(arg.as_str())).collect()
+    };
+    let mut status = 0;
+    for spec in specs {
+        match shell.jobs.find(spec) {
+            Ok(id) => {
+                shell.jobs.remove(id);
+            }
+            Err(e) => {
+                writeln!(io.err(), "disown: {}", e)?;
+                status = 1;
+            }
+        }
+    }
+    Ok(status)
+}
+
+/// `trap [-lp] [[ACTION] CONDITION...]`: sets the command run when a signal
+/// arrives, a command fails (`ERR`) or the shell exits (`EXIT`).
+///
+/// An ACTION of `-`, or none with a single condition, resets the conditions,
+/// and an empty one ignores the signals. `-p` or no arguments print the
+/// traps as commands; `-l` lists the signals.
+fn trap(shell: &mut Shell, args: &[String], io: &mut Io) -> io::Result<i32> {
+    let args = match args {
+        [flag, rest @ ..] if flag == "--" => rest,
+        args => args,
+    };
+    let (action, specs) = match args {
+        [] => return print_traps(shell, &[], io),
+        [flag, specs @ ..] if flag == "-p" => return print_traps(shell, specs, io),
+        [flag] if flag == "-l" => {
+            for signal in signals::all() {
+                writeln!(io.out(), "{:2}) {}", signal, signals::name(signal))?;
+            }
+            return Ok(0);
+        }
+        [flag, ..] if flag.starts_with('-') && flag != "-" => {
+            writeln!(io.err(), "trap: {}: invalid option", flag)?;
+            writeln!(io.err(), "trap: usage: trap [-lp] [[arg] signal_spec ...]")?;
+            return Ok(2);
+        }
+        // A lone condition, or conditions starting with a number, are reset.
+        [_] => ("-", args),
+        [first, ..] if first.parse::<u32>().is_ok() => ("-", args),
+        [action, specs @ ..] => (action.as_str(), specs),
+    };
+
+    let mut status = 0;
+    for spec in specs {
+        let Some(condition) = Condition::parse(spec) else {
+            writeln!(io.err(), "trap: {}: invalid signal specification", spec)?;
+            status = 1;
+            continue;
+        };
+        if action == "-" {
+            shell.traps.reset(condition);
+        } else {
+            shell.traps.set(condition, action);
+        }
+    }
+    Ok(status)
+}
+
+/// Prints the traps for `specs`, or all of them, as `trap` commands.
+fn print_traps(shell: &Shell, specs: &[String], io: &mut Io) -> io::Result<i32> {
+    let mut status = 0;
+    let mut conditions = Vec::new();
+    for spec in specs {
+        match Condition::parse(spec) {
+            Some(condition) => conditions.push(condition),
+            None => {
+                writeln!(io.err(), "trap: {}: invalid signal specification", spec)?;
+                status = 1;
+            }
+        }
+    }
+    for (condition, action) in shell.traps.iter() {
+        if specs.is_empty() || conditions.contains(&condition) {
+            writeln!(io.out(), "trap -- {} {}", quote(action), condition.name())?;
+        }
+    }
+    Ok(status)
+}
+
+/// Single-quotes `value` so the shell reads it back unchanged.
+pub(crate) fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
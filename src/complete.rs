@@ -0,0 +1,434 @@
+//! Tab completion of the word before the cursor.
+//!
+//! The first word of a command completes to builtins and executables on
+//! `$PATH`, later words to file paths, and a word ending in `$NAME` to
+//! variable names. Candidates are quoted the way the word was: with the
+//! same opening quote, or with backslashes before special characters.
+//!
+//! Arguments of commands with a specification set by the `complete` builtin
+//! complete from its word list, or from `$COMPREPLY` as set by its function.
+
+use std::collections::BTreeSet;
+use std::fs::{self, File};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use crate::builtins;
+use crate::exec;
+use crate::helpers;
+use crate::parser;
+use crate::shell::Shell;
+use crate::streams::{Input, Output, Streams};
+
+/// How to complete the arguments of a command, as set by `complete`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct CompletionSpec {
+    /// `-W`: the candidates, separated by whitespace.
+    pub words: Option<String>,
+    /// `-F`: a function that sets `$COMPREPLY` to the candidates.
+    pub function: Option<String>,
+}
+
+impl CompletionSpec {
+    /// The `complete` command that recreates this spec for `name`.
+    pub fn to_command(&self, name: &str) -> String {
+        let mut command = "complete".to_string();
+        if let Some(words) = &self.words {
+            command.push_str(&format!(" -W {}", builtins::quote(words)));
+        }
+        if let Some(function) = &self.function {
+            command.push_str(&format!(" -F {}", function));
+        }
+        format!("{} {}", command, name)
+    }
+}
+
+/// The candidates for completing the text from `start` (a character index)
+/// up to the cursor.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct Completion {
+    pub start: usize,
+    /// Replacements for the word, sorted. Those ending in `/` are
+    /// directories, which completion continues into rather than finishing.
+    pub candidates: Vec<String>,
+}
+
+/// Words that put the next word in command position.
+const COMMAND_KEYWORDS: [&str; 9] = [
+    "!", "if", "then", "else", "elif", "do", "while", "until", "{",
+];
+
+/// The words of a command line up to the cursor, as completion sees them.
+#[derive(Debug, Default)]
+pub(crate) struct CommandWords {
+    /// The words of the command the cursor is in, without quotes.
+    pub words: Vec<String>,
+    /// Index in `words` of the word being completed, which is the last one.
+    pub current: usize,
+    /// Character index where the word being completed starts.
+    pub start: usize,
+    /// The quote left open in the word being completed, if any.
+    pub open_quote: Option<char>,
+}
+
+impl CommandWords {
+    /// Splits `chars`, the line up to the cursor, into the words of its last command.
+    pub fn parse(chars: &[char]) -> CommandWords {
+        let mut command = CommandWords::default();
+        // The word in progress, if any: its text and where it started.
+        let mut word: Option<(String, usize)> = None;
+        let mut quote = None;
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            match (quote, c) {
+                (Some(q), c) if c == q => quote = None,
+                (Some('"'), '\\') if matches!(chars.get(i + 1), Some('"' | '\\' | '$' | '`')) => {
+                    i += 1;
+                    word_text(&mut word, i).push(chars[i]);
+                }
+                (Some(_), c) => word_text(&mut word, i).push(c),
+                (None, '\'' | '"') => {
+                    word_text(&mut word, i);
+                    quote = Some(c);
+                }
+                (None, '\\') => {
+                    let start = i;
+                    i += 1;
+                    if let Some(&escaped) = chars.get(i) {
+                        word_text(&mut word, start).push(escaped);
+                    }
+                }
+                (None, ';' | '&' | '|' | '(' | ')' | '<' | '>') => {
+                    if let Some((text, _)) = word.take() {
+                        command.words.push(text);
+                    }
+                    if matches!(c, ';' | '&' | '|' | '(' | ')') {
+                        command.words.clear();
+                    }
+                }
+                (None, c) if c.is_whitespace() => {
+                    if let Some((text, _)) = word.take() {
+                        command.words.push(text);
+                    }
+                }
+                (None, c) => word_text(&mut word, i).push(c),
+            }
+            i += 1;
+        }
+
+        let (text, start) = word.unwrap_or((String::new(), chars.len()));
+        command.start = start;
+        command.open_quote = quote;
+        command.words.push(text);
+        command.current = command.words.len() - 1;
+        command
+    }
+
+    /// Whether the word being completed names the command to run.
+    fn in_command_position(&self) -> bool {
+        self.command_index() == self.current
+    }
+
+    /// Index of the command name in `words`, after any leading reserved
+    /// words and `NAME=value` assignments.
+    fn command_index(&self) -> usize {
+        self.words
+            .iter()
+            .position(|word| !COMMAND_KEYWORDS.contains(&word.as_str()) && !is_assignment(word))
+            .unwrap_or(self.current)
+            .min(self.current)
+    }
+
+    /// The name of the command whose argument is being completed, if any.
+    fn command_name(&self) -> Option<&str> {
+        let index = self.command_index();
+        (index < self.current).then(|| self.words[index].as_str())
+    }
+}
+
+fn word_text(word: &mut Option<(String, usize)>, start: usize) -> &mut String {
+    &mut word.get_or_insert_with(|| (String::new(), start)).0
+}
+
+fn is_assignment(word: &str) -> bool {
+    word.split_once('=')
+        .is_some_and(|(name, _)| parser::is_name(name))
+}
+
+/// Completes the word before `cursor` (a character index) in `line`.
+pub(crate) fn complete(shell: &mut Shell, line: &str, cursor: usize) -> Completion {
+    let chars: Vec<char> = line.chars().take(cursor).collect();
+    let command = CommandWords::parse(&chars);
+    let word = &command.words[command.current];
+
+    if let Some(completion) = complete_variable(shell, &chars, &command) {
+        return completion;
+    }
+    let spec = command
+        .command_name()
+        .and_then(|name| shell.completions.get(name))
+        .cloned();
+    let mut names = if let Some(spec) = spec {
+        spec_names(shell, &spec, line, cursor, &command)
+    } else if command.in_command_position() && !word.contains('/') {
+        command_names(shell, word)
+    } else {
+        path_names(shell, word, command.in_command_position())
+    };
+    names.sort();
+    names.dedup();
+    Completion {
+        start: command.start,
+        candidates: names
+            .into_iter()
+            .map(|name| quote(&name, command.open_quote))
+            .collect(),
+    }
+}
+
+/// Completes `$NAME` or `${NAME` at the end of the word to variable names.
+fn complete_variable(shell: &Shell, chars: &[char], command: &CommandWords) -> Option<Completion> {
+    if command.open_quote == Some('\'') {
+        return None;
+    }
+    let name_start = chars[command.start..]
+        .iter()
+        .rposition(|c| !(c.is_ascii_alphanumeric() || *c == '_'))
+        .map(|offset| command.start + offset + 1)?;
+    let prefix: String = chars[name_start..].iter().collect();
+    let braced = chars[..name_start].ends_with(&['$', '{']);
+    let dollar = if braced {
+        name_start - 2
+    } else {
+        name_start - 1
+    };
+    if chars.get(dollar) != Some(&'$') || dollar < command.start {
+        return None;
+    }
+    if prefix.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let candidates = shell
+        .variables
+        .sorted()
+        .into_iter()
+        .filter(|(name, variable)| name.starts_with(&prefix) && variable.value.is_some())
+        .map(|(name, _)| {
+            let mut candidate: String = chars[command.start..dollar].iter().collect();
+            candidate.push('$');
+            if braced {
+                candidate.push('{');
+                candidate.push_str(name);
+                candidate.push('}');
+            } else {
+                candidate.push_str(name);
+            }
+            candidate
+        })
+        .collect();
+    Some(Completion {
+        start: command.start,
+        candidates,
+    })
+}
+
+/// The candidates `spec` gives for the word being completed.
+///
+/// A function runs with `$1` set to the command name, `$2` to the word being
+/// completed and `$3` to the word before it. `$COMP_WORDS` holds the words
+/// of the command separated by spaces, `$COMP_CWORD` the index of the word
+/// being completed, and `$COMP_LINE` and `$COMP_POINT` the line and cursor
+/// position. It sets `$COMPREPLY` to the candidates, separated by
+/// whitespace. Its output is discarded.
+fn spec_names(
+    shell: &mut Shell,
+    spec: &CompletionSpec,
+    line: &str,
+    cursor: usize,
+    command: &CommandWords,
+) -> Vec<String> {
+    let index = command.command_index();
+    let words = &command.words[index..];
+    let current = &command.words[command.current];
+    let mut names: Vec<String> = spec
+        .words
+        .iter()
+        .flat_map(|words| words.split_whitespace())
+        .filter(|word| word.starts_with(current.as_str()))
+        .map(str::to_string)
+        .collect();
+
+    if let Some(function) = &spec.function {
+        let previous = words.len().checked_sub(2).map_or("", |i| words[i].as_str());
+        let source = format!(
+            "{} {} {} {}",
+            function,
+            builtins::quote(&words[0]),
+            builtins::quote(current),
+            builtins::quote(previous)
+        );
+        let settings = [
+            ("COMP_WORDS", words.join(" ")),
+            ("COMP_CWORD", (words.len() - 1).to_string()),
+            ("COMP_LINE", line.to_string()),
+            ("COMP_POINT", cursor.to_string()),
+        ];
+        for (name, value) in settings {
+            let _ = shell.variables.set(name, value);
+        }
+        let _ = shell.variables.unset("COMPREPLY");
+        run_quietly(shell, &source);
+        if let Some(reply) = shell.variables.get("COMPREPLY") {
+            names.extend(reply.split_whitespace().map(str::to_string));
+        }
+        for name in [
+            "COMP_WORDS",
+            "COMP_CWORD",
+            "COMP_LINE",
+            "COMP_POINT",
+            "COMPREPLY",
+        ] {
+            let _ = shell.variables.unset(name);
+        }
+    }
+    names
+}
+
+/// Runs `source` with its input and output connected to `/dev/null`,
+/// leaving `$?` as it was.
+fn run_quietly(shell: &mut Shell, source: &str) {
+    let (Ok(list), Ok(null)) = (
+        parser::parse(source),
+        File::options().write(true).open("/dev/null"),
+    ) else {
+        return;
+    };
+    let Ok(null_err) = null.try_clone() else {
+        return;
+    };
+    let streams = Streams {
+        stdin: Input::Null,
+        stdout: Output::File(null),
+        stderr: Output::File(null_err),
+    };
+    let status = shell.last_status;
+    let _ = exec::run_list(shell, &list, &streams, &mut std::io::sink());
+    shell.control = None;
+    shell.last_status = status;
+}
+
+/// Functions, builtins and executables on `$PATH` whose names start with `prefix`.
+fn command_names(shell: &Shell, prefix: &str) -> Vec<String> {
+    let mut names: BTreeSet<String> = shell
+        .functions
+        .keys()
+        .map(String::as_str)
+        .chain(builtins::BUILT_INS)
+        .filter(|name| name.starts_with(prefix))
+        .map(|name| name.to_string())
+        .collect();
+    let search_path = shell.variables.get("PATH").unwrap_or_default();
+    for dir in search_path.split(':').filter(|dir| !dir.is_empty()) {
+        let Ok(entries) = fs::read_dir(dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with(prefix) && is_executable(&entry.path()) {
+                names.insert(name);
+            }
+        }
+    }
+    names.into_iter().collect()
+}
+
+/// Paths starting with `word`, with `/` after directories. Only directories
+/// and executables complete a command name.
+fn path_names(shell: &Shell, word: &str, executables_only: bool) -> Vec<String> {
+    let (dir, prefix) = match word.rfind('/') {
+        Some(slash) => word.split_at(slash + 1),
+        None => ("", word),
+    };
+    let search_dir = match dir.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+            let home = shell
+                .variables
+                .get("HOME")
+                .map(str::to_string)
+                .or_else(helpers::current_user_home)
+                .unwrap_or_default();
+            format!("{}{}", home, rest)
+        }
+        _ if dir.is_empty() => ".".to_string(),
+        _ => dir.to_string(),
+    };
+    let Ok(entries) = fs::read_dir(&search_dir) else {
+        return Vec::new();
+    };
+
+    let mut names = Vec::new();
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        // Hidden files only complete when asked for.
+        if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+            continue;
+        }
+        let path = entry.path();
+        if path.is_dir() {
+            names.push(format!("{}{}/", dir, name));
+        } else if !executables_only || is_executable(&path) {
+            names.push(format!("{}{}", dir, name));
+        }
+    }
+    names
+}
+
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path)
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+/// Characters that need a backslash outside quotes. A leading `~` is left
+/// for tilde expansion.
+const SPECIAL_CHARS: [char; 23] = [
+    ' ', '\t', '\n', '\'', '"', '\\', '$', '`', '&', '|', ';', '<', '>', '(', ')', '*', '?', '[',
+    ']', '{', '}', '!', '#',
+];
+
+/// Quotes a completed word so the shell reads it back unchanged: inside the
+/// quote the word was started with, closed unless it is a directory, or with
+/// backslashes.
+fn quote(name: &str, open_quote: Option<char>) -> String {
+    let mut quoted = String::with_capacity(name.len() + 2);
+    match open_quote {
+        Some(q) => {
+            quoted.push(q);
+            for c in name.chars() {
+                if q == '\'' && c == '\'' {
+                    // A single quote can't be escaped inside one: close the
+                    // quote, add an escaped one and reopen it.
+                    quoted.push_str("'\\''");
+                    continue;
+                }
+                if q == '"' && matches!(c, '"' | '\\' | '$' | '`') {
+                    quoted.push('\\');
+                }
+                quoted.push(c);
+            }
+            if !name.ends_with('/') {
+                quoted.push(q);
+            }
+        }
+        None => {
+            for c in name.chars() {
+                if SPECIAL_CHARS.contains(&c) {
+                    quoted.push('\\');
+                }
+                quoted.push(c);
+            }
+        }
+    }
+    quoted
+}
@@ -0,0 +1,524 @@
+//! An emacs-style line editor for reading commands from a terminal.
+//!
+//! The terminal is put in raw mode only while a line is being read, so
+//! commands run with the settings they expect. Editing assumes every
+//! character takes one column and the line fits on one screen row.
+
+use std::io::{self, Read, Write};
+use std::mem;
+
+use crate::complete::Completion;
+
+/// Finds the completions for the word before a cursor position in a line.
+pub(crate) type Completer<'a> = dyn FnMut(&str, usize) -> Completion + 'a;
+
+/// A key press, decoded from the bytes the terminal sends for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Key {
+    Char(char),
+    /// A control character, given as its letter: `Ctrl('a')` is Ctrl-A.
+    Ctrl(char),
+    /// A character typed with Alt (or after Escape).
+    Alt(char),
+    Enter,
+    Tab,
+    Backspace,
+    AltBackspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    CtrlLeft,
+    CtrlRight,
+    /// An escape sequence this editor doesn't bind.
+    Unknown,
+}
+
+/// The line being edited and the cursor position in it, in characters.
+#[derive(Debug, Default)]
+struct Line {
+    chars: Vec<char>,
+    cursor: usize,
+}
+
+impl Line {
+    fn set(&mut self, text: &str) {
+        self.chars = text.chars().collect();
+        self.cursor = self.chars.len();
+    }
+
+    fn text(&self) -> String {
+        self.chars.iter().collect()
+    }
+
+    fn insert(&mut self, text: &str) {
+        for c in text.chars() {
+            self.chars.insert(self.cursor, c);
+            self.cursor += 1;
+        }
+    }
+
+    /// Removes the characters between the cursor and `to`, leaving the cursor
+    /// at the start of the removed range, and returns them.
+    fn cut(&mut self, to: usize) -> String {
+        let (start, end) = (self.cursor.min(to), self.cursor.max(to));
+        self.cursor = start;
+        self.chars.drain(start..end).collect()
+    }
+
+    /// Start of the word before the cursor.
+    fn word_start(&self) -> usize {
+        let mut i = self.cursor;
+        while i > 0 && !is_word_char(self.chars[i - 1]) {
+            i -= 1;
+        }
+        while i > 0 && is_word_char(self.chars[i - 1]) {
+            i -= 1;
+        }
+        i
+    }
+
+    /// End of the word after the cursor.
+    fn word_end(&self) -> usize {
+        let mut i = self.cursor;
+        while i < self.chars.len() && !is_word_char(self.chars[i]) {
+            i += 1;
+        }
+        while i < self.chars.len() && is_word_char(self.chars[i]) {
+            i += 1;
+        }
+        i
+    }
+
+    /// Start of the whitespace-separated word before the cursor, as Ctrl-W uses.
+    fn big_word_start(&self) -> usize {
+        let mut i = self.cursor;
+        while i > 0 && self.chars[i - 1].is_whitespace() {
+            i -= 1;
+        }
+        while i > 0 && !self.chars[i - 1].is_whitespace() {
+            i -= 1;
+        }
+        i
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// What the editor should do after a key.
+enum Outcome {
+    Continue,
+    /// The line is complete.
+    Accept,
+    /// The line is abandoned with Ctrl-C.
+    Cancel,
+    /// Ctrl-D on an empty line: end of input.
+    EndOfInput,
+}
+
+/// Reads lines with emacs-style editing, history navigation and a kill buffer.
+#[derive(Debug, Default)]
+pub(crate) struct LineEditor {
+    /// The text most recently killed, which Ctrl-Y yanks back.
+    kill_buffer: String,
+    /// Whether the last line was abandoned with Ctrl-C rather than entered.
+    cancelled: bool,
+}
+
+impl LineEditor {
+    pub fn new() -> Self {
+        LineEditor::default()
+    }
+
+    /// Whether the line [`edit`] last returned was abandoned with Ctrl-C.
+    ///
+    /// [`edit`]: LineEditor::edit
+    pub fn cancelled(&self) -> bool {
+        self.cancelled
+    }
+
+    /// Reads a line from the terminal on stdin, showing `prompt` and echoing
+    /// to `out`, with `history` (oldest first) reachable with Up and Down and
+    /// Tab completing words with `complete`.
+    ///
+    /// Returns `None` at end of input.
+    pub fn read_line(
+        &mut self,
+        prompt: &str,
+        history: &[String],
+        complete: &mut Completer,
+        out: &mut dyn Write,
+    ) -> io::Result<Option<String>> {
+        let _raw = RawMode::enable()?;
+        self.edit(prompt, history, complete, &mut TerminalInput, out)
+    }
+
+    /// Edits a line with the keys read from `keys`, which [`read_line`]
+    /// connects to the terminal.
+    ///
+    /// [`read_line`]: LineEditor::read_line
+    pub fn edit(
+        &mut self,
+        prompt: &str,
+        history: &[String],
+        complete: &mut Completer,
+        keys: &mut dyn Read,
+        out: &mut dyn Write,
+    ) -> io::Result<Option<String>> {
+        let mut line = Line::default();
+        // A second Tab in a row lists the candidates.
+        let mut after_tab = false;
+        // `history_index == history.len()` is the new line, whose text is
+        // kept in `draft` while browsing older entries.
+        let mut history_index = history.len();
+        let mut draft = String::new();
+        self.cancelled = false;
+
+        write!(out, "{}", prompt)?;
+        out.flush()?;
+        loop {
+            let Some(key) = read_key(keys)? else {
+                if line.chars.is_empty() {
+                    return Ok(None);
+                }
+                // Input ended mid-line: take what was typed.
+                writeln!(out)?;
+                return Ok(Some(line.text()));
+            };
+            let outcome = match key {
+                Key::Up | Key::Ctrl('p') if history_index > 0 => {
+                    if history_index == history.len() {
+                        draft = line.text();
+                    }
+                    history_index -= 1;
+                    line.set(&history[history_index]);
+                    Outcome::Continue
+                }
+                Key::Down | Key::Ctrl('n') if history_index < history.len() => {
+                    history_index += 1;
+                    match history.get(history_index) {
+                        Some(entry) => line.set(entry),
+                        None => line.set(&draft),
+                    }
+                    Outcome::Continue
+                }
+                Key::Ctrl('l') => {
+                    write!(out, "\x1b[H\x1b[2J")?;
+                    Outcome::Continue
+                }
+                Key::Tab => {
+                    let completion = complete(&line.text(), line.cursor);
+                    complete_word(&mut line, completion, after_tab, out)?;
+                    Outcome::Continue
+                }
+                key => self.apply(key, &mut line),
+            };
+            after_tab = key == Key::Tab;
+            match outcome {
+                Outcome::Continue => refresh(prompt, &line, out)?,
+                Outcome::Accept => {
+                    writeln!(out)?;
+                    out.flush()?;
+                    return Ok(Some(line.text()));
+                }
+                Outcome::Cancel => {
+                    self.cancelled = true;
+                    writeln!(out, "^C")?;
+                    out.flush()?;
+                    return Ok(Some(String::new()));
+                }
+                Outcome::EndOfInput => return Ok(None),
+            }
+        }
+    }
+
+    /// Applies an editing key to `line`.
+    fn apply(&mut self, key: Key, line: &mut Line) -> Outcome {
+        match key {
+            Key::Enter => return Outcome::Accept,
+            Key::Ctrl('c') => return Outcome::Cancel,
+            Key::Ctrl('d') if line.chars.is_empty() => return Outcome::EndOfInput,
+            Key::Char(c) => line.insert(c.encode_utf8(&mut [0; 4])),
+            Key::Left | Key::Ctrl('b') => line.cursor = line.cursor.saturating_sub(1),
+            Key::Right | Key::Ctrl('f') => line.cursor = (line.cursor + 1).min(line.chars.len()),
+            Key::Home | Key::Ctrl('a') => line.cursor = 0,
+            Key::End | Key::Ctrl('e') => line.cursor = line.chars.len(),
+            Key::CtrlLeft | Key::Alt('b') => line.cursor = line.word_start(),
+            Key::CtrlRight | Key::Alt('f') => line.cursor = line.word_end(),
+            Key::Backspace | Key::Ctrl('h') if line.cursor > 0 => {
+                line.cut(line.cursor - 1);
+            }
+            Key::Delete | Key::Ctrl('d') if line.cursor < line.chars.len() => {
+                line.cut(line.cursor + 1);
+            }
+            Key::Ctrl('k') => self.kill(line, line.chars.len()),
+            Key::Ctrl('u') => self.kill(line, 0),
+            Key::Ctrl('w') => self.kill(line, line.big_word_start()),
+            Key::AltBackspace => self.kill(line, line.word_start()),
+            Key::Alt('d') => self.kill(line, line.word_end()),
+            Key::Ctrl('y') => line.insert(&self.kill_buffer),
+            Key::Ctrl('t') if line.cursor > 0 && line.chars.len() > 1 => {
+                // Swap the characters before and at the cursor, or the last
+                // two at the end of the line.
+                let at = line.cursor.min(line.chars.len() - 1);
+                line.chars.swap(at - 1, at);
+                line.cursor = at + 1;
+            }
+            _ => {}
+        }
+        Outcome::Continue
+    }
+
+    /// Moves the text between the cursor and `to` into the kill buffer.
+    fn kill(&mut self, line: &mut Line, to: usize) {
+        if to != line.cursor {
+            self.kill_buffer = line.cut(to);
+        }
+    }
+}
+
+/// Applies `completion` to the word before the cursor: a single candidate
+/// replaces it, followed by a space unless it is a directory; several extend
+/// it to their longest common prefix, or are listed if `list` is set and
+/// there is nothing to extend.
+fn complete_word(
+    line: &mut Line,
+    completion: Completion,
+    list: bool,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    let Completion { start, candidates } = completion;
+    let current: String = line.chars[start..line.cursor].iter().collect();
+    let replacement = match candidates.as_slice() {
+        [] => None,
+        [only] if only.ends_with('/') => Some(only.clone()),
+        [only] => Some(format!("{} ", only)),
+        _ => Some(common_prefix(&candidates)).filter(|common| *common != current),
+    };
+    match replacement {
+        Some(replacement) => {
+            line.cut(start);
+            line.insert(&replacement);
+        }
+        None if list && !candidates.is_empty() => list_candidates(&candidates, out)?,
+        // Ring the bell: nothing to complete, or ambiguous.
+        None => write!(out, "\x07")?,
+    }
+    Ok(())
+}
+
+fn common_prefix(candidates: &[String]) -> String {
+    let mut prefix: Vec<char> = candidates[0].chars().collect();
+    for candidate in &candidates[1..] {
+        let common = prefix
+            .iter()
+            .zip(candidate.chars())
+            .take_while(|(a, b)| **a == *b)
+            .count();
+        prefix.truncate(common);
+    }
+    prefix.into_iter().collect()
+}
+
+/// Prints the candidates below the line in columns, by their last path component.
+fn list_candidates(candidates: &[String], out: &mut dyn Write) -> io::Result<()> {
+    let names: Vec<&str> = candidates
+        .iter()
+        .map(|candidate| {
+            let trimmed = candidate.strip_suffix('/').unwrap_or(candidate);
+            let start = trimmed.rfind('/').map_or(0, |slash| slash + 1);
+            &candidate[start..]
+        })
+        .collect();
+    let width = names
+        .iter()
+        .map(|name| name.chars().count())
+        .max()
+        .unwrap_or(0)
+        + 2;
+    let columns = (terminal_width() / width).max(1);
+    let rows = names.len().div_ceil(columns);
+    writeln!(out)?;
+    // Fill columns first, as `ls` does.
+    for row in 0..rows {
+        let mut text = String::new();
+        for name in names.iter().skip(row).step_by(rows) {
+            text.push_str(&format!("{:<1$}", name, width));
+        }
+        writeln!(out, "{}", text.trim_end())?;
+    }
+    Ok(())
+}
+
+/// The width of the terminal on stdout, or 80 columns if it is unknown.
+fn terminal_width() -> usize {
+    // SAFETY: `winsize` is plain data that `ioctl` fills in on success.
+    let mut size: libc::winsize = unsafe { mem::zeroed() };
+    let ok = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0;
+    if ok && size.ws_col > 0 {
+        usize::from(size.ws_col)
+    } else {
+        80
+    }
+}
+
+/// Redraws the prompt and line, and puts the cursor in place.
+fn refresh(prompt: &str, line: &Line, out: &mut dyn Write) -> io::Result<()> {
+    write!(out, "\r{}{}\x1b[K\r", prompt, line.text())?;
+    let column = prompt.chars().count() + line.cursor;
+    if column > 0 {
+        write!(out, "\x1b[{}C", column)?;
+    }
+    out.flush()
+}
+
+fn read_byte(input: &mut dyn Read) -> io::Result<Option<u8>> {
+    let mut byte = [0];
+    loop {
+        match input.read(&mut byte) {
+            Ok(0) => return Ok(None),
+            Ok(_) => return Ok(Some(byte[0])),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Reads the next key, or `None` at end of input.
+fn read_key(input: &mut dyn Read) -> io::Result<Option<Key>> {
+    let Some(byte) = read_byte(input)? else {
+        return Ok(None);
+    };
+    let key = match byte {
+        b'\r' | b'\n' => Key::Enter,
+        b'\t' => Key::Tab,
+        0x7f => Key::Backspace,
+        0x1b => read_escape(input)?,
+        0x01..=0x1a => Key::Ctrl(char::from(b'a' + byte - 1)),
+        0x00..=0x1f => Key::Unknown,
+        0x20..=0x7e => Key::Char(char::from(byte)),
+        _ => read_utf8(input, byte)?,
+    };
+    Ok(Some(key))
+}
+
+/// Decodes a multi-byte UTF-8 character starting with `first`.
+fn read_utf8(input: &mut dyn Read, first: u8) -> io::Result<Key> {
+    let len = match first {
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => return Ok(Key::Unknown),
+    };
+    let mut bytes = vec![first];
+    for _ in 1..len {
+        match read_byte(input)? {
+            Some(byte) => bytes.push(byte),
+            None => break,
+        }
+    }
+    Ok(std::str::from_utf8(&bytes)
+        .ok()
+        .and_then(|text| text.chars().next())
+        .map_or(Key::Unknown, Key::Char))
+}
+
+/// Decodes the rest of a key that started with Escape: an Alt combination
+/// or a CSI (`ESC [`) or SS3 (`ESC O`) sequence.
+fn read_escape(input: &mut dyn Read) -> io::Result<Key> {
+    let key = match read_byte(input)? {
+        Some(b'[') => {
+            // Parameters, then a final byte in `@`..=`~`.
+            let mut params = String::new();
+            loop {
+                match read_byte(input)? {
+                    Some(byte @ 0x40..=0x7e) => break csi_key(&params, byte),
+                    Some(byte) => params.push(char::from(byte)),
+                    None => break Key::Unknown,
+                }
+            }
+        }
+        Some(b'O') => match read_byte(input)? {
+            Some(b'H') => Key::Home,
+            Some(b'F') => Key::End,
+            _ => Key::Unknown,
+        },
+        Some(0x7f) => Key::AltBackspace,
+        Some(byte @ 0x20..=0x7e) => Key::Alt(char::from(byte)),
+        _ => Key::Unknown,
+    };
+    Ok(key)
+}
+
+fn csi_key(params: &str, final_byte: u8) -> Key {
+    match (params, final_byte) {
+        ("", b'A') => Key::Up,
+        ("", b'B') => Key::Down,
+        ("", b'C') => Key::Right,
+        ("", b'D') => Key::Left,
+        ("", b'H') | ("1" | "7", b'~') => Key::Home,
+        ("", b'F') | ("4" | "8", b'~') => Key::End,
+        ("3", b'~') => Key::Delete,
+        ("1;5", b'C') => Key::CtrlRight,
+        ("1;5", b'D') => Key::CtrlLeft,
+        _ => Key::Unknown,
+    }
+}
+
+/// Unbuffered reads from stdin, so nothing typed ahead is held back from
+/// the commands that run next.
+struct TerminalInput;
+
+impl Read for TerminalInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // SAFETY: `buf` is valid for writes of `buf.len()` bytes.
+        let n = unsafe { libc::read(libc::STDIN_FILENO, buf.as_mut_ptr().cast(), buf.len()) };
+        if n < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(n as usize)
+        }
+    }
+}
+
+/// Puts the terminal on stdin in raw mode until dropped.
+struct RawMode {
+    original: libc::termios,
+}
+
+impl RawMode {
+    fn enable() -> io::Result<RawMode> {
+        // SAFETY: `termios` is plain data that `tcgetattr` fills in.
+        let mut original: libc::termios = unsafe { mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut original) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut raw = original;
+        // Keys arrive one at a time and unechoed, and Ctrl-C, Ctrl-Z and
+        // Ctrl-V reach the editor instead of the terminal driver. Output
+        // processing stays on, so `\n` still moves to the start of the line.
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG | libc::IEXTEN);
+        raw.c_iflag &= !(libc::IXON | libc::ICRNL);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        // SAFETY: `raw` is a valid `termios` derived from the current one.
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, &raw) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(RawMode { original })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        // SAFETY: restores the settings read in `enable`.
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, &self.original);
+        }
+    }
+}
@@ -0,0 +1,477 @@
+use std::env;
+use std::fs::File;
+use std::io::{self, Write};
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+
+use crate::ast::{
+    AndOr, CaseCommand, Command, CompoundCommand, Connector, ForLoop, FunctionDefinition,
+    IfCommand, List, Loop, Pipeline,
+};
+use crate::expand;
+use crate::jobs::{self, Process, Relay};
+use crate::pattern;
+use crate::pipeline;
+use crate::shell::{Control, Shell};
+use crate::signals;
+use crate::streams::{self, Input, Streams};
+use crate::trap::{self, Condition};
+
+/// Runs the items of `list` in order, stopping early once a builtin asks to leave.
+///
+/// Returns the status of the last command that ran.
+pub fn run_list(
+    shell: &mut Shell,
+    list: &List,
+    streams: &Streams,
+    terminal: &mut dyn Write,
+) -> io::Result<i32> {
+    for item in &list.items {
+        if item.background {
+            run_background(shell, &item.and_or, streams, terminal)?;
+            continue;
+        }
+        run_and_or(shell, &item.and_or, streams, terminal)?;
+        trap::run_pending(shell, terminal)?;
+        if shell.control.is_some() {
+            break;
+        }
+    }
+    Ok(shell.last_status)
+}
+
+fn run_and_or(
+    shell: &mut Shell,
+    and_or: &AndOr,
+    streams: &Streams,
+    terminal: &mut dyn Write,
+) -> io::Result<()> {
+    let last = and_or.rest.last().map_or(&and_or.first, |(_, p)| p);
+    // Only the last pipeline may replace a forked shell, which has nothing
+    // left to do after it; a negated one still has its status to invert.
+    let exec_last = mem::take(&mut shell.exec_last);
+    let mut run = |shell: &mut Shell, pipeline: &Pipeline| {
+        shell.exec_last = exec_last && std::ptr::eq(pipeline, last) && !pipeline.negated;
+        let status = pipeline::run_pipeline(shell, pipeline, streams, terminal);
+        shell.exec_last = false;
+        status
+    };
+    shell.last_status = run(shell, &and_or.first)?;
+    let mut last_run = &and_or.first;
+    for (connector, pipeline) in &and_or.rest {
+        if shell.control.is_some() {
+            break;
+        }
+        let should_run = match connector {
+            Connector::And => shell.last_status == 0,
+            Connector::Or => shell.last_status != 0,
+        };
+        if should_run {
+            shell.last_status = run(shell, pipeline)?;
+            last_run = pipeline;
+        }
+    }
+    // Failures that only decide whether `&&` or `||` go on, or that `!`
+    // inverts, are not errors.
+    let is_last = std::ptr::eq(last_run, last);
+    if shell.last_status != 0
+        && is_last
+        && !last_run.negated
+        && shell.control.is_none()
+        && shell.condition_depth == 0
+        && shell.function_depth == 0
+        && !reports_own_failures(last_run)
+    {
+        trap::run(shell, Condition::Err, terminal)?;
+    }
+    Ok(())
+}
+
+/// Whether the status of `pipeline` is that of a compound command whose
+/// failing commands already ran the `ERR` trap themselves.
+fn reports_own_failures(pipeline: &Pipeline) -> bool {
+    match pipeline.commands.as_slice() {
+        [Command::Compound(compound, _)] => !matches!(
+            compound,
+            CompoundCommand::Subshell(_) | CompoundCommand::Arithmetic(_)
+        ),
+        _ => false,
+    }
+}
+
+/// Starts `and_or` as a background job in a forked copy of the shell, setting `$!`.
+///
+/// An interactive shell puts the job in its own process group and announces
+/// it as `[1] PID`. Unless children inherit the shell's stdio, the job's
+/// terminal output goes through a pipe and is relayed when the shell next
+/// reports on its jobs.
+///
+/// The job's last command replaces the forked shell when it is external, so
+/// `$!` is that command's own process ID.
+fn run_background(
+    shell: &mut Shell,
+    and_or: &AndOr,
+    streams: &Streams,
+    terminal: &mut dyn Write,
+) -> io::Result<()> {
+    let mut streams = streams.try_clone()?;
+    if !shell.is_interactive() {
+        // Without job control, background jobs must not compete for the shell's input.
+        if let Input::Terminal = streams.stdin {
+            streams.stdin = Input::Null;
+        }
+    }
+    let (output_read, output_write) = if shell.inherits_stdio() {
+        (None, None)
+    } else {
+        let (read, write) = streams::pipe()?;
+        (Some(read), Some(write))
+    };
+    let pid = fork_shell(shell, terminal, |shell| {
+        run_forked_job(shell, and_or, streams, output_write)
+    })?;
+
+    if shell.is_interactive() {
+        jobs::set_process_group(pid, pid);
+    }
+    let output = output_read.map(Relay::start).transpose()?;
+    let output = output.map(|relay| Arc::new(Mutex::new(relay)));
+    let id = shell
+        .jobs
+        .add(pid, vec![Process::new(pid)], &and_or.text, output);
+    shell.last_background_pid = Some(pid as u32);
+    if shell.is_interactive() {
+        writeln!(terminal, "[{}] {}", id, pid)?;
+    }
+    shell.last_status = 0;
+    Ok(())
+}
+
+/// Runs `run` in a forked copy of the shell, which exits with the code it
+/// returns, and returns the child's pid.
+///
+/// Whatever `run` owns is dropped in the parent, so the child keeps the only
+/// copies of descriptors moved into it.
+pub(crate) fn fork_shell(
+    shell: &mut Shell,
+    terminal: &mut dyn Write,
+    run: impl FnOnce(&mut Shell) -> i32,
+) -> io::Result<i32> {
+    // Anything buffered now would be written twice, once by each process.
+    terminal.flush()?;
+
+    // SAFETY: `fork` copies only the calling thread, but the shell may have
+    // other threads, which forward child output or collect a command
+    // substitution's, and the child goes on to allocate, start threads and
+    // spawn commands, which POSIX does not allow after forking a
+    // multi-threaded process. It works because no other thread can hold a
+    // lock the child needs: glibc's `fork` leaves the allocator usable in the
+    // child, and those threads only read a pipe into a buffer or a channel or
+    // wait for a child and send its state, never taking the stdout or
+    // environment locks. Threads the shell starts
+    // must keep to that. The child never returns into the caller and leaves
+    // through `_exit`.
+    let pid = unsafe { libc::fork() };
+    if pid == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    if pid == 0 {
+        let code = panic::catch_unwind(AssertUnwindSafe(|| run(shell))).unwrap_or(1);
+        // SAFETY: `_exit` ends the child without running the parent's destructors.
+        unsafe { libc::_exit(code) };
+    }
+    Ok(pid)
+}
+
+/// Runs a background job in the forked child, returning its exit code.
+fn run_forked_job(
+    shell: &mut Shell,
+    and_or: &AndOr,
+    streams: Streams,
+    output: Option<File>,
+) -> i32 {
+    if shell.is_interactive() {
+        let pid = std::process::id() as i32;
+        jobs::set_process_group(pid, pid);
+        signals::leave_interactive();
+    }
+    shell.begin_forked_copy();
+    let mut streams = streams;
+    if let Some(pipe) = output {
+        // Children inherit the job's pipe, so the last command can take the
+        // place of this process.
+        if shell.inherit_output(&pipe).is_err() {
+            return 1;
+        }
+        if let Input::Terminal = streams.stdin {
+            streams.stdin = Input::Null;
+        }
+    }
+    shell.exec_last = true;
+    let mut terminal = io::stdout();
+    let result = run_and_or(shell, and_or, &streams, &mut terminal);
+    let _ = terminal.flush();
+    match (result, shell.control) {
+        (Err(_), _) => 1,
+        (Ok(()), Some(Control::Exit(code))) => code,
+        (Ok(()), Some(Control::Return(code))) => code,
+        (
+            Ok(()),
+            Some(Control::Interrupt | Control::Suspend | Control::Break(_) | Control::Continue(_))
+            | None,
+        ) => shell.last_status,
+    }
+}
+
+pub fn run_compound(
+    shell: &mut Shell,
+    compound: &CompoundCommand,
+    streams: &Streams,
+    terminal: &mut dyn Write,
+) -> io::Result<i32> {
+    match compound {
+        CompoundCommand::BraceGroup(list) => run_list(shell, list, streams, terminal),
+        CompoundCommand::Subshell(list) => run_subshell(shell, list, streams, terminal),
+        CompoundCommand::Arithmetic(expression) => {
+            match expand::expand_arithmetic(shell, &expression.text, terminal) {
+                Ok(value) => Ok(i32::from(value == 0)),
+                Err(e) => {
+                    streams.stderr.report(terminal, shell.inherits_stdio(), e)?;
+                    Ok(1)
+                }
+            }
+        }
+        CompoundCommand::If(command) => run_if(shell, command, streams, terminal),
+        CompoundCommand::While(body) => run_loop(shell, body, false, streams, terminal),
+        CompoundCommand::Until(body) => run_loop(shell, body, true, streams, terminal),
+        CompoundCommand::For(command) => run_for(shell, command, streams, terminal),
+        CompoundCommand::Case(command) => run_case(shell, command, streams, terminal),
+    }
+}
+
+/// Runs the body of the first branch whose condition succeeds.
+///
+/// The status is the body's, or zero if no branch ran.
+fn run_if(
+    shell: &mut Shell,
+    command: &IfCommand,
+    streams: &Streams,
+    terminal: &mut dyn Write,
+) -> io::Result<i32> {
+    for (condition, body) in &command.branches {
+        let status = run_condition(shell, condition, streams, terminal)?;
+        if shell.control.is_some() {
+            return Ok(status);
+        }
+        if status == 0 {
+            return run_list(shell, body, streams, terminal);
+        }
+    }
+    match &command.else_body {
+        Some(body) => run_list(shell, body, streams, terminal),
+        None => Ok(0),
+    }
+}
+
+/// Runs a `while` loop, or an `until` loop if `until` is set.
+///
+/// The status is the last run of the body's, or zero if it never ran.
+fn run_loop(
+    shell: &mut Shell,
+    command: &Loop,
+    until: bool,
+    streams: &Streams,
+    terminal: &mut dyn Write,
+) -> io::Result<i32> {
+    in_loop(shell, |shell| {
+        let mut status = 0;
+        loop {
+            let condition = run_condition(shell, &command.condition, streams, terminal)?;
+            if shell.control.is_some() {
+                if leaves_loop(shell) {
+                    break;
+                }
+                continue;
+            }
+            if (condition == 0) == until {
+                break;
+            }
+            status = run_list(shell, &command.body, streams, terminal)?;
+            if leaves_loop(shell) {
+                break;
+            }
+        }
+        Ok(status)
+    })
+}
+
+/// Runs the body of a `for` loop once for each word, with the variable set to it.
+fn run_for(
+    shell: &mut Shell,
+    command: &ForLoop,
+    streams: &Streams,
+    terminal: &mut dyn Write,
+) -> io::Result<i32> {
+    let values = match &command.words {
+        Some(words) => match expand::expand_words(shell, words, terminal) {
+            Ok(values) => values,
+            Err(e) => {
+                streams.stderr.report(terminal, shell.inherits_stdio(), e)?;
+                return Ok(1);
+            }
+        },
+        None => shell.positional.clone(),
+    };
+    in_loop(shell, |shell| {
+        let mut status = 0;
+        for value in values {
+            if let Err(e) = shell.variables.set(&command.name, value) {
+                streams.stderr.report(terminal, shell.inherits_stdio(), e)?;
+                return Ok(1);
+            }
+            status = run_list(shell, &command.body, streams, terminal)?;
+            if leaves_loop(shell) {
+                break;
+            }
+        }
+        Ok(status)
+    })
+}
+
+/// Runs the body of the first `case` item with a pattern matching the word.
+///
+/// The status is the body's, or zero if no pattern matched.
+fn run_case(
+    shell: &mut Shell,
+    command: &CaseCommand,
+    streams: &Streams,
+    terminal: &mut dyn Write,
+) -> io::Result<i32> {
+    let word = match expand::expand_to_string(shell, &command.word, terminal) {
+        Ok(word) => word,
+        Err(e) => {
+            streams.stderr.report(terminal, shell.inherits_stdio(), e)?;
+            return Ok(1);
+        }
+    };
+    for item in &command.items {
+        for pattern in &item.patterns {
+            let pattern = match expand::expand_pattern(shell, pattern, terminal) {
+                Ok(pattern) => pattern,
+                Err(e) => {
+                    streams.stderr.report(terminal, shell.inherits_stdio(), e)?;
+                    return Ok(1);
+                }
+            };
+            if pattern::matches(&pattern, &word) {
+                return if item.body.items.is_empty() {
+                    Ok(0)
+                } else {
+                    run_list(shell, &item.body, streams, terminal)
+                };
+            }
+        }
+    }
+    Ok(0)
+}
+
+/// Runs the condition of an `if`, `while` or `until`, where failing is not an error.
+fn run_condition(
+    shell: &mut Shell,
+    condition: &List,
+    streams: &Streams,
+    terminal: &mut dyn Write,
+) -> io::Result<i32> {
+    shell.condition_depth += 1;
+    let status = run_list(shell, condition, streams, terminal);
+    shell.condition_depth -= 1;
+    status
+}
+
+/// Runs `run` as the body of a loop, which `break` and `continue` can leave.
+fn in_loop(shell: &mut Shell, run: impl FnOnce(&mut Shell) -> io::Result<i32>) -> io::Result<i32> {
+    shell.loop_depth += 1;
+    let status = run(shell);
+    shell.loop_depth -= 1;
+    status
+}
+
+/// Handles a `break` or `continue` after an iteration, returning whether the
+/// innermost loop should stop. Requests aimed at outer loops are passed on
+/// to them, as is any other control request.
+fn leaves_loop(shell: &mut Shell) -> bool {
+    match shell.control {
+        None => false,
+        Some(Control::Break(count)) => {
+            shell.control = (count > 1).then(|| Control::Break(count - 1));
+            true
+        }
+        Some(Control::Continue(1)) => {
+            shell.control = None;
+            false
+        }
+        Some(Control::Continue(count)) => {
+            shell.control = Some(Control::Continue(count - 1));
+            true
+        }
+        Some(Control::Exit(_) | Control::Interrupt | Control::Suspend | Control::Return(_)) => true,
+    }
+}
+
+/// Calls `function` with `args` as its positional parameters.
+///
+/// Variables the body makes `local` are put back when it returns. The
+/// status is the one given to `return`, or the last command's.
+pub fn run_function(
+    shell: &mut Shell,
+    function: &FunctionDefinition,
+    args: Vec<String>,
+    streams: &Streams,
+    terminal: &mut dyn Write,
+) -> io::Result<i32> {
+    let positional = mem::replace(&mut shell.positional, args);
+    // `break` and `continue` in the body cannot reach loops around the call.
+    let loop_depth = mem::take(&mut shell.loop_depth);
+    shell.function_depth += 1;
+    shell.variables.push_scope();
+    let result = run_compound(shell, &function.body, streams, terminal);
+    shell.variables.pop_scope();
+    shell.function_depth -= 1;
+    shell.loop_depth = loop_depth;
+    shell.positional = positional;
+    let status = result?;
+    if let Some(Control::Return(code)) = shell.control {
+        shell.control = None;
+        return Ok(code);
+    }
+    Ok(status)
+}
+
+/// Runs `list` in a copy of the shell, so its variables, `exit` and `cd` don't leak out.
+///
+/// Jobs the subshell starts are children of the shell's process all the
+/// same, so the shell takes them over to reap them.
+pub fn run_subshell(
+    shell: &mut Shell,
+    list: &List,
+    streams: &Streams,
+    terminal: &mut dyn Write,
+) -> io::Result<i32> {
+    let cwd = env::current_dir().ok();
+    let mut subshell = shell.clone();
+    subshell.traps = shell.traps.for_subshell();
+    let status = run_list(&mut subshell, list, streams, terminal)?;
+    subshell.last_status = status;
+    trap::run(&mut subshell, Condition::Exit, terminal)?;
+    shell.traps.reapply(&subshell.traps);
+    shell.jobs.adopt(mem::take(&mut subshell.jobs));
+    if let Some(cwd) = cwd {
+        let _ = env::set_current_dir(cwd);
+    }
+    Ok(match subshell.control {
+        Some(Control::Exit(code) | Control::Return(code)) => code,
+        Some(Control::Interrupt | Control::Suspend | Control::Break(_) | Control::Continue(_))
+        | None => status,
+    })
+}
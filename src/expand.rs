@@ -0,0 +1,760 @@
+use std::io::{self, Read, Write};
+use std::process;
+use std::thread;
+
+use thiserror::Error;
+
+use crate::arith;
+use crate::ast::Word;
+use crate::brace;
+use crate::exec;
+use crate::glob;
+use crate::helpers;
+use crate::lexer;
+use crate::parser;
+use crate::pattern;
+use crate::shell::{Control, Shell};
+use crate::streams::{self, Input, Output, Streams};
+
+/// An expansion that cannot be performed, which aborts the command.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("{0}")]
+pub struct ExpandError(String);
+
+impl From<io::Error> for ExpandError {
+    fn from(e: io::Error) -> Self {
+        ExpandError(e.to_string())
+    }
+}
+
+/// Expands `word` into the fields it produces as command arguments.
+///
+/// Brace expansion first turns the word into any number of words. The
+/// results of unquoted expansions are then split into fields at `$IFS`
+/// characters, and `"$@"` makes one field per positional parameter. An
+/// unquoted word that expands to nothing produces no field at all, while
+/// `''` or `"$EMPTY"` produce one empty field. A field with unquoted
+/// wildcards is replaced by the paths it matches, if any.
+///
+/// Command substitutions run with their errors going to `terminal`.
+pub fn expand_word(
+    shell: &mut Shell,
+    word: &Word,
+    terminal: &mut dyn Write,
+) -> Result<Vec<String>, ExpandError> {
+    let mut fields = Vec::new();
+    for text in brace::expand(&word.text) {
+        fields.extend(expand_fields(shell, &text, terminal)?);
+    }
+    Ok(fields)
+}
+
+/// Expands one brace-expanded word into fields, as described for `expand_word`.
+fn expand_fields(
+    shell: &mut Shell,
+    text: &str,
+    terminal: &mut dyn Write,
+) -> Result<Vec<String>, ExpandError> {
+    let expansion = expand(shell, text, false, false, terminal)?;
+    let mut fields = Vec::new();
+    for field in expansion.split_fields(ifs(shell)) {
+        fields.extend(glob_field(shell, field)?);
+    }
+    Ok(fields)
+}
+
+/// Replaces a field containing unquoted wildcards with the paths it matches.
+fn glob_field(shell: &Shell, field: Expansion) -> Result<Vec<String>, ExpandError> {
+    let pattern = field.to_pattern();
+    let value = field.into_string();
+    if pattern::has_wildcards(&pattern) {
+        let paths = glob::glob(&pattern, shell.options.dotglob);
+        if !paths.is_empty() {
+            return Ok(paths);
+        }
+        if shell.options.failglob {
+            return Err(ExpandError(format!("no match: {}", value)));
+        }
+        if shell.options.nullglob {
+            return Ok(Vec::new());
+        }
+    }
+    Ok(vec![value])
+}
+
+/// The field separators: `$IFS`, or space, tab and newline if it is unset.
+fn ifs(shell: &Shell) -> &str {
+    shell.variables.get("IFS").unwrap_or(" \t\n")
+}
+
+/// Expands all `words`, concatenating their fields.
+pub fn expand_words(
+    shell: &mut Shell,
+    words: &[Word],
+    terminal: &mut dyn Write,
+) -> Result<Vec<String>, ExpandError> {
+    let mut fields = Vec::new();
+    for word in words {
+        fields.extend(expand_word(shell, word, terminal)?);
+    }
+    Ok(fields)
+}
+
+/// Expands `word` into a single string, as for the word of a `case` command.
+pub fn expand_to_string(
+    shell: &mut Shell,
+    word: &Word,
+    terminal: &mut dyn Write,
+) -> Result<String, ExpandError> {
+    Ok(expand(shell, &word.text, false, false, terminal)?.into_string())
+}
+
+/// Expands `word` into a pattern in which only unquoted wildcards are
+/// special, as for `case` patterns.
+pub fn expand_pattern(
+    shell: &mut Shell,
+    word: &Word,
+    terminal: &mut dyn Write,
+) -> Result<String, ExpandError> {
+    Ok(expand(shell, &word.text, false, false, terminal)?.to_pattern())
+}
+
+/// Expands the value of an assignment, where a `~` after any unquoted `:`
+/// is expanded too, as in `PATH=~/bin:~/.local/bin`.
+pub fn expand_assignment(
+    shell: &mut Shell,
+    value: &Word,
+    terminal: &mut dyn Write,
+) -> Result<String, ExpandError> {
+    Ok(expand(shell, &value.text, false, true, terminal)?.into_string())
+}
+
+/// Where a piece of an expanded word came from, which decides what later
+/// steps may still do to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Origin {
+    /// Unquoted text written in the word itself.
+    Literal,
+    /// Text protected by quotes or a backslash.
+    Quoted,
+    /// The result of an unquoted expansion, still subject to field splitting.
+    Expanded,
+    /// A boundary between the words of `$@`, holding no text.
+    Break,
+}
+
+/// A word after expansion, before quote removal has thrown away which parts were quoted.
+///
+/// Quotes always leave a (possibly empty) `Quoted` piece behind, so that
+/// `''` still makes a field.
+#[derive(Debug, Default)]
+struct Expansion {
+    pieces: Vec<(Origin, String)>,
+}
+
+impl Expansion {
+    fn push(&mut self, origin: Origin, text: &str) {
+        match self.pieces.last_mut() {
+            Some((last, piece)) if *last == origin => piece.push_str(text),
+            _ => self.pieces.push((origin, text.to_string())),
+        }
+    }
+
+    fn push_char(&mut self, origin: Origin, c: char) {
+        self.push(origin, c.encode_utf8(&mut [0; 4]));
+    }
+
+    fn push_break(&mut self) {
+        self.pieces.push((Origin::Break, String::new()));
+    }
+
+    /// Joins the pieces into one string, with the words of `$@` separated by spaces.
+    fn into_string(self) -> String {
+        self.pieces
+            .into_iter()
+            .map(|(origin, text)| match origin {
+                Origin::Break => " ".to_string(),
+                _ => text,
+            })
+            .collect()
+    }
+
+    /// Joins the pieces into a pattern in which only unquoted wildcards are special.
+    fn to_pattern(&self) -> String {
+        self.pieces
+            .iter()
+            .map(|(origin, text)| match origin {
+                Origin::Quoted => pattern::escape(text),
+                Origin::Literal | Origin::Expanded | Origin::Break => text.clone(),
+            })
+            .collect()
+    }
+
+    /// Splits the word into fields at the `ifs` characters in unquoted
+    /// expansion results and at `$@` word boundaries.
+    ///
+    /// Runs of IFS whitespace separate fields and are dropped at either end,
+    /// while each other IFS character ends a field, possibly an empty one.
+    /// A field made only of unquoted expansions that produced nothing is
+    /// dropped.
+    fn split_fields(self, ifs: &str) -> Vec<Expansion> {
+        let mut fields = Vec::new();
+        let mut field = Expansion::default();
+        // Whether `field` has begun, and whether the last delimiter was IFS
+        // whitespace that ended a field, which absorbs one following
+        // non-whitespace delimiter.
+        let mut started = false;
+        let mut after_whitespace = false;
+        for (origin, text) in self.pieces {
+            match origin {
+                Origin::Literal | Origin::Quoted => {
+                    field.push(origin, &text);
+                    started = true;
+                    after_whitespace = false;
+                }
+                Origin::Break => {
+                    if started {
+                        fields.push(std::mem::take(&mut field));
+                    }
+                    started = false;
+                    after_whitespace = false;
+                }
+                Origin::Expanded => {
+                    for c in text.chars() {
+                        if !ifs.contains(c) {
+                            field.push_char(origin, c);
+                            started = true;
+                            after_whitespace = false;
+                        } else if c.is_whitespace() {
+                            if started {
+                                fields.push(std::mem::take(&mut field));
+                                started = false;
+                                after_whitespace = true;
+                            }
+                        } else if after_whitespace {
+                            after_whitespace = false;
+                        } else {
+                            fields.push(std::mem::take(&mut field));
+                            started = false;
+                        }
+                    }
+                }
+            }
+        }
+        if started {
+            fields.push(field);
+        }
+        fields
+    }
+}
+
+/// Performs parameter expansion on the source text of a word.
+///
+/// `in_double_quotes` is set when expanding the word of a `${...}` that is
+/// itself inside double quotes, and `assignment` when expanding an
+/// assignment value. The lexer has already rejected unbalanced quotes and
+/// invalid escapes.
+fn expand(
+    shell: &mut Shell,
+    text: &str,
+    mut in_double_quotes: bool,
+    assignment: bool,
+    terminal: &mut dyn Write,
+) -> Result<Expansion, ExpandError> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut expansion = Expansion::default();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let tilde_allowed = i == 0 || (assignment && chars[i - 1] == ':' && !in_double_quotes);
+        i += 1;
+        let origin = if in_double_quotes {
+            Origin::Quoted
+        } else {
+            Origin::Literal
+        };
+        match c {
+            '~' if tilde_allowed && !in_double_quotes => {
+                let expanded = tilde_prefix_end(&chars, i, assignment).and_then(|end| {
+                    let prefix: String = chars[i..end].iter().collect();
+                    Some((tilde_expansion(shell, &prefix)?, end))
+                });
+                match expanded {
+                    Some((path, end)) => {
+                        // The result is never split or globbed.
+                        expansion.push(Origin::Quoted, &path);
+                        i = end;
+                    }
+                    None => expansion.push_char(origin, c),
+                }
+            }
+            '\'' if !in_double_quotes => {
+                let end = find_char(&chars, i, '\'');
+                let quoted: String = chars[i..end].iter().collect();
+                expansion.push(Origin::Quoted, &quoted);
+                i = end + 1;
+            }
+            '"' => {
+                if !in_double_quotes {
+                    expansion.push(Origin::Quoted, "");
+                }
+                in_double_quotes = !in_double_quotes;
+            }
+            '\\' => {
+                match chars.get(i) {
+                    // An escaped newline joins lines and disappears.
+                    Some('\n') | None => {}
+                    Some('n') if in_double_quotes => expansion.push_char(Origin::Quoted, '\n'),
+                    Some(&escaped) => expansion.push_char(Origin::Quoted, escaped),
+                }
+                i += 1;
+            }
+            '`' => {
+                let end = find_closing_backquote(&chars, i);
+                let source = unescape_backquoted(&chars[i..end]);
+                let output = substitute(shell, &source, terminal)?;
+                expansion.push(expanded_origin(in_double_quotes), &output);
+                i = end + 1;
+            }
+            '$' => {
+                let expanded_origin = expanded_origin(in_double_quotes);
+                match chars.get(i) {
+                    Some('(')
+                        if chars.get(i + 1) == Some(&'(')
+                            && arithmetic_end(&chars, i + 2).is_some() =>
+                    {
+                        let end = arithmetic_end(&chars, i + 2).unwrap_or(chars.len());
+                        let expression: String = chars[i + 2..end].iter().collect();
+                        let value = expand_arithmetic(shell, &expression, terminal)?;
+                        expansion.push(expanded_origin, &value.to_string());
+                        i = end + 2;
+                    }
+                    Some('(') => {
+                        let rest: String = chars[i + 1..].iter().collect();
+                        let end = lexer::command_substitution_len(&rest)
+                            .map_or(chars.len(), |len| i + 1 + len);
+                        let source: String = chars[i + 1..end].iter().collect();
+                        let output = substitute(shell, &source, terminal)?;
+                        expansion.push(expanded_origin, &output);
+                        i = end + 1;
+                    }
+                    Some('{') => {
+                        let end = find_closing(&chars, i + 1, '{', '}');
+                        let body: String = chars[i + 1..end].iter().collect();
+                        i = end + 1;
+                        if body == "@" || body == "*" {
+                            push_positional(shell, &mut expansion, &body, in_double_quotes);
+                            continue;
+                        }
+                        let braced = expand_braced(shell, &body, in_double_quotes, terminal)?;
+                        for (origin, text) in braced.pieces {
+                            // Whatever the value, it is only as protected as the `${...}` itself.
+                            let origin = if origin == Origin::Literal {
+                                expanded_origin
+                            } else {
+                                origin
+                            };
+                            match origin {
+                                Origin::Break => expansion.push_break(),
+                                _ => expansion.push(origin, &text),
+                            }
+                        }
+                    }
+                    _ => match special_or_name_len(&chars[i..]) {
+                        Some(len) => {
+                            let name: String = chars[i..i + len].iter().collect();
+                            i += len;
+                            if name == "@" || name == "*" {
+                                push_positional(shell, &mut expansion, &name, in_double_quotes);
+                                continue;
+                            }
+                            let value = parameter(shell, &name).unwrap_or_default();
+                            expansion.push(expanded_origin, &value);
+                        }
+                        // A `$` that starts no expansion is kept literally.
+                        None => expansion.push_char(origin, c),
+                    },
+                }
+            }
+            _ => expansion.push_char(origin, c),
+        }
+    }
+
+    Ok(expansion)
+}
+
+/// Pushes the positional parameters for `$@` or `$*`.
+///
+/// Each parameter becomes a word of its own, except in `"$*"`, which joins
+/// them with the first character of `$IFS`. With no parameters, `"$@"`
+/// produces nothing at all, not even an empty field.
+fn push_positional(shell: &Shell, expansion: &mut Expansion, name: &str, in_double_quotes: bool) {
+    let origin = expanded_origin(in_double_quotes);
+    if name == "*" && in_double_quotes {
+        let separator: String = ifs(shell).chars().take(1).collect();
+        expansion.push(origin, &shell.positional.join(&separator));
+        return;
+    }
+    if shell.positional.is_empty() && in_double_quotes {
+        // Take back the empty piece left by the opening quote.
+        if expansion.pieces.last() == Some(&(Origin::Quoted, String::new())) {
+            expansion.pieces.pop();
+        }
+        return;
+    }
+    for (index, value) in shell.positional.iter().enumerate() {
+        if index > 0 {
+            expansion.push_break();
+        }
+        expansion.push(origin, value);
+    }
+}
+
+/// Length of the parameter name at the start of `chars` after an unbraced `$`:
+/// a variable name, a single digit or a special parameter.
+fn special_or_name_len(chars: &[char]) -> Option<usize> {
+    match chars.first()? {
+        '?' | '#' | '@' | '*' | '$' | '!' | '0'..='9' => Some(1),
+        c if c.is_alphabetic() || *c == '_' => Some(
+            chars
+                .iter()
+                .take_while(|c| c.is_alphanumeric() || **c == '_')
+                .count(),
+        ),
+        _ => None,
+    }
+}
+
+/// Index of the next `target` at or after `start`, or the end of `chars`.
+fn find_char(chars: &[char], start: usize, target: char) -> usize {
+    chars[start..]
+        .iter()
+        .position(|&c| c == target)
+        .map_or(chars.len(), |offset| start + offset)
+}
+
+/// End of the tilde-prefix starting at `start`: up to the first `/`, or `:` in
+/// an assignment. Returns `None` if the prefix contains quotes or expansions,
+/// which disables tilde expansion.
+fn tilde_prefix_end(chars: &[char], start: usize, assignment: bool) -> Option<usize> {
+    let end = chars[start..]
+        .iter()
+        .position(|&c| c == '/' || (assignment && c == ':'))
+        .map_or(chars.len(), |offset| start + offset);
+    if chars[start..end]
+        .iter()
+        .any(|c| matches!(c, '\'' | '"' | '\\' | '$' | '`'))
+    {
+        None
+    } else {
+        Some(end)
+    }
+}
+
+/// The directory a tilde-prefix names, or `None` to leave it unexpanded:
+/// `~` is `$HOME`, `~+` is `$PWD`, `~-` is `$OLDPWD` and `~user` is that
+/// user's home directory.
+fn tilde_expansion(shell: &Shell, prefix: &str) -> Option<String> {
+    match prefix {
+        "" => shell
+            .variables
+            .get("HOME")
+            .map(str::to_string)
+            .or_else(helpers::current_user_home),
+        "+" => shell.variables.get("PWD").map(str::to_string),
+        "-" => shell.variables.get("OLDPWD").map(str::to_string),
+        user => helpers::user_home(user),
+    }
+}
+
+/// Expands and evaluates the arithmetic `expression` of `$((...))` or `((...))`.
+pub fn expand_arithmetic(
+    shell: &mut Shell,
+    expression: &str,
+    terminal: &mut dyn Write,
+) -> Result<i64, ExpandError> {
+    // Quotes are removed, but the result is never split or globbed.
+    let expression = expand(shell, expression, true, false, terminal)?.into_string();
+    arith::evaluate(&expression, &mut shell.variables)
+        .map_err(|e| ExpandError(format!("{}: {}", expression.trim(), e)))
+}
+
+/// Index of the first `)` of the `))` closing a `$((` whose expression starts
+/// at `start`, or `None` if the `$((` is really `$(` followed by a subshell.
+fn arithmetic_end(chars: &[char], start: usize) -> Option<usize> {
+    let end = find_closing(chars, start, '(', ')');
+    (chars.get(end + 1) == Some(&')')).then_some(end)
+}
+
+/// Origin of the result of an expansion, which only quotes around it protect.
+fn expanded_origin(in_double_quotes: bool) -> Origin {
+    if in_double_quotes {
+        Origin::Quoted
+    } else {
+        Origin::Expanded
+    }
+}
+
+/// Index of the `close` balancing an `open` just before `start`, skipping
+/// over quotes, escapes and nested pairs.
+fn find_closing(chars: &[char], start: usize, open: char, close: char) -> usize {
+    let mut depth = 0;
+    let mut i = start;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '\'' => i = find_char(chars, i + 1, '\''),
+            '"' => {
+                i += 1;
+                while i < chars.len() && chars[i] != '"' {
+                    if chars[i] == '\\' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+            }
+            '`' => {
+                i = find_closing_backquote(chars, i + 1);
+            }
+            c if c == open => depth += 1,
+            c if c == close && depth == 0 => return i,
+            c if c == close => depth -= 1,
+            _ => {}
+        }
+        i += 1;
+    }
+    chars.len()
+}
+
+/// Index of the backquote ending a substitution whose text starts at `start`.
+fn find_closing_backquote(chars: &[char], start: usize) -> usize {
+    let mut i = start;
+    while i < chars.len() && chars[i] != '`' {
+        if chars[i] == '\\' {
+            i += 1;
+        }
+        i += 1;
+    }
+    i.min(chars.len())
+}
+
+/// Recovers the command inside backquotes, where `\\`, `` \` `` and `\$`
+/// stand for the character after the backslash.
+fn unescape_backquoted(chars: &[char]) -> String {
+    let mut source = String::with_capacity(chars.len());
+    let mut i = 0;
+    while i < chars.len() {
+        match (chars[i], chars.get(i + 1)) {
+            ('\\', Some(&next @ ('\\' | '`' | '$'))) => {
+                source.push(next);
+                i += 2;
+            }
+            (c, _) => {
+                source.push(c);
+                i += 1;
+            }
+        }
+    }
+    source
+}
+
+/// Runs `source` in a subshell and returns what it wrote to stdout, minus
+/// trailing newlines.
+///
+/// Its status becomes `$?` and is recorded for commands made only of assignments.
+fn substitute(
+    shell: &mut Shell,
+    source: &str,
+    terminal: &mut dyn Write,
+) -> Result<String, ExpandError> {
+    let list = parser::parse(source).map_err(|e| ExpandError(e.to_string()))?;
+
+    let (read, write) = streams::pipe()?;
+    // Drain the pipe while the commands run, so they never block on a full pipe.
+    let reader = thread::spawn(move || {
+        let mut output = Vec::new();
+        let _ = (&read).read_to_end(&mut output);
+        output
+    });
+    let streams = Streams {
+        stdin: Input::Terminal,
+        stdout: Output::File(write),
+        stderr: Output::Stderr,
+    };
+    let status = exec::run_subshell(shell, &list, &streams, terminal);
+    // Close the last write end, so the reader sees end of file.
+    drop(streams);
+    let output = reader.join().unwrap_or_default();
+    let status = status?;
+
+    shell.last_status = status;
+    shell.substitution_status = Some(status);
+    let mut output = String::from_utf8_lossy(&output).into_owned();
+    output.truncate(output.trim_end_matches('\n').len());
+    Ok(output)
+}
+
+/// The value of the parameter `name`, or `None` if it is unset.
+fn parameter(shell: &Shell, name: &str) -> Option<String> {
+    match name {
+        "?" => Some(shell.last_status.to_string()),
+        "#" => Some(shell.positional.len().to_string()),
+        "@" | "*" if shell.positional.is_empty() => None,
+        "@" => Some(shell.positional.join(" ")),
+        "*" => {
+            let separator: String = ifs(shell).chars().take(1).collect();
+            Some(shell.positional.join(&separator))
+        }
+        "$" => Some(process::id().to_string()),
+        "!" => shell.last_background_pid.map(|pid| pid.to_string()),
+        "0" => Some(shell.name.clone()),
+        _ => match name.parse::<usize>() {
+            Ok(0) => None,
+            Ok(index) => shell.positional.get(index - 1).cloned(),
+            Err(_) => shell.variables.get(name).map(str::to_string),
+        },
+    }
+}
+
+/// Expands the body of a `${...}`.
+fn expand_braced(
+    shell: &mut Shell,
+    body: &str,
+    in_double_quotes: bool,
+    terminal: &mut dyn Write,
+) -> Result<Expansion, ExpandError> {
+    let bad_substitution = || ExpandError(format!("${{{}}}: bad substitution", body));
+    let mut expansion = Expansion::default();
+
+    // `${#name}` is the length of the value, but `${#}` is `$#` itself.
+    if let Some(name) = body.strip_prefix('#').filter(|name| !name.is_empty()) {
+        if braced_name_len(name) != Some(name.len()) {
+            return Err(bad_substitution());
+        }
+        let length = match name {
+            "@" | "*" => shell.positional.len(),
+            _ => parameter(shell, name).unwrap_or_default().chars().count(),
+        };
+        expansion.push(Origin::Literal, &length.to_string());
+        return Ok(expansion);
+    }
+
+    let name_len = braced_name_len(body).ok_or_else(bad_substitution)?;
+    let (name, rest) = body.split_at(name_len);
+    let value = parameter(shell, name);
+    if rest.is_empty() {
+        expansion.push(Origin::Literal, &value.unwrap_or_default());
+        return Ok(expansion);
+    }
+
+    let (check_null, rest) = match rest.strip_prefix(':') {
+        Some(rest) => (true, rest),
+        None => (false, rest),
+    };
+    let mut operator_chars = rest.chars();
+    let operator = operator_chars.next().ok_or_else(bad_substitution)?;
+    let word = operator_chars.as_str();
+    // `${name:-word}` and friends treat an empty value like an unset one.
+    let missing = match &value {
+        None => true,
+        Some(value) => check_null && value.is_empty(),
+    };
+
+    match operator {
+        '-' if missing => return expand(shell, word, in_double_quotes, false, terminal),
+        '=' if missing => {
+            if !parser::is_name(name) {
+                return Err(ExpandError(format!("${}: cannot assign in this way", name)));
+            }
+            let assigned = expand(shell, word, in_double_quotes, false, terminal)?.into_string();
+            shell
+                .variables
+                .set(name, assigned.as_str())
+                .map_err(|e| ExpandError(e.to_string()))?;
+            expansion.push(Origin::Literal, &assigned);
+        }
+        '?' if missing => {
+            let message =
+                match expand(shell, word, in_double_quotes, false, terminal)?.into_string() {
+                    message if !message.is_empty() => message,
+                    _ if check_null => "parameter null or not set".to_string(),
+                    _ => "parameter not set".to_string(),
+                };
+            // A non-interactive shell exits when a required parameter is missing.
+            if !shell.is_interactive() {
+                shell.control = Some(Control::Exit(1));
+            }
+            return Err(ExpandError(format!("{}: {}", name, message)));
+        }
+        '+' if !missing => return expand(shell, word, in_double_quotes, false, terminal),
+        '+' => {}
+        '-' | '=' | '?' => expansion.push(Origin::Literal, &value.unwrap_or_default()),
+        '#' | '%' if !check_null => {
+            let value = value.unwrap_or_default();
+            let (longest, word) = match word.strip_prefix(operator) {
+                Some(word) => (true, word),
+                None => (false, word),
+            };
+            let pattern = expand(shell, word, in_double_quotes, false, terminal)?.to_pattern();
+            let remaining = if operator == '#' {
+                remove_prefix(&value, &pattern, longest)
+            } else {
+                remove_suffix(&value, &pattern, longest)
+            };
+            expansion.push(Origin::Literal, remaining);
+        }
+        _ => return Err(bad_substitution()),
+    }
+    Ok(expansion)
+}
+
+/// Length of the parameter name at the start of a `${...}` body, where
+/// positional parameters may have several digits.
+fn braced_name_len(body: &str) -> Option<usize> {
+    let first = body.chars().next()?;
+    if first.is_ascii_digit() {
+        Some(body.chars().take_while(char::is_ascii_digit).count())
+    } else if first.is_alphabetic() || first == '_' {
+        Some(
+            body.chars()
+                .take_while(|c| c.is_alphanumeric() || *c == '_')
+                .map(char::len_utf8)
+                .sum(),
+        )
+    } else if "?#@*$!".contains(first) {
+        Some(1)
+    } else {
+        None
+    }
+}
+
+/// Char boundaries of `value`, including its end.
+fn boundaries(value: &str) -> impl DoubleEndedIterator<Item = usize> + '_ {
+    value
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain(std::iter::once(value.len()))
+}
+
+/// `${name#pattern}` and `${name##pattern}`.
+fn remove_prefix<'a>(value: &'a str, pattern: &str, longest: bool) -> &'a str {
+    let matching = |&i: &usize| pattern::matches(pattern, &value[..i]);
+    let end = if longest {
+        boundaries(value).rev().find(matching)
+    } else {
+        boundaries(value).find(matching)
+    };
+    end.map_or(value, |end| &value[end..])
+}
+
+/// `${name%pattern}` and `${name%%pattern}`.
+fn remove_suffix<'a>(value: &'a str, pattern: &str, longest: bool) -> &'a str {
+    let matching = |&i: &usize| pattern::matches(pattern, &value[i..]);
+    let start = if longest {
+        boundaries(value).find(matching)
+    } else {
+        boundaries(value).rev().find(matching)
+    };
+    start.map_or(value, |start| &value[..start])
+}
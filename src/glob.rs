@@ -0,0 +1,47 @@
+use std::fs;
+
+use crate::pattern;
+
+/// Expands `pattern` to the sorted paths it matches, one path component at a time.
+///
+/// Wildcards never match a `/`, and only match a leading `.` if the component
+/// starts with a literal `.` or `dotglob` is set.
+pub fn glob(pattern: &str, dotglob: bool) -> Vec<String> {
+    let components: Vec<&str> = pattern.split('/').collect();
+    let mut paths = vec![String::new()];
+
+    for (i, component) in components.iter().enumerate() {
+        let mut next = Vec::new();
+        for prefix in &paths {
+            if !pattern::has_wildcards(component) {
+                next.push(format!("{}{}", prefix, pattern::unescape(component)));
+                continue;
+            }
+            let dir = if prefix.is_empty() { "." } else { prefix };
+            let Ok(entries) = fs::read_dir(dir) else {
+                continue;
+            };
+            let match_hidden =
+                dotglob || component.starts_with('.') || component.starts_with("\\.");
+            for entry in entries.flatten() {
+                let Ok(name) = entry.file_name().into_string() else {
+                    continue;
+                };
+                if (match_hidden || !name.starts_with('.')) && pattern::matches(component, &name) {
+                    next.push(format!("{}{}", prefix, name));
+                }
+            }
+        }
+        if i + 1 < components.len() {
+            for path in &mut next {
+                path.push('/');
+            }
+        }
+        paths = next;
+    }
+
+    // Literal components were taken on trust, so check the result exists.
+    paths.retain(|path| fs::symlink_metadata(path).is_ok());
+    paths.sort();
+    paths
+}
@@ -1,134 +1,55 @@
-use std::env;
+use std::ffi::{CStr, CString};
+use std::io;
+use std::path::{Path, PathBuf};
 
-pub fn parse_input(input: &str) -> Result<(String, Vec<String>), String> {
-    let input = input.trim();
-    let mut chars = input.chars().peekable();
-    let mut command = String::new();
-    let mut args = Vec::new();
-    let mut current_arg = String::new();
-    let mut in_single_quotes = false;
-    let mut in_double_quotes = false;
-    let mut is_first_token = true;
-    let mut in_escape = false;
+/// Resolves `command` to an existing file, searching the directories in
+/// `search_path` (a `$PATH` value) unless it contains a `/`.
+pub fn find_in_path(command: &str, search_path: &str) -> Option<PathBuf> {
+    if command.contains('/') {
+        let path = PathBuf::from(command);
+        return path.exists().then_some(path);
+    }
 
-    while let Some(c) = chars.next() {
-        if in_escape {
-            if in_double_quotes {
-                match c {
-                    '\\' | '$' | '"' | 'n' => {
-                        current_arg.push(if c == 'n' { '\n' } else { c });
-                        in_escape = false;
-                    }
-                    _ => return Err("parse error: invalid escape sequence".to_string()),
-                }
-            } else {
-                current_arg.push(c);
-                in_escape = false;
-            }
-            continue;
-        }
+    search_path
+        .split(':')
+        .map(|dir| Path::new(dir).join(command))
+        .find(|candidate| candidate.exists())
+}
 
-        if !in_single_quotes && !in_double_quotes {
-            match c {
-                '\'' => {
-                    in_single_quotes = true;
-                }
-                '"' => {
-                    in_double_quotes = true;
-                }
-                ' ' => {
-                    if !current_arg.is_empty() {
-                        if is_first_token {
-                            command = current_arg;
-                            is_first_token = false;
-                        } else {
-                            args.push(current_arg);
-                        }
-                        current_arg = String::new();
-                    }
-                }
-                '\\' => {
-                    in_escape = true;
-                }
-                '$' if chars
-                    .peek()
-                    .map(|&c| c.is_alphabetic() || c == '_')
-                    .unwrap_or(false) =>
-                {
-                    let mut var_name = String::new();
-                    while let Some(&next_c) = chars.peek() {
-                        if next_c.is_alphanumeric() || next_c == '_' {
-                            var_name.push(chars.next().unwrap());
-                        } else {
-                            break;
-                        }
-                    }
-                    let var_value = env::var(&var_name).unwrap_or_default();
-                    current_arg.push_str(&var_value);
-                }
-                _ => current_arg.push(c),
-            }
-        } else if in_single_quotes {
-            if c == '\'' {
-                in_single_quotes = false;
-                // Handle adjacent single quotes
-                if chars.peek() == Some(&'\'') {
-                    chars.next(); // Consume the second quote
-                    in_single_quotes = true; // Stay in quote mode
-                }
-            } else {
-                current_arg.push(c);
-            }
-        } else if in_double_quotes {
-            match c {
-                '"' => {
-                    in_double_quotes = false;
-                }
-                '\\' => {
-                    in_escape = true;
-                }
-                '$' if chars
-                    .peek()
-                    .map(|&c| c.is_alphabetic() || c == '_')
-                    .unwrap_or(false) =>
-                {
-                    let mut var_name = String::new();
-                    while let Some(&next_c) = chars.peek() {
-                        if next_c.is_alphanumeric() || next_c == '_' {
-                            var_name.push(chars.next().unwrap());
-                        } else {
-                            break;
-                        }
-                    }
-                    let var_value = env::var(&var_name).unwrap_or_default();
-                    current_arg.push_str(&var_value);
-                }
-                _ => current_arg.push(c),
-            }
-        }
+/// Formats an I/O error the way shells usually report it, without Rust's `(os error N)` suffix.
+pub fn describe_io_error(e: &io::Error) -> String {
+    match e.kind() {
+        io::ErrorKind::NotFound => "No such file or directory".to_string(),
+        io::ErrorKind::PermissionDenied => "Permission denied".to_string(),
+        _ => match e.raw_os_error() {
+            Some(libc::EISDIR) => "Is a directory".to_string(),
+            Some(libc::ENOTDIR) => "Not a directory".to_string(),
+            _ => e.to_string(),
+        },
     }
+}
 
-    if in_single_quotes {
-        return Err("parse error: unclosed single quote".to_string());
-    }
-    if in_double_quotes {
-        return Err("parse error: unclosed double quote".to_string());
-    }
-    if in_escape {
-        return Err("parse error: incomplete escape sequence".to_string());
-    }
+/// The home directory of `user` in the passwd database.
+pub fn user_home(user: &str) -> Option<String> {
+    let name = CString::new(user).ok()?;
+    // SAFETY: `name` is a valid C string, and the entry is copied out before
+    // any other passwd lookup could overwrite it.
+    unsafe { home_of(libc::getpwnam(name.as_ptr())) }
+}
 
-    if !current_arg.is_empty() {
-        if is_first_token {
-            command = current_arg;
-        } else {
-            args.push(current_arg);
-        }
-    }
+/// The current user's home directory in the passwd database.
+pub fn current_user_home() -> Option<String> {
+    // SAFETY: as in `user_home`.
+    unsafe { home_of(libc::getpwuid(libc::getuid())) }
+}
 
-    if command.is_empty() {
-        return Ok((String::new(), Vec::new()));
+/// # Safety
+///
+/// `entry` must be null or point to a valid passwd entry.
+unsafe fn home_of(entry: *const libc::passwd) -> Option<String> {
+    if entry.is_null() || (*entry).pw_dir.is_null() {
+        return None;
     }
-
-    Ok((command, args))
+    let dir = CStr::from_ptr((*entry).pw_dir);
+    Some(dir.to_string_lossy().into_owned())
 }
@@ -0,0 +1,238 @@
+//! The command history list and bash-style history expansion.
+//!
+//! History expansion runs on each interactive line before it is parsed:
+//! `!!` is the previous command, `!n` command number `n`, `!-n` the `n`th
+//! previous command and `!prefix` the most recent command starting with
+//! `prefix`. A line `^old^new` repeats the previous command with the first
+//! `old` replaced by `new`.
+
+use std::fs;
+use std::io;
+use std::mem;
+use std::path::Path;
+
+/// How many entries are kept when `$HISTSIZE` is unset or invalid.
+pub const DEFAULT_SIZE: usize = 500;
+
+/// Commands entered interactively, oldest first, numbered from 1.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct History {
+    entries: Vec<String>,
+    /// The number of the first entry minus one: entries dropped off the front
+    /// to respect the size limit keep the later numbers stable.
+    base: usize,
+}
+
+impl History {
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    /// The entries with their numbers, oldest first.
+    pub fn numbered(&self) -> impl Iterator<Item = (usize, &str)> {
+        self.entries
+            .iter()
+            .enumerate()
+            .map(|(index, entry)| (self.base + index + 1, entry.as_str()))
+    }
+
+    /// Appends `line`, dropping the oldest entries beyond `size`.
+    pub fn push(&mut self, line: &str, size: usize) {
+        self.entries.push(line.to_string());
+        self.truncate(size);
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.base = 0;
+    }
+
+    /// Removes the entry numbered `number`, returning whether it existed.
+    pub fn delete(&mut self, number: usize) -> bool {
+        match self.index_of(number) {
+            Some(index) => {
+                self.entries.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Appends the entries of the file at `path`, keeping at most `size` entries.
+    ///
+    /// Entries spanning several lines are read back as
+    /// [`History::write_file`] wrote them.
+    pub fn read_file(&mut self, path: &Path, size: usize) -> io::Result<()> {
+        let contents = fs::read_to_string(path)?;
+        let mut entry = String::new();
+        for line in contents.lines() {
+            let (text, continues) = decode_line(line);
+            entry.push_str(&text);
+            if continues {
+                entry.push('\n');
+            } else if !entry.trim().is_empty() {
+                self.entries.push(mem::take(&mut entry));
+            } else {
+                entry.clear();
+            }
+        }
+        if !entry.trim().is_empty() {
+            self.entries.push(entry);
+        }
+        self.truncate(size);
+        Ok(())
+    }
+
+    /// Writes the entries to the file at `path`, one per line.
+    ///
+    /// A line break inside an entry is written as a backslash ending the
+    /// line. Backslashes that already end a line are doubled, so an odd
+    /// number of them always means the entry goes on.
+    pub fn write_file(&self, path: &Path) -> io::Result<()> {
+        let mut contents = String::new();
+        for entry in &self.entries {
+            let mut lines = entry.split('\n').peekable();
+            while let Some(line) = lines.next() {
+                contents.push_str(line);
+                contents.push_str(&"\\".repeat(trailing_backslashes(line)));
+                if lines.peek().is_some() {
+                    contents.push('\\');
+                }
+                contents.push('\n');
+            }
+        }
+        fs::write(path, contents)
+    }
+
+    fn truncate(&mut self, size: usize) {
+        if self.entries.len() > size {
+            let excess = self.entries.len() - size;
+            self.entries.drain(..excess);
+            self.base += excess;
+        }
+    }
+
+    fn index_of(&self, number: usize) -> Option<usize> {
+        number
+            .checked_sub(self.base + 1)
+            .filter(|&index| index < self.entries.len())
+    }
+
+    /// Performs history expansion on `line`, returning the expanded line or
+    /// the error to report, such as `!x: event not found`.
+    pub fn expand(&self, line: &str) -> Result<String, String> {
+        if let Some(rest) = line.strip_prefix('^') {
+            return self.quick_substitution(line, rest);
+        }
+
+        let chars: Vec<char> = line.chars().collect();
+        let mut expanded = String::with_capacity(line.len());
+        let mut in_single_quotes = false;
+        let mut in_double_quotes = false;
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            match c {
+                '\'' if !in_double_quotes => in_single_quotes = !in_single_quotes,
+                '"' if !in_single_quotes => in_double_quotes = !in_double_quotes,
+                '\\' if !in_single_quotes => {
+                    expanded.push(c);
+                    if let Some(&next) = chars.get(i + 1) {
+                        expanded.push(next);
+                    }
+                    i += 2;
+                    continue;
+                }
+                '!' if !in_single_quotes && !is_literal_bang(&chars, i) => {
+                    if let Some((event, len)) = event_designator(&chars[i + 1..]) {
+                        expanded.push_str(self.event(&event)?);
+                        i += 1 + len;
+                        continue;
+                    }
+                }
+                _ => {}
+            }
+            expanded.push(c);
+            i += 1;
+        }
+        Ok(expanded)
+    }
+
+    /// Finds the entry an event designator such as `!`, `-2`, `12` or `ls` names.
+    fn event(&self, designator: &str) -> Result<&str, String> {
+        let not_found = || format!("!{}: event not found", designator);
+        let index = if designator == "!" {
+            self.entries.len().checked_sub(1)
+        } else if let Some(back) = designator.strip_prefix('-') {
+            let back: usize = back.parse().map_err(|_| not_found())?;
+            self.entries.len().checked_sub(back).filter(|_| back > 0)
+        } else if let Ok(number) = designator.parse::<usize>() {
+            self.index_of(number)
+        } else {
+            self.entries
+                .iter()
+                .rposition(|entry| entry.starts_with(designator))
+        };
+        index
+            .map(|index| self.entries[index].as_str())
+            .ok_or_else(not_found)
+    }
+
+    /// Expands `^old^new[^]` against the previous command.
+    fn quick_substitution(&self, line: &str, rest: &str) -> Result<String, String> {
+        let (old, new) = rest.split_once('^').unwrap_or((rest, ""));
+        let new = new.strip_suffix('^').unwrap_or(new);
+        let previous = self.event("!")?;
+        if old.is_empty() || !previous.contains(old) {
+            return Err(format!("{}: substitution failed", line));
+        }
+        Ok(previous.replacen(old, new, 1))
+    }
+}
+
+/// The number of backslashes at the end of `line`.
+fn trailing_backslashes(line: &str) -> usize {
+    line.len() - line.trim_end_matches('\\').len()
+}
+
+/// Undoes the escaping of a history file line, returning its text and
+/// whether the entry goes on with the next line.
+fn decode_line(line: &str) -> (String, bool) {
+    let count = trailing_backslashes(line);
+    let mut text = line[..line.len() - count].to_string();
+    text.push_str(&"\\".repeat(count / 2));
+    (text, count % 2 == 1)
+}
+
+/// Whether the `!` at `i` starts a negated bracket expression `[!...]` or an
+/// indirect expansion `${!name}` rather than a history reference.
+fn is_literal_bang(chars: &[char], i: usize) -> bool {
+    let bracket = i > 0 && chars[i - 1] == '[' && chars[i + 1..].contains(&']');
+    let indirect = i > 1 && chars[i - 2..i] == ['$', '{'];
+    bracket || indirect
+}
+
+/// Reads the event designator after a `!`, returning it with its length, or
+/// `None` if this `!` is an ordinary character.
+fn event_designator(chars: &[char]) -> Option<(String, usize)> {
+    match chars.first()? {
+        '!' => Some(("!".to_string(), 1)),
+        '-' | '0'..='9' => {
+            let digits = chars[1..].iter().take_while(|c| c.is_ascii_digit()).count();
+            if chars[0] == '-' && digits == 0 {
+                return None;
+            }
+            let len = 1 + digits;
+            Some((chars[..len].iter().collect(), len))
+        }
+        c if c.is_whitespace() || matches!(c, '=' | '(' | '"' | '\'') => None,
+        _ => {
+            // A prefix runs up to whitespace or a character that ends a command.
+            let len = chars
+                .iter()
+                .take_while(|c| !c.is_whitespace() && !matches!(c, ';' | '&' | '|' | '"' | '\''))
+                .count();
+            (len > 0).then(|| (chars[..len].iter().collect(), len))
+        }
+    }
+}
@@ -0,0 +1,713 @@
+//! The job table and the process plumbing behind job control.
+//!
+//! A job is a foreground pipeline that was stopped, or an and-or list started
+//! with `&`. An interactive shell runs each job in its own process group, so
+//! that when it owns the terminal it can hand the terminal to the foreground
+//! job and take it back once the job exits or stops.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::fd::AsRawFd;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+
+use crate::shell::{Control, Shell};
+use crate::signals;
+use crate::streams;
+
+/// What a child process is doing, as far as `waitpid` has told.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ProcessState {
+    Running,
+    Stopped(i32),
+    Exited(i32),
+    Signaled(i32),
+}
+
+impl ProcessState {
+    /// The shell status: the exit code, or 128+N for signal N.
+    pub fn status(self) -> i32 {
+        match self {
+            ProcessState::Running => 0,
+            ProcessState::Exited(code) => code,
+            ProcessState::Stopped(signal) | ProcessState::Signaled(signal) => 128 + signal,
+        }
+    }
+
+    fn from_wait_status(status: libc::c_int) -> ProcessState {
+        if libc::WIFCONTINUED(status) {
+            ProcessState::Running
+        } else if libc::WIFSTOPPED(status) {
+            ProcessState::Stopped(libc::WSTOPSIG(status))
+        } else if libc::WIFSIGNALED(status) {
+            ProcessState::Signaled(libc::WTERMSIG(status))
+        } else if libc::WIFEXITED(status) {
+            ProcessState::Exited(libc::WEXITSTATUS(status))
+        } else {
+            ProcessState::Running
+        }
+    }
+}
+
+/// A child process of the shell.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Process {
+    pub pid: i32,
+    pub state: ProcessState,
+}
+
+impl Process {
+    pub fn new(pid: i32) -> Self {
+        Process {
+            pid,
+            state: ProcessState::Running,
+        }
+    }
+
+    fn has_exited(&self) -> bool {
+        matches!(
+            self.state,
+            ProcessState::Exited(_) | ProcessState::Signaled(_)
+        )
+    }
+
+    /// Updates the state from `waitpid` with `options`, returning `false` if
+    /// the process has nothing new to report.
+    fn wait(&mut self, options: libc::c_int) -> io::Result<bool> {
+        let mut status = 0;
+        loop {
+            // SAFETY: `status` is a valid place for `waitpid` to write to.
+            match unsafe { libc::waitpid(self.pid, &mut status, options) } {
+                0 => return Ok(false),
+                -1 => {
+                    let e = io::Error::last_os_error();
+                    match e.raw_os_error() {
+                        Some(libc::EINTR) => continue,
+                        // Someone else reaped it; all that's left to say is that it's gone.
+                        Some(libc::ECHILD) => {
+                            self.state = ProcessState::Exited(0);
+                            return Ok(true);
+                        }
+                        _ => return Err(e),
+                    }
+                }
+                _ => {
+                    self.state = ProcessState::from_wait_status(status);
+                    return Ok(true);
+                }
+            }
+        }
+    }
+}
+
+/// The overall state of a job's processes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum JobState {
+    Running,
+    Stopped(i32),
+    /// Every process has exited; this is the last one's state.
+    Done(ProcessState),
+}
+
+impl JobState {
+    pub fn of(processes: &[Process]) -> JobState {
+        if processes.iter().all(Process::has_exited) {
+            let last = processes
+                .last()
+                .map_or(ProcessState::Exited(0), |p| p.state);
+            return JobState::Done(last);
+        }
+        let stopped = processes.iter().find_map(|p| match p.state {
+            ProcessState::Stopped(signal) => Some(signal),
+            _ => None,
+        });
+        match stopped {
+            Some(signal) if !processes.iter().any(|p| p.state == ProcessState::Running) => {
+                JobState::Stopped(signal)
+            }
+            _ => JobState::Running,
+        }
+    }
+
+    /// The status `$?`, `fg` and `wait` give for a job in this state.
+    pub fn status(self) -> i32 {
+        match self {
+            JobState::Running => 0,
+            JobState::Stopped(signal) => 128 + signal,
+            JobState::Done(last) => last.status(),
+        }
+    }
+
+    /// How `jobs` describes the state: `Running`, `Stopped`, `Done`, `Exit 2`, `Killed`...
+    fn describe(self) -> String {
+        match self {
+            JobState::Running => "Running".to_string(),
+            JobState::Stopped(_) => "Stopped".to_string(),
+            JobState::Done(ProcessState::Exited(0)) => "Done".to_string(),
+            JobState::Done(ProcessState::Exited(code)) => format!("Exit {}", code),
+            JobState::Done(ProcessState::Signaled(signal)) => signals::describe(signal),
+            JobState::Done(_) => "Done".to_string(),
+        }
+    }
+}
+
+/// Terminal output of a job's children that the shell relays rather than
+/// letting them inherit its stdout.
+pub(crate) type JobOutput = Arc<Mutex<Relay>>;
+
+/// What the threads behind a [`Relay`] hand over to the shell.
+enum Event {
+    /// Output of the job's children.
+    Output(Vec<u8>),
+    /// The job's process at this index changed to this state.
+    Changed(usize, ProcessState),
+    /// The reader stopped: the pipe was closed, or drained after the job's
+    /// processes exited.
+    End,
+}
+
+/// The terminal output of a job's children, read from a pipe on a
+/// background thread so that they never block on a full pipe, along with
+/// the state changes of their processes.
+///
+/// A process the job started in the background may keep the pipe open long
+/// after the job is done, so its end of file can't be relied on. Once all of
+/// the job's processes have exited, everything they wrote is in the pipe, and
+/// the reader takes what is there without waiting for more.
+#[derive(Debug)]
+pub(crate) struct Relay {
+    events: Receiver<Event>,
+    /// Lets the threads waiting for the job's processes report to the shell.
+    sender: Sender<Event>,
+    /// Written to when the job's processes have exited, telling the reader
+    /// to drain the pipe and stop.
+    drain: Option<File>,
+    /// The read end of `drain`, kept open so that writing to it can't raise
+    /// `SIGPIPE` in a forked copy of the shell once the reader is gone.
+    _drain_read: File,
+    ended: bool,
+}
+
+impl Relay {
+    /// Starts relaying the output that arrives through `pipe`.
+    pub fn start(pipe: File) -> io::Result<Relay> {
+        let (drain_read, drain_write) = streams::pipe()?;
+        let (sender, events) = mpsc::channel();
+        let reader = sender.clone();
+        let wake = drain_read.try_clone()?;
+        thread::spawn(move || {
+            read_output(&pipe, &wake, &reader);
+            let _ = reader.send(Event::End);
+        });
+        Ok(Relay {
+            events,
+            sender,
+            drain: Some(drain_write),
+            _drain_read: drain_read,
+            ended: false,
+        })
+    }
+
+    /// Starts a thread waiting for each running process of `processes` to
+    /// exit or stop, which reports it as an event.
+    fn watch(&self, processes: &[Process]) {
+        for (index, process) in processes.iter().enumerate() {
+            if process.state != ProcessState::Running {
+                continue;
+            }
+            let mut process = *process;
+            let sender = self.sender.clone();
+            thread::spawn(move || {
+                if process.wait(libc::WUNTRACED).is_err() {
+                    // Nothing more can be learned about it; treat it as gone.
+                    process.state = ProcessState::Exited(1);
+                }
+                let _ = sender.send(Event::Changed(index, process.state));
+            });
+        }
+    }
+
+    /// Tells the reader that the job's processes have exited.
+    fn drain(&mut self) {
+        if let Some(mut drain) = self.drain.take() {
+            // A forked shell may hold a copy of this end, so closing it is not enough.
+            let _ = drain.write_all(b"x");
+        }
+    }
+
+    /// The next event, waiting for it. A reader that is gone has ended.
+    fn next(&mut self) -> Event {
+        let event = self.events.recv().unwrap_or(Event::End);
+        if let Event::End = event {
+            self.ended = true;
+        }
+        event
+    }
+
+    /// Copies the output that has arrived so far to `terminal`.
+    fn relay_pending(&mut self, terminal: &mut dyn Write) -> io::Result<()> {
+        while let Ok(event) = self.events.try_recv() {
+            match event {
+                Event::Output(chunk) => terminal.write_all(&chunk)?,
+                Event::Changed(..) => {}
+                Event::End => self.ended = true,
+            }
+        }
+        terminal.flush()
+    }
+
+    /// Copies the rest of the output of a job whose processes have exited to
+    /// `terminal`.
+    fn relay_remaining(&mut self, terminal: &mut dyn Write) -> io::Result<()> {
+        self.drain();
+        while !self.ended {
+            if let Event::Output(chunk) = self.next() {
+                terminal.write_all(&chunk)?;
+            }
+        }
+        terminal.flush()
+    }
+}
+
+/// Sends what arrives through `pipe` as events until it is closed, or once
+/// `drain` is written to or closed, until the pipe is empty.
+fn read_output(pipe: &File, drain: &File, sender: &Sender<Event>) {
+    let mut buffer = [0; 8192];
+    let mut draining = false;
+    loop {
+        if !draining {
+            let mut fds = [pipe.as_raw_fd(), drain.as_raw_fd()].map(|fd| libc::pollfd {
+                fd,
+                events: libc::POLLIN,
+                revents: 0,
+            });
+            // SAFETY: `fds` holds two valid `pollfd` structures.
+            if unsafe { libc::poll(fds.as_mut_ptr(), 2, -1) } == -1 {
+                if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return;
+            }
+            if fds[1].revents != 0 {
+                draining = true;
+                // SAFETY: the descriptor is open for as long as `pipe` lives.
+                unsafe { libc::fcntl(pipe.as_raw_fd(), libc::F_SETFL, libc::O_NONBLOCK) };
+            } else if fds[0].revents == 0 {
+                continue;
+            }
+        }
+        match (&*pipe).read(&mut buffer) {
+            Ok(0) => return,
+            Ok(n) => {
+                if sender.send(Event::Output(buffer[..n].to_vec())).is_err() {
+                    return;
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => return,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Job {
+    /// The number in `%1`, `%2`...
+    pub id: usize,
+    pub pgid: i32,
+    pub processes: Vec<Process>,
+    pub command: String,
+    pub output: Option<JobOutput>,
+    /// The highest is the current job `%+`, the next the previous job `%-`.
+    recency: u64,
+    /// The state last reported, so each change is announced once.
+    reported: JobState,
+}
+
+impl Job {
+    pub fn state(&self) -> JobState {
+        JobState::of(&self.processes)
+    }
+
+    /// Sends `SIGCONT` to the job's process group and marks its stopped
+    /// processes as running.
+    pub fn resume(&mut self) {
+        // SAFETY: `killpg` has no memory-safety requirements. It only fails
+        // when the group is gone, leaving nothing to resume.
+        unsafe { libc::killpg(self.pgid, libc::SIGCONT) };
+        for process in &mut self.processes {
+            if let ProcessState::Stopped(_) = process.state {
+                process.state = ProcessState::Running;
+            }
+        }
+        self.reported = JobState::Running;
+    }
+
+    /// Copies the rest of a finished job's output to `terminal`.
+    fn relay_remaining(&self, terminal: &mut dyn Write) -> io::Result<()> {
+        match &self.output {
+            Some(output) => lock(output).relay_remaining(terminal),
+            None => Ok(()),
+        }
+    }
+
+    /// Copies whatever output the job has produced so far to `terminal`.
+    fn relay_pending(&self, terminal: &mut dyn Write) -> io::Result<()> {
+        match &self.output {
+            Some(output) => lock(output).relay_pending(terminal),
+            None => Ok(()),
+        }
+    }
+}
+
+fn lock(output: &JobOutput) -> MutexGuard<'_, Relay> {
+    output.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// The jobs of an interactive shell.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Jobs {
+    jobs: Vec<Job>,
+    recency: u64,
+    /// Statuses of finished jobs by process ID, for a later `wait PID`.
+    finished: BTreeMap<i32, i32>,
+}
+
+impl Jobs {
+    /// Adds a job as the current job, returning its number.
+    pub fn add(
+        &mut self,
+        pgid: i32,
+        processes: Vec<Process>,
+        command: &str,
+        output: Option<JobOutput>,
+    ) -> usize {
+        let id = self.jobs.iter().map(|job| job.id).max().unwrap_or(0) + 1;
+        let reported = JobState::of(&processes);
+        self.recency += 1;
+        self.jobs.push(Job {
+            id,
+            pgid,
+            processes,
+            command: command.to_string(),
+            output,
+            recency: self.recency,
+            reported,
+        });
+        id
+    }
+
+    /// Takes over the jobs of a subshell that ran in the shell's process,
+    /// which are the shell's children and would otherwise never be reaped.
+    pub fn adopt(&mut self, subshell: Jobs) {
+        for job in subshell.by_recency().into_iter().rev() {
+            let output = job.output.clone();
+            let id = self.add(job.pgid, job.processes.clone(), &job.command, output);
+            if let Some(adopted) = self.get_mut(id) {
+                adopted.reported = job.reported;
+            }
+        }
+        self.finished.extend(subshell.finished);
+    }
+
+    pub fn ids(&self) -> Vec<usize> {
+        self.jobs.iter().map(|job| job.id).collect()
+    }
+
+    pub fn get(&self, id: usize) -> Option<&Job> {
+        self.jobs.iter().find(|job| job.id == id)
+    }
+
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut Job> {
+        self.jobs.iter_mut().find(|job| job.id == id)
+    }
+
+    pub fn remove(&mut self, id: usize) -> Option<Job> {
+        let index = self.jobs.iter().position(|job| job.id == id)?;
+        Some(self.jobs.remove(index))
+    }
+
+    /// Removes finished job `id`, remembering its status for `wait`.
+    fn forget_finished(&mut self, id: usize) {
+        if let Some(job) = self.remove(id) {
+            let status = job.state().status();
+            for process in job.processes {
+                self.finished.insert(process.pid, status);
+            }
+        }
+    }
+
+    /// The status of a finished job with process `pid` that was removed from
+    /// the table, which can only be asked for once.
+    pub fn take_finished(&mut self, pid: i32) -> Option<i32> {
+        self.finished.remove(&pid)
+    }
+
+    /// Makes job `id` the current job.
+    pub fn touch(&mut self, id: usize) {
+        self.recency += 1;
+        let recency = self.recency;
+        if let Some(job) = self.get_mut(id) {
+            job.recency = recency;
+        }
+    }
+
+    /// The jobs by recency, most recent first.
+    fn by_recency(&self) -> Vec<&Job> {
+        let mut jobs: Vec<&Job> = self.jobs.iter().collect();
+        jobs.sort_by_key(|job| std::cmp::Reverse(job.recency));
+        jobs
+    }
+
+    /// The `+` of the current job, the `-` of the previous one, or a space.
+    pub fn marker(&self, id: usize) -> char {
+        match self.by_recency().iter().position(|job| job.id == id) {
+            Some(0) => '+',
+            Some(1) => '-',
+            _ => ' ',
+        }
+    }
+
+    /// Resolves a job specification: `%N`, `%%`, `%+`, `%-`, `%prefix` or
+    /// `%?text`, or a process ID.
+    ///
+    /// `None` means the current job. Errors are `SPEC: no such job`.
+    pub fn find(&self, spec: Option<&str>) -> Result<usize, String> {
+        let no_such_job = || format!("{}: no such job", spec.unwrap_or("current"));
+        let recent = self.by_recency();
+        let found = match spec.map(|spec| spec.strip_prefix('%').ok_or(spec)) {
+            None | Some(Ok("" | "%" | "+")) => recent.first().copied(),
+            Some(Ok("-")) => recent.get(1).copied(),
+            Some(Ok(spec)) => {
+                if let Ok(number) = spec.parse::<usize>() {
+                    self.get(number)
+                } else if let Some(text) = spec.strip_prefix('?') {
+                    recent.into_iter().find(|job| job.command.contains(text))
+                } else {
+                    recent.into_iter().find(|job| job.command.starts_with(spec))
+                }
+            }
+            Some(Err(pid)) => {
+                let pid = pid.parse::<i32>().map_err(|_| no_such_job())?;
+                self.jobs
+                    .iter()
+                    .find(|job| job.processes.iter().any(|p| p.pid == pid))
+            }
+        };
+        found.map(|job| job.id).ok_or_else(no_such_job)
+    }
+
+    /// Collects the state changes of all jobs without blocking.
+    pub fn update(&mut self) -> io::Result<()> {
+        for job in &mut self.jobs {
+            for process in &mut job.processes {
+                if !process.has_exited() {
+                    process.wait(libc::WNOHANG | libc::WUNTRACED | libc::WCONTINUED)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// A line of `jobs` output: `[1]+  Running                 sleep 10 &`,
+    /// with the process IDs when `long`.
+    pub fn format(&self, id: usize, long: bool) -> String {
+        let Some(job) = self.get(id) else {
+            return String::new();
+        };
+        let state = job.state();
+        let pid = if long {
+            format!("{} ", job.pgid)
+        } else {
+            String::new()
+        };
+        let background = if state == JobState::Running { " &" } else { "" };
+        format!(
+            "[{}]{}  {}{:<24}{}{}",
+            job.id,
+            self.marker(id),
+            pid,
+            state.describe(),
+            job.command,
+            background
+        )
+    }
+
+    /// Reports the jobs that finished or stopped since they were last
+    /// reported, and forgets the finished ones.
+    pub fn notify(&mut self, terminal: &mut dyn Write) -> io::Result<()> {
+        self.update()?;
+        for id in self.ids() {
+            let Some(job) = self.get(id) else { continue };
+            job.relay_pending(terminal)?;
+            let state = job.state();
+            if state != job.reported && state != JobState::Running {
+                self.report(id, false, terminal)?;
+            }
+        }
+        terminal.flush()
+    }
+
+    /// Writes job `id`'s pending output and its line of `jobs` output to
+    /// `out`, then forgets the job if it has finished.
+    pub fn report(&mut self, id: usize, long: bool, out: &mut dyn Write) -> io::Result<()> {
+        let Some(job) = self.get(id) else {
+            return Ok(());
+        };
+        let state = job.state();
+        if let JobState::Done(_) = state {
+            job.relay_remaining(out)?;
+        } else {
+            job.relay_pending(out)?;
+        }
+        writeln!(out, "{}", self.format(id, long))?;
+        if let JobState::Done(_) = state {
+            self.forget_finished(id);
+        } else if let Some(job) = self.get_mut(id) {
+            job.reported = state;
+        }
+        Ok(())
+    }
+
+    /// Waits until job `id` finishes or stops, relaying its output, and
+    /// forgets it if it finished. Returns its state.
+    pub fn wait(&mut self, id: usize, terminal: &mut dyn Write) -> io::Result<JobState> {
+        let Some(job) = self.get_mut(id) else {
+            return Ok(JobState::Done(ProcessState::Exited(127)));
+        };
+        let output = job.output.clone();
+        let mut relay = output.as_ref().map(lock);
+        wait(&mut job.processes, relay.as_deref_mut(), terminal)?;
+        let state = job.state();
+        job.reported = state;
+        if let JobState::Done(_) = state {
+            self.forget_finished(id);
+        } else {
+            self.touch(id);
+        }
+        Ok(state)
+    }
+}
+
+/// Waits until each of `processes` has exited or stopped, meanwhile copying
+/// the children's terminal output from `output` to `terminal` as it arrives.
+///
+/// Output left over when a process stops stays in `output` for when the job
+/// resumes. Once every process has exited, the rest of their output is
+/// relayed. Output that ends without a newline gets one, keeping the next
+/// prompt on its own line.
+pub(crate) fn wait(
+    processes: &mut [Process],
+    output: Option<&mut Relay>,
+    terminal: &mut dyn Write,
+) -> io::Result<()> {
+    let Some(relay) = output else {
+        for process in processes.iter_mut() {
+            if process.state == ProcessState::Running {
+                process.wait(libc::WUNTRACED)?;
+            }
+        }
+        return terminal.flush();
+    };
+    relay.watch(processes);
+    let mut ends_with_newline = true;
+    loop {
+        match JobState::of(processes) {
+            JobState::Stopped(_) => break,
+            JobState::Done(_) if relay.ended => break,
+            JobState::Done(_) => relay.drain(),
+            JobState::Running => {}
+        }
+        match relay.next() {
+            Event::Output(chunk) => {
+                terminal.write_all(&chunk)?;
+                terminal.flush()?;
+                ends_with_newline = chunk.ends_with(b"\n");
+            }
+            Event::Changed(index, state) => processes[index].state = state,
+            Event::End => {}
+        }
+    }
+    if !ends_with_newline {
+        writeln!(terminal)?;
+    }
+    terminal.flush()
+}
+
+/// Puts child `pid` in process group `pgid`.
+///
+/// The child does the same itself before running its program; doing it in
+/// both processes means the group exists by the time either relies on it.
+pub(crate) fn set_process_group(pid: i32, pgid: i32) {
+    // SAFETY: `setpgid` has no memory-safety requirements. It fails harmlessly
+    // once the child has run its program, by which time the child has done it.
+    unsafe { libc::setpgid(pid, pgid) };
+}
+
+/// Puts the shell in its own process group in the foreground of the terminal
+/// on stdin, and ignores the keyboard and terminal signals, returning whether
+/// the shell now owns the terminal.
+///
+/// A shell started in the background waits until it is brought to the foreground.
+pub(crate) fn take_terminal() -> bool {
+    // SAFETY: these calls have no memory-safety requirements.
+    unsafe {
+        if libc::isatty(libc::STDIN_FILENO) == 0 {
+            return false;
+        }
+        loop {
+            let group = libc::getpgrp();
+            if libc::tcgetpgrp(libc::STDIN_FILENO) == group {
+                break;
+            }
+            libc::kill(-group, libc::SIGTTIN);
+        }
+        signals::ignore_interactive();
+        let pid = libc::getpid();
+        libc::setpgid(pid, pid);
+        libc::tcsetpgrp(libc::STDIN_FILENO, pid) == 0
+    }
+}
+
+/// Hands the terminal to process group `pgid`, or with `None` takes it back.
+pub(crate) fn give_terminal(pgid: Option<i32>) {
+    // SAFETY: these calls have no memory-safety requirements; failure leaves
+    // the terminal where it was.
+    unsafe {
+        let pgid = pgid.unwrap_or_else(|| libc::getpgrp());
+        libc::tcsetpgrp(libc::STDIN_FILENO, pgid);
+    }
+}
+
+/// Reports a foreground job that a signal killed, as `Killed` or
+/// `Segmentation fault`.
+///
+/// Ctrl-C and broken pipes go unreported, but after Ctrl-C an interactive
+/// shell abandons the rest of the command line, as the user meant to stop it.
+/// The shell sits outside the job's process group and never gets the
+/// `SIGINT` itself, so its `trap` action is set to run here.
+pub(crate) fn finish_foreground(
+    shell: &mut Shell,
+    state: JobState,
+    terminal: &mut dyn Write,
+) -> io::Result<()> {
+    let JobState::Done(ProcessState::Signaled(signal)) = state else {
+        return Ok(());
+    };
+    match signal {
+        libc::SIGINT => {
+            // The terminal echoed `^C`; keep the next prompt on a line of its own.
+            writeln!(terminal)?;
+            if shell.is_interactive() {
+                shell.control = Some(Control::Interrupt);
+            }
+            if shell.traps.caught_signals().contains(&libc::SIGINT) {
+                signals::mark_pending(libc::SIGINT);
+            }
+        }
+        libc::SIGPIPE => {}
+        _ => writeln!(terminal, "{}", signals::describe(signal))?,
+    }
+    terminal.flush()
+}
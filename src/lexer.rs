@@ -0,0 +1,546 @@
+use std::fmt;
+use std::mem;
+
+use thiserror::Error;
+
+use crate::ast::Span;
+
+/// A line and column in the parsed source, both starting at 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Position {
+    fn of(source: &str, offset: usize) -> Self {
+        let before = &source[..offset.min(source.len())];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let column = before[line_start..].chars().count() + 1;
+        Position { line, column }
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line == 1 {
+            write!(f, "column {}", self.column)
+        } else {
+            write!(f, "line {}, column {}", self.line, self.column)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("parse error: {message} ({position})")]
+pub struct ParseError {
+    pub message: String,
+    pub span: Span,
+    pub position: Position,
+    /// The input ended in the middle of a construct, so more input could complete it.
+    pub incomplete: bool,
+}
+
+impl ParseError {
+    pub fn new(source: &str, span: Span, message: impl Into<String>) -> Self {
+        ParseError {
+            message: message.into(),
+            span,
+            position: Position::of(source, span.start),
+            incomplete: false,
+        }
+    }
+
+    pub fn incomplete(source: &str, span: Span, message: impl Into<String>) -> Self {
+        ParseError {
+            incomplete: true,
+            ..ParseError::new(source, span, message)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    /// Source text of a word, quotes and escapes included.
+    Word(String),
+    /// The digits of `2>file`, naming the file descriptor to redirect.
+    IoNumber(u32),
+    /// The expression of an `(( expression ))` command.
+    Arithmetic(String),
+    Operator(Operator),
+    Newline,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Pipe,
+    AndIf,
+    OrIf,
+    Semi,
+    /// `;;`, which ends a `case` item.
+    DSemi,
+    Amp,
+    Less,
+    Great,
+    DGreat,
+    GreatAnd,
+    AndGreat,
+    AndDGreat,
+    LParen,
+    RParen,
+}
+
+impl Operator {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Operator::Pipe => "|",
+            Operator::AndIf => "&&",
+            Operator::OrIf => "||",
+            Operator::Semi => ";",
+            Operator::DSemi => ";;",
+            Operator::Amp => "&",
+            Operator::Less => "<",
+            Operator::Great => ">",
+            Operator::DGreat => ">>",
+            Operator::GreatAnd => ">&",
+            Operator::AndGreat => "&>",
+            Operator::AndDGreat => "&>>",
+            Operator::LParen => "(",
+            Operator::RParen => ")",
+        }
+    }
+}
+
+/// Characters that end an unquoted word.
+fn is_metacharacter(c: char) -> bool {
+    matches!(
+        c,
+        ' ' | '\t' | '\n' | '|' | '&' | ';' | '<' | '>' | '(' | ')'
+    )
+}
+
+/// Splits `source` into tokens, dropping blanks, comments and escaped newlines.
+pub fn tokenize(source: &str) -> Result<Vec<Token>, ParseError> {
+    let mut lexer = Lexer {
+        source,
+        chars: source.char_indices().collect(),
+        pos: 0,
+    };
+    let mut tokens = Vec::new();
+    while let Some(token) = lexer.next_token()? {
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// Number of characters in the body of the command substitution whose `$(`
+/// `source` starts right after, or `None` if its closing `)` is missing.
+pub fn command_substitution_len(source: &str) -> Option<usize> {
+    let mut lexer = Lexer {
+        source,
+        chars: source.char_indices().collect(),
+        pos: 0,
+    };
+    lexer.read_substitution(0).ok()?;
+    Some(lexer.pos - 1)
+}
+
+struct Lexer<'a> {
+    source: &'a str,
+    chars: Vec<(usize, char)>,
+    pos: usize,
+}
+
+impl Lexer<'_> {
+    fn peek(&self) -> Option<char> {
+        self.peek_nth(0)
+    }
+
+    fn peek_nth(&self, n: usize) -> Option<char> {
+        self.chars.get(self.pos + n).map(|&(_, c)| c)
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        Some(c)
+    }
+
+    fn bump_if(&mut self, expected: char) -> bool {
+        if self.peek() == Some(expected) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Byte offset of the current character.
+    fn offset(&self) -> usize {
+        self.chars
+            .get(self.pos)
+            .map_or(self.source.len(), |&(offset, _)| offset)
+    }
+
+    fn unclosed(&self, start: usize, what: &str) -> ParseError {
+        let span = Span::new(start, self.source.len());
+        ParseError::incomplete(self.source, span, format!("unclosed {}", what))
+    }
+
+    /// Reports an escaped newline at `start` that ends the source as
+    /// incomplete, since the line it joins is still to come.
+    fn check_line_continues(&self, start: usize) -> Result<(), ParseError> {
+        if self.pos < self.chars.len() {
+            return Ok(());
+        }
+        Err(ParseError::incomplete(
+            self.source,
+            Span::new(self.chars[start].0, self.source.len()),
+            "incomplete escape sequence",
+        ))
+    }
+
+    fn next_token(&mut self) -> Result<Option<Token>, ParseError> {
+        loop {
+            match (self.peek(), self.peek_nth(1)) {
+                (Some(' ' | '\t'), _) => self.pos += 1,
+                (Some('\\'), Some('\n')) => {
+                    self.pos += 2;
+                    self.check_line_continues(self.pos - 2)?;
+                }
+                (Some('#'), _) => {
+                    while self.peek().is_some_and(|c| c != '\n') {
+                        self.pos += 1;
+                    }
+                }
+                _ => break,
+            }
+        }
+
+        let start = self.offset();
+        let Some(c) = self.bump() else {
+            return Ok(None);
+        };
+
+        let kind = match c {
+            '\n' => TokenKind::Newline,
+            '|' if self.bump_if('|') => TokenKind::Operator(Operator::OrIf),
+            '|' => TokenKind::Operator(Operator::Pipe),
+            '&' if self.bump_if('&') => TokenKind::Operator(Operator::AndIf),
+            '&' if self.bump_if('>') => {
+                if self.bump_if('>') {
+                    TokenKind::Operator(Operator::AndDGreat)
+                } else {
+                    TokenKind::Operator(Operator::AndGreat)
+                }
+            }
+            '&' => TokenKind::Operator(Operator::Amp),
+            ';' if self.bump_if(';') => TokenKind::Operator(Operator::DSemi),
+            ';' => TokenKind::Operator(Operator::Semi),
+            '<' => TokenKind::Operator(Operator::Less),
+            '>' if self.bump_if('>') => TokenKind::Operator(Operator::DGreat),
+            '>' if self.bump_if('&') => TokenKind::Operator(Operator::GreatAnd),
+            '>' => TokenKind::Operator(Operator::Great),
+            '(' if self.peek() == Some('(') => match self
+                .arithmetic_end(start, "arithmetic command")?
+            {
+                Some(end) => {
+                    let expression = self.source[self.offset() + 1..self.chars[end].0].to_string();
+                    self.pos = end + 2;
+                    TokenKind::Arithmetic(expression)
+                }
+                // `((a) | b)` is a subshell inside a subshell.
+                None => TokenKind::Operator(Operator::LParen),
+            },
+            '(' => TokenKind::Operator(Operator::LParen),
+            ')' => TokenKind::Operator(Operator::RParen),
+            _ => {
+                self.pos -= 1;
+                self.read_word()?;
+                let text = &self.source[start..self.offset()];
+                if text.chars().all(|c| c.is_ascii_digit())
+                    && matches!(self.peek(), Some('<' | '>'))
+                {
+                    let fd = text.parse().map_err(|_| {
+                        ParseError::new(
+                            self.source,
+                            Span::new(start, self.offset()),
+                            "file descriptor out of range",
+                        )
+                    })?;
+                    TokenKind::IoNumber(fd)
+                } else {
+                    TokenKind::Word(text.to_string())
+                }
+            }
+        };
+
+        Ok(Some(Token {
+            kind,
+            span: Span::new(start, self.offset()),
+        }))
+    }
+
+    /// Finds the `))` closing an `((` whose first `(` was just consumed,
+    /// returning the index of its first `)`.
+    ///
+    /// Returns `None` if a `)` at depth zero is not followed by another, as in
+    /// `((a) | b)` or `$((a) | b)`, which then hold a nested subshell.
+    fn arithmetic_end(&self, start: usize, what: &str) -> Result<Option<usize>, ParseError> {
+        let mut depth = 0;
+        for i in self.pos + 1..self.chars.len() {
+            match self.chars[i].1 {
+                '(' => depth += 1,
+                ')' if depth == 0 => {
+                    return Ok((self.chars.get(i + 1).map(|&(_, c)| c) == Some(')')).then_some(i))
+                }
+                ')' => depth -= 1,
+                _ => {}
+            }
+        }
+        Err(self.unclosed(start, what))
+    }
+
+    /// Advances past one word, validating its quotes and nested constructs.
+    fn read_word(&mut self) -> Result<(), ParseError> {
+        while let Some(c) = self.peek() {
+            match c {
+                _ if is_metacharacter(c) => break,
+                '\'' => self.read_single_quoted()?,
+                '"' => self.read_double_quoted()?,
+                '\\' => {
+                    let start = self.offset();
+                    self.pos += 1;
+                    match self.bump() {
+                        None => {
+                            return Err(ParseError::incomplete(
+                                self.source,
+                                Span::new(start, self.offset()),
+                                "incomplete escape sequence",
+                            ))
+                        }
+                        Some('\n') => self.check_line_continues(self.pos - 2)?,
+                        Some(_) => {}
+                    }
+                }
+                '$' => self.read_dollar()?,
+                '`' => self.read_backquoted()?,
+                _ => self.pos += 1,
+            }
+        }
+        Ok(())
+    }
+
+    fn read_single_quoted(&mut self) -> Result<(), ParseError> {
+        let start = self.offset();
+        self.pos += 1;
+        loop {
+            match self.bump() {
+                Some('\'') => return Ok(()),
+                Some(_) => {}
+                None => return Err(self.unclosed(start, "single quote")),
+            }
+        }
+    }
+
+    fn read_double_quoted(&mut self) -> Result<(), ParseError> {
+        let start = self.offset();
+        self.pos += 1;
+        loop {
+            match self.peek() {
+                Some('"') => {
+                    self.pos += 1;
+                    return Ok(());
+                }
+                Some('\\') => {
+                    let escape_start = self.offset();
+                    self.pos += 1;
+                    match self.bump() {
+                        Some('\\' | '$' | '"' | '`' | 'n' | '\n') => {}
+                        Some(_) => {
+                            return Err(ParseError::new(
+                                self.source,
+                                Span::new(escape_start, self.offset()),
+                                "invalid escape sequence",
+                            ))
+                        }
+                        None => return Err(self.unclosed(start, "double quote")),
+                    }
+                }
+                Some('$') => self.read_dollar()?,
+                Some('`') => self.read_backquoted()?,
+                Some(_) => self.pos += 1,
+                None => return Err(self.unclosed(start, "double quote")),
+            }
+        }
+    }
+
+    /// Advances past `$`, including a following `$(...)`, `$((...))` or `${...}`.
+    fn read_dollar(&mut self) -> Result<(), ParseError> {
+        let start = self.offset();
+        self.pos += 1;
+        match (self.peek(), self.peek_nth(1)) {
+            (Some('('), Some('(')) => {
+                self.pos += 1;
+                match self.arithmetic_end(start, "arithmetic expansion")? {
+                    Some(end) => {
+                        self.pos = end + 2;
+                        Ok(())
+                    }
+                    None => self.read_substitution(start),
+                }
+            }
+            (Some('('), _) => {
+                self.pos += 1;
+                self.read_substitution(start)
+            }
+            (Some('{'), _) => {
+                self.pos += 1;
+                self.read_braced(start)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Advances past the `}` that closes a `${` already consumed.
+    fn read_braced(&mut self, start: usize) -> Result<(), ParseError> {
+        loop {
+            match self.peek() {
+                Some('\'') => self.read_single_quoted()?,
+                Some('"') => self.read_double_quoted()?,
+                Some('`') => self.read_backquoted()?,
+                Some('$') => self.read_dollar()?,
+                Some('\\') => self.pos += 2,
+                Some('}') => {
+                    self.pos += 1;
+                    return Ok(());
+                }
+                Some(_) => self.pos += 1,
+                None => return Err(self.unclosed(start, "parameter expansion")),
+            }
+        }
+    }
+
+    /// Advances past the `)` that ends a command substitution whose `(` was
+    /// already consumed.
+    ///
+    /// The body is tokenized on the way, following nested parentheses and
+    /// `case` commands, so that the `)` after a case pattern is not taken for
+    /// the end of the body.
+    fn read_substitution(&mut self, start: usize) -> Result<(), ParseError> {
+        let mut open = Vec::new();
+        // Whether a word would start a command, so it can be a reserved word.
+        let mut command_start = true;
+        let mut redirect_target = false;
+        loop {
+            let Some(token) = self.next_token()? else {
+                return Err(self.unclosed(start, "command substitution"));
+            };
+            match token.kind {
+                TokenKind::Word(_) if mem::take(&mut redirect_target) => {}
+                TokenKind::Word(word) => match open.last_mut() {
+                    Some(Open::Case(state @ CaseState::Subject)) => *state = CaseState::In,
+                    Some(Open::Case(state @ CaseState::In)) => *state = CaseState::Patterns,
+                    Some(Open::Case(CaseState::Patterns)) if word == "esac" => {
+                        open.pop();
+                        command_start = false;
+                    }
+                    Some(Open::Case(CaseState::Patterns)) => {}
+                    _ if !command_start => {}
+                    Some(Open::Case(CaseState::Commands)) if word == "esac" => {
+                        open.pop();
+                        command_start = false;
+                    }
+                    _ if word == "case" => {
+                        open.push(Open::Case(CaseState::Subject));
+                        command_start = false;
+                    }
+                    _ => command_start = BEFORE_COMMAND.contains(&word.as_str()),
+                },
+                // The optional `(` before a pattern.
+                TokenKind::Operator(Operator::LParen)
+                    if open.last() == Some(&Open::Case(CaseState::Patterns)) => {}
+                TokenKind::Operator(Operator::LParen) => {
+                    open.push(Open::Paren);
+                    command_start = true;
+                }
+                TokenKind::Operator(Operator::RParen) => match open.last_mut() {
+                    None => return Ok(()),
+                    Some(Open::Paren) => {
+                        open.pop();
+                        // A function's body follows `name()`.
+                        command_start = true;
+                    }
+                    Some(Open::Case(state @ CaseState::Patterns)) => {
+                        *state = CaseState::Commands;
+                        command_start = true;
+                    }
+                    // The body is unfinished, so it goes on past the `)`.
+                    Some(Open::Case(_)) => {}
+                },
+                TokenKind::Operator(Operator::DSemi) => match open.last_mut() {
+                    Some(Open::Case(state @ CaseState::Commands)) => *state = CaseState::Patterns,
+                    _ => command_start = true,
+                },
+                TokenKind::Operator(
+                    Operator::Less
+                    | Operator::Great
+                    | Operator::DGreat
+                    | Operator::GreatAnd
+                    | Operator::AndGreat
+                    | Operator::AndDGreat,
+                ) => redirect_target = true,
+                TokenKind::Operator(_) | TokenKind::Newline => command_start = true,
+                TokenKind::IoNumber(_) => {}
+                TokenKind::Arithmetic(_) => command_start = false,
+            }
+        }
+    }
+
+    fn read_backquoted(&mut self) -> Result<(), ParseError> {
+        let start = self.offset();
+        self.pos += 1;
+        loop {
+            match self.bump() {
+                Some('`') => return Ok(()),
+                Some('\\') => {
+                    self.bump();
+                }
+                Some(_) => {}
+                None => return Err(self.unclosed(start, "backquote")),
+            }
+        }
+    }
+}
+
+/// Reserved words after which a word still starts a command.
+const BEFORE_COMMAND: [&str; 9] = [
+    "!", "{", "do", "elif", "else", "if", "then", "until", "while",
+];
+
+/// What is open in a command substitution body while looking for its end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Open {
+    Paren,
+    Case(CaseState),
+}
+
+/// How far a `case` command has got.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CaseState {
+    /// Before the word being matched.
+    Subject,
+    /// Before `in`.
+    In,
+    /// In the patterns of an item, which a `)` ends.
+    Patterns,
+    /// In the commands of an item, which `;;` or `esac` ends.
+    Commands,
+}
@@ -1,10 +1,58 @@
-use simple_shell::run_repl;
-use std::io::{self, stdin, stdout};
+use simple_shell::Shell;
+use std::env;
+use std::io::{self, stdin, stdout, IsTerminal};
+use std::path::Path;
 use std::process;
 
+/// Exit status for command-line usage errors, as in other POSIX shells.
+const USAGE_STATUS: i32 = 2;
+
+/// Runs the shell as
+///
+/// - `shell`: reads commands from stdin, with a prompt and line editing if
+///   it is a terminal;
+/// - `shell -c 'commands' [name [args...]]`: runs a command string;
+/// - `shell script [args...]`: runs a script file, as a `#!` interpreter does.
 fn main() -> io::Result<()> {
-    let stdin = stdin().lock();
+    let mut args = env::args();
+    let program = args.next().unwrap_or_else(|| "shell".to_string());
+    let args: Vec<String> = args.collect();
     let stdout = stdout();
-    let exit_code = run_repl(stdin, stdout)?;
+
+    let exit_code = match args.split_first() {
+        None => {
+            let stdin = stdin();
+            let interactive = stdin.is_terminal();
+            let mut shell = Shell::new()
+                .inherit_stdio(true)
+                .interactive(interactive)
+                .arguments(program, Vec::new());
+            if interactive {
+                shell.run_terminal(stdout)?
+            } else {
+                shell.run(stdin.lock(), stdout)?
+            }
+        }
+        Some((flag, rest)) if flag == "-c" => {
+            let Some((source, rest)) = rest.split_first() else {
+                eprintln!("{}: -c: option requires an argument", program);
+                process::exit(USAGE_STATUS);
+            };
+            let (name, positional) = match rest.split_first() {
+                Some((name, positional)) => (name.clone(), positional.to_vec()),
+                None => (program, Vec::new()),
+            };
+            Shell::new()
+                .inherit_stdio(true)
+                .interactive(false)
+                .arguments(name, positional)
+                .run_string(source, stdout)?
+        }
+        Some((script, positional)) => Shell::new()
+            .inherit_stdio(true)
+            .interactive(false)
+            .arguments(script.clone(), positional.to_vec())
+            .run_file(Path::new(script), stdout)?,
+    };
     process::exit(exit_code);
 }
@@ -0,0 +1,574 @@
+use std::rc::Rc;
+
+use crate::ast::{
+    AndOr, Assignment, CaseCommand, CaseItem, Command, CompoundCommand, Connector, ForLoop,
+    FunctionDefinition, IfCommand, List, ListItem, Loop, Pipeline, Redirect, RedirectKind,
+    SimpleCommand, Span, Word,
+};
+use crate::lexer::{self, Operator, Token, TokenKind};
+
+pub use crate::lexer::ParseError;
+
+/// Parses a complete piece of shell source into a command list.
+pub fn parse(source: &str) -> Result<List, ParseError> {
+    let tokens = lexer::tokenize(source)?;
+    let mut parser = Parser {
+        source,
+        tokens,
+        pos: 0,
+    };
+    let list = parser.parse_list()?;
+    match parser.peek() {
+        None => Ok(list),
+        Some(token) => Err(parser.unexpected(token)),
+    }
+}
+
+/// Reserved words that close a compound command's body.
+const LIST_TERMINATORS: [&str; 8] = ["}", "then", "elif", "else", "fi", "do", "done", "esac"];
+
+/// Whether `text` is a valid variable name.
+pub fn is_name(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn peek_operator(&self) -> Option<Operator> {
+        match self.peek()?.kind {
+            TokenKind::Operator(op) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn peek_word(&self) -> Option<&str> {
+        match &self.peek()?.kind {
+            TokenKind::Word(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    fn bump(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat_operator(&mut self, op: Operator) -> bool {
+        if self.peek_operator() == Some(op) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn skip_newlines(&mut self) {
+        while matches!(
+            self.peek(),
+            Some(Token {
+                kind: TokenKind::Newline,
+                ..
+            })
+        ) {
+            self.pos += 1;
+        }
+    }
+
+    /// The source text of the tokens from index `start` up to the current one.
+    fn text_since(&self, start: usize) -> String {
+        let from = self.tokens[start].span.start;
+        let to = self.tokens[self.pos - 1].span.end;
+        self.source[from..to].to_string()
+    }
+
+    fn end_span(&self) -> Span {
+        Span::new(self.source.len(), self.source.len())
+    }
+
+    fn unexpected(&self, token: &Token) -> ParseError {
+        let found = match &token.kind {
+            TokenKind::Word(text) => format!("'{}'", text),
+            TokenKind::IoNumber(fd) => format!("'{}'", fd),
+            TokenKind::Arithmetic(expression) => format!("'(({}))'", expression),
+            TokenKind::Operator(op) => format!("'{}'", op.as_str()),
+            TokenKind::Newline => "newline".to_string(),
+        };
+        ParseError::new(self.source, token.span, format!("unexpected {}", found))
+    }
+
+    /// Reports the next token as unexpected, or the end of input as incomplete.
+    fn unexpected_or_incomplete(&self, end_message: &str) -> ParseError {
+        match self.peek() {
+            Some(token) => self.unexpected(token),
+            None => ParseError::incomplete(self.source, self.end_span(), end_message),
+        }
+    }
+
+    fn at_list_end(&self) -> bool {
+        match self.peek() {
+            None => true,
+            Some(Token {
+                kind: TokenKind::Operator(Operator::RParen | Operator::DSemi),
+                ..
+            }) => true,
+            Some(Token {
+                kind: TokenKind::Word(text),
+                ..
+            }) => LIST_TERMINATORS.contains(&text.as_str()),
+            _ => false,
+        }
+    }
+
+    fn parse_list(&mut self) -> Result<List, ParseError> {
+        let mut items = Vec::new();
+        loop {
+            self.skip_newlines();
+            if self.at_list_end() {
+                break;
+            }
+            let and_or = self.parse_and_or()?;
+            let background = match self.peek().map(|token| &token.kind) {
+                Some(TokenKind::Operator(Operator::Amp)) => true,
+                Some(TokenKind::Operator(Operator::Semi) | TokenKind::Newline) => false,
+                _ => {
+                    items.push(ListItem {
+                        and_or,
+                        background: false,
+                    });
+                    break;
+                }
+            };
+            self.pos += 1;
+            items.push(ListItem { and_or, background });
+        }
+        Ok(List { items })
+    }
+
+    fn parse_and_or(&mut self) -> Result<AndOr, ParseError> {
+        let start = self.pos;
+        let first = self.parse_pipeline()?;
+        let mut rest = Vec::new();
+        loop {
+            let connector = match self.peek_operator() {
+                Some(Operator::AndIf) => Connector::And,
+                Some(Operator::OrIf) => Connector::Or,
+                _ => break,
+            };
+            self.pos += 1;
+            self.skip_newlines();
+            if self.peek().is_none() {
+                return Err(self.unexpected_or_incomplete("unexpected end of input"));
+            }
+            rest.push((connector, self.parse_pipeline()?));
+        }
+        Ok(AndOr {
+            first,
+            rest,
+            text: self.text_since(start),
+        })
+    }
+
+    fn parse_pipeline(&mut self) -> Result<Pipeline, ParseError> {
+        let start = self.pos;
+        let negated = self.peek_word() == Some("!");
+        if negated {
+            self.pos += 1;
+        }
+        let mut commands = vec![self.parse_command()?];
+        while self.eat_operator(Operator::Pipe) {
+            self.skip_newlines();
+            if self.peek().is_none() {
+                return Err(self.unexpected_or_incomplete("unexpected end of pipeline"));
+            }
+            commands.push(self.parse_command()?);
+        }
+        Ok(Pipeline {
+            negated,
+            commands,
+            text: self.text_since(start),
+        })
+    }
+
+    fn parse_command(&mut self) -> Result<Command, ParseError> {
+        if let Some(definition) = self.parse_function_definition()? {
+            return Ok(Command::Function(Rc::new(definition)));
+        }
+        let Some(compound) = self.parse_compound()? else {
+            return self.parse_simple_command().map(Command::Simple);
+        };
+        Ok(Command::Compound(compound, self.parse_redirects()?))
+    }
+
+    fn parse_redirects(&mut self) -> Result<Vec<Redirect>, ParseError> {
+        let mut redirects = Vec::new();
+        while let Some(redirect) = self.parse_redirect()? {
+            redirects.push(redirect);
+        }
+        Ok(redirects)
+    }
+
+    /// Parses a function definition if one starts at the next token.
+    fn parse_function_definition(&mut self) -> Result<Option<FunctionDefinition>, ParseError> {
+        let start = self.pos;
+        let name = if self.peek_word() == Some("function") {
+            self.pos += 1;
+            let name = self.expect_any_word("expected a function name")?;
+            if !is_name(&name.text) {
+                return Err(ParseError::new(
+                    self.source,
+                    name.span,
+                    format!("'{}': not a valid identifier", name.text),
+                ));
+            }
+            // The parentheses are optional after `function NAME`.
+            if self.empty_parentheses_at(self.pos) {
+                self.pos += 2;
+            }
+            name.text
+        } else {
+            match self.peek_word() {
+                Some(name) if is_name(name) && self.empty_parentheses_at(self.pos + 1) => {
+                    let name = name.to_string();
+                    self.pos += 3;
+                    name
+                }
+                _ => return Ok(None),
+            }
+        };
+        self.skip_newlines();
+        let Some(body) = self.parse_compound()? else {
+            return Err(self.unexpected_or_incomplete("expected a function body"));
+        };
+        let redirects = self.parse_redirects()?;
+        Ok(Some(FunctionDefinition {
+            name,
+            body,
+            redirects,
+            text: self.text_since(start),
+        }))
+    }
+
+    /// Whether the tokens at `index` and after it are `(` and `)`.
+    fn empty_parentheses_at(&self, index: usize) -> bool {
+        self.operator_at(index) == Some(Operator::LParen)
+            && self.operator_at(index + 1) == Some(Operator::RParen)
+    }
+
+    fn operator_at(&self, index: usize) -> Option<Operator> {
+        match self.tokens.get(index)?.kind {
+            TokenKind::Operator(op) => Some(op),
+            _ => None,
+        }
+    }
+
+    /// Parses a compound command if one starts at the next token.
+    fn parse_compound(&mut self) -> Result<Option<CompoundCommand>, ParseError> {
+        let Some(token) = self.peek() else {
+            return Ok(None);
+        };
+        let compound = match &token.kind {
+            TokenKind::Arithmetic(text) => {
+                let expression = Word {
+                    text: text.clone(),
+                    span: token.span,
+                };
+                self.pos += 1;
+                CompoundCommand::Arithmetic(expression)
+            }
+            TokenKind::Word(text) if text == "{" => {
+                self.pos += 1;
+                let body = self.parse_body()?;
+                self.expect_word("}")?;
+                CompoundCommand::BraceGroup(body)
+            }
+            TokenKind::Operator(Operator::LParen) => {
+                self.pos += 1;
+                let body = self.parse_body()?;
+                if !self.eat_operator(Operator::RParen) {
+                    return Err(self.unexpected_or_incomplete("expected ')'"));
+                }
+                CompoundCommand::Subshell(body)
+            }
+            TokenKind::Word(text) if text == "if" => {
+                self.pos += 1;
+                CompoundCommand::If(self.parse_if()?)
+            }
+            TokenKind::Word(text) if text == "while" || text == "until" => {
+                let until = text == "until";
+                self.pos += 1;
+                let condition = self.parse_body()?;
+                let body = self.parse_do_group()?;
+                let body = Loop { condition, body };
+                if until {
+                    CompoundCommand::Until(body)
+                } else {
+                    CompoundCommand::While(body)
+                }
+            }
+            TokenKind::Word(text) if text == "for" => {
+                self.pos += 1;
+                CompoundCommand::For(self.parse_for()?)
+            }
+            TokenKind::Word(text) if text == "case" => {
+                self.pos += 1;
+                CompoundCommand::Case(self.parse_case()?)
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(compound))
+    }
+
+    /// Parses the rest of an `if` command after the `if`.
+    fn parse_if(&mut self) -> Result<IfCommand, ParseError> {
+        let mut branches = Vec::new();
+        loop {
+            let condition = self.parse_body()?;
+            self.expect_word("then")?;
+            branches.push((condition, self.parse_body()?));
+            if self.peek_word() != Some("elif") {
+                break;
+            }
+            self.pos += 1;
+        }
+        let else_body = if self.peek_word() == Some("else") {
+            self.pos += 1;
+            Some(self.parse_body()?)
+        } else {
+            None
+        };
+        self.expect_word("fi")?;
+        Ok(IfCommand {
+            branches,
+            else_body,
+        })
+    }
+
+    /// Parses the rest of a `for` loop after the `for`.
+    fn parse_for(&mut self) -> Result<ForLoop, ParseError> {
+        let name = match self.peek() {
+            Some(Token {
+                kind: TokenKind::Word(text),
+                ..
+            }) if is_name(text) => text.clone(),
+            Some(Token {
+                kind: TokenKind::Word(text),
+                span,
+            }) => {
+                return Err(ParseError::new(
+                    self.source,
+                    *span,
+                    format!("'{}': not a valid identifier", text),
+                ))
+            }
+            _ => return Err(self.unexpected_or_incomplete("expected a variable name")),
+        };
+        self.pos += 1;
+        self.skip_newlines();
+        let words = if self.peek_word() == Some("in") {
+            self.pos += 1;
+            let mut words = Vec::new();
+            while let Some(TokenKind::Word(text)) = self.peek().map(|token| &token.kind) {
+                words.push(Word {
+                    text: text.clone(),
+                    span: self.tokens[self.pos].span,
+                });
+                self.pos += 1;
+            }
+            if !matches!(
+                self.peek().map(|token| &token.kind),
+                Some(TokenKind::Operator(Operator::Semi) | TokenKind::Newline)
+            ) {
+                return Err(self.unexpected_or_incomplete("expected 'do'"));
+            }
+            self.pos += 1;
+            Some(words)
+        } else {
+            self.eat_operator(Operator::Semi);
+            None
+        };
+        self.skip_newlines();
+        let body = self.parse_do_group()?;
+        Ok(ForLoop { name, words, body })
+    }
+
+    /// Parses `do list; done`.
+    fn parse_do_group(&mut self) -> Result<List, ParseError> {
+        self.expect_word("do")?;
+        let body = self.parse_body()?;
+        self.expect_word("done")?;
+        Ok(body)
+    }
+
+    /// Parses the rest of a `case` command after the `case`.
+    fn parse_case(&mut self) -> Result<CaseCommand, ParseError> {
+        let word = self.expect_any_word("expected a word")?;
+        self.skip_newlines();
+        self.expect_word("in")?;
+        let mut items = Vec::new();
+        loop {
+            self.skip_newlines();
+            if self.peek_word() == Some("esac") {
+                self.pos += 1;
+                break;
+            }
+            self.eat_operator(Operator::LParen);
+            let mut patterns = vec![self.expect_any_word("expected a pattern")?];
+            while self.eat_operator(Operator::Pipe) {
+                patterns.push(self.expect_any_word("expected a pattern")?);
+            }
+            if !self.eat_operator(Operator::RParen) {
+                return Err(self.unexpected_or_incomplete("expected ')'"));
+            }
+            // An item may have an empty body, as in `*) ;;`.
+            let body = self.parse_list()?;
+            items.push(CaseItem { patterns, body });
+            if !self.eat_operator(Operator::DSemi) {
+                self.skip_newlines();
+                self.expect_word("esac")?;
+                break;
+            }
+        }
+        Ok(CaseCommand { word, items })
+    }
+
+    /// Parses the non-empty list inside a compound command.
+    fn parse_body(&mut self) -> Result<List, ParseError> {
+        let body = self.parse_list()?;
+        if body.items.is_empty() {
+            return Err(self.unexpected_or_incomplete("unexpected end of input"));
+        }
+        Ok(body)
+    }
+
+    fn expect_word(&mut self, expected: &str) -> Result<(), ParseError> {
+        if self.peek_word() == Some(expected) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.unexpected_or_incomplete(&format!("expected '{}'", expected)))
+        }
+    }
+
+    /// Takes the next token, which must be a word.
+    fn expect_any_word(&mut self, end_message: &str) -> Result<Word, ParseError> {
+        match self.peek() {
+            Some(Token {
+                kind: TokenKind::Word(text),
+                span,
+            }) => {
+                let word = Word {
+                    text: text.clone(),
+                    span: *span,
+                };
+                self.pos += 1;
+                Ok(word)
+            }
+            _ => Err(self.unexpected_or_incomplete(end_message)),
+        }
+    }
+
+    fn parse_simple_command(&mut self) -> Result<SimpleCommand, ParseError> {
+        let mut command = SimpleCommand::default();
+        loop {
+            if let Some(redirect) = self.parse_redirect()? {
+                command.redirects.push(redirect);
+                continue;
+            }
+            let Some(TokenKind::Word(text)) = self.peek().map(|token| &token.kind) else {
+                break;
+            };
+            let word = Word {
+                text: text.clone(),
+                span: self.tokens[self.pos].span,
+            };
+            self.pos += 1;
+            match split_assignment(&word) {
+                Some(assignment) if command.words.is_empty() => {
+                    command.assignments.push(assignment)
+                }
+                _ => command.words.push(word),
+            }
+        }
+
+        if command.assignments.is_empty()
+            && command.words.is_empty()
+            && command.redirects.is_empty()
+        {
+            return Err(self.unexpected_or_incomplete("unexpected end of input"));
+        }
+        Ok(command)
+    }
+
+    fn parse_redirect(&mut self) -> Result<Option<Redirect>, ParseError> {
+        let start = self.pos;
+        let io_number = match self.peek().map(|token| &token.kind) {
+            Some(TokenKind::IoNumber(fd)) => {
+                let fd = *fd;
+                self.pos += 1;
+                Some(fd)
+            }
+            _ => None,
+        };
+        let kind = match self.peek_operator() {
+            Some(Operator::Less) => RedirectKind::Read,
+            Some(Operator::Great) => RedirectKind::Write,
+            Some(Operator::DGreat) => RedirectKind::Append,
+            Some(Operator::GreatAnd) => RedirectKind::Duplicate,
+            Some(Operator::AndGreat) if io_number.is_none() => RedirectKind::WriteBoth,
+            Some(Operator::AndDGreat) if io_number.is_none() => RedirectKind::AppendBoth,
+            _ => {
+                self.pos = start;
+                return Ok(None);
+            }
+        };
+        self.pos += 1;
+
+        let target = match self.bump() {
+            Some(Token {
+                kind: TokenKind::Word(text),
+                span,
+            }) => Word { text, span },
+            _ => {
+                self.pos -= 1;
+                let span = self.peek().map_or(self.end_span(), |token| token.span);
+                return Err(ParseError::new(
+                    self.source,
+                    span,
+                    "missing redirection target",
+                ));
+            }
+        };
+        let fd = io_number.unwrap_or(if kind == RedirectKind::Read { 0 } else { 1 });
+        Ok(Some(Redirect { fd, kind, target }))
+    }
+}
+
+/// Splits `NAME=value` into an assignment, if `word` has that shape.
+fn split_assignment(word: &Word) -> Option<Assignment> {
+    let (name, value) = word.text.split_once('=')?;
+    if !is_name(name) {
+        return None;
+    }
+    let value_start = word.span.start + name.len() + 1;
+    Some(Assignment {
+        name: name.to_string(),
+        value: Word {
+            text: value.to_string(),
+            span: Span::new(value_start, word.span.end),
+        },
+    })
+}
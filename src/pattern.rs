@@ -0,0 +1,197 @@
+//! Shell pattern matching, as used by parameter expansion, globbing and `case`.
+//!
+//! `*` matches any string, `?` any single character, and `[...]` any one of
+//! the listed characters, ranges or `[:class:]`es, negated by a leading `!`
+//! or `^`. A backslash makes the next character literal.
+
+/// Whether `text` as a whole matches `pattern`.
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let tokens = compile(pattern);
+    let text: Vec<char> = text.chars().collect();
+    matches_tokens(&tokens, &text)
+}
+
+/// Whether `pattern` contains an unescaped `*`, `?` or bracket expression.
+pub fn has_wildcards(pattern: &str) -> bool {
+    compile(pattern)
+        .iter()
+        .any(|token| !matches!(token, Token::Char(_)))
+}
+
+/// Escapes `text` so that it matches only itself.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Removes the backslashes from a pattern without wildcards, giving the only
+/// text it matches.
+pub fn unescape(pattern: &str) -> String {
+    compile(pattern)
+        .into_iter()
+        .map(|token| match token {
+            Token::Char(c) => c,
+            _ => unreachable!("unescape is only used on patterns without wildcards"),
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Char(char),
+    AnyChar,
+    AnyString,
+    Bracket {
+        negated: bool,
+        items: Vec<BracketItem>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum BracketItem {
+    Char(char),
+    Range(char, char),
+    Class(String),
+}
+
+fn compile(pattern: &str) -> Vec<Token> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let token = match chars[i] {
+            '\\' if i + 1 < chars.len() => {
+                i += 1;
+                Token::Char(chars[i])
+            }
+            '*' => Token::AnyString,
+            '?' => Token::AnyChar,
+            '[' => match compile_bracket(&chars, i + 1) {
+                Some((token, end)) => {
+                    i = end;
+                    token
+                }
+                // An unclosed bracket is an ordinary character.
+                None => Token::Char('['),
+            },
+            c => Token::Char(c),
+        };
+        tokens.push(token);
+        i += 1;
+    }
+    tokens
+}
+
+/// Compiles the bracket expression starting after the `[` at `start - 1`,
+/// returning it with the index of its closing `]`.
+fn compile_bracket(chars: &[char], start: usize) -> Option<(Token, usize)> {
+    let mut i = start;
+    let negated = matches!(chars.get(i), Some('!' | '^'));
+    if negated {
+        i += 1;
+    }
+    let first = i;
+    let mut items = Vec::new();
+    loop {
+        let c = *chars.get(i)?;
+        match c {
+            // A `]` right after the opening bracket is a member, not the end.
+            ']' if i > first => return Some((Token::Bracket { negated, items }, i)),
+            '[' if chars.get(i + 1) == Some(&':') => {
+                let rest: String = chars[i + 2..].iter().collect();
+                let end = rest.find(":]")?;
+                items.push(BracketItem::Class(rest[..end].to_string()));
+                i += 2 + rest[..end].chars().count() + 2;
+                continue;
+            }
+            _ => {
+                let c = if c == '\\' {
+                    i += 1;
+                    *chars.get(i)?
+                } else {
+                    c
+                };
+                match (chars.get(i + 1), chars.get(i + 2)) {
+                    (Some('-'), Some(&end)) if end != ']' => {
+                        items.push(BracketItem::Range(c, end));
+                        i += 3;
+                    }
+                    _ => {
+                        items.push(BracketItem::Char(c));
+                        i += 1;
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn matches_tokens(tokens: &[Token], text: &[char]) -> bool {
+    // Iterative matching with backtracking to the most recent `*`.
+    let (mut t, mut p) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match tokens.get(p) {
+            Some(Token::AnyString) => {
+                backtrack = Some((p, t));
+                p += 1;
+                continue;
+            }
+            Some(token) if matches_char(token, text[t]) => {
+                p += 1;
+                t += 1;
+                continue;
+            }
+            _ => {}
+        }
+        match backtrack {
+            Some((star, star_t)) => {
+                p = star + 1;
+                t = star_t + 1;
+                backtrack = Some((star, star_t + 1));
+            }
+            None => return false,
+        }
+    }
+    tokens[p..].iter().all(|token| *token == Token::AnyString)
+}
+
+fn matches_char(token: &Token, c: char) -> bool {
+    match token {
+        Token::Char(expected) => *expected == c,
+        Token::AnyChar => true,
+        Token::AnyString => false,
+        Token::Bracket { negated, items } => {
+            let found = items.iter().any(|item| match item {
+                BracketItem::Char(expected) => *expected == c,
+                BracketItem::Range(low, high) => (*low..=*high).contains(&c),
+                BracketItem::Class(class) => in_class(class, c),
+            });
+            found != *negated
+        }
+    }
+}
+
+fn in_class(class: &str, c: char) -> bool {
+    match class {
+        "alpha" => c.is_alphabetic(),
+        "digit" => c.is_ascii_digit(),
+        "alnum" => c.is_alphanumeric(),
+        "upper" => c.is_uppercase(),
+        "lower" => c.is_lowercase(),
+        "space" => c.is_whitespace(),
+        "blank" => c == ' ' || c == '\t',
+        "punct" => c.is_ascii_punctuation(),
+        "xdigit" => c.is_ascii_hexdigit(),
+        "cntrl" => c.is_control(),
+        "print" => !c.is_control(),
+        "graph" => !c.is_control() && !c.is_whitespace(),
+        _ => false,
+    }
+}
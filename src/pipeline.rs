@@ -0,0 +1,456 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::mem;
+use std::os::fd::AsFd;
+use std::os::unix::process::CommandExt;
+use std::process::{Command as ChildCommand, Stdio};
+use std::rc::Rc;
+use std::slice;
+use std::sync::{Arc, Mutex};
+
+use crate::ast::{Command, CompoundCommand, FunctionDefinition, Pipeline, Redirect, SimpleCommand};
+use crate::builtins;
+use crate::exec;
+use crate::expand;
+use crate::helpers;
+use crate::jobs::{self, JobState, Process, Relay};
+use crate::shell::{Control, Shell};
+use crate::signals;
+use crate::streams::{self, Input, Io, Output, Streams};
+
+/// A stage that runs inside the shell process rather than as a child.
+enum InProcess<'a> {
+    /// A builtin with its arguments and prefix assignments.
+    Builtin(String, Vec<String>, Vec<(String, String)>),
+    Compound(&'a CompoundCommand),
+    /// A function definition, which adds the function to the shell.
+    Define(&'a Rc<FunctionDefinition>),
+    /// A function call with its arguments and prefix assignments.
+    Function(Rc<FunctionDefinition>, Vec<String>, Vec<(String, String)>),
+}
+
+/// Runs `pipeline`, connecting each stage's stdout to the next stage's stdin
+/// through an OS pipe, then applying each stage's redirections on top of
+/// `streams`.
+///
+/// The stages of a multi-stage pipeline run concurrently, each in a forked
+/// copy of the shell that expands and runs it, so assignments, `exit` or `cd`
+/// only affect their own stage; an external command takes the place of its
+/// copy. A single command is expanded in the shell itself, which also runs
+/// it unless it is an external command. The returned status is the last
+/// stage's, inverted by a leading `!`.
+///
+/// Unless the shell lets children inherit its stdio, their terminal-bound
+/// output is streamed to `terminal` as it is produced.
+pub fn run_pipeline(
+    shell: &mut Shell,
+    pipeline: &Pipeline,
+    streams: &Streams,
+    terminal: &mut dyn Write,
+) -> io::Result<i32> {
+    let status = run_stages(shell, &pipeline.commands, &pipeline.text, streams, terminal)?;
+    Ok(if pipeline.negated {
+        i32::from(status == 0)
+    } else {
+        status
+    })
+}
+
+fn run_stages(
+    shell: &mut Shell,
+    commands: &[Command],
+    text: &str,
+    streams: &Streams,
+    terminal: &mut dyn Write,
+) -> io::Result<i32> {
+    let exec_last = mem::take(&mut shell.exec_last) && commands.len() == 1;
+    let mut stage_streams = commands
+        .iter()
+        .map(|_| streams.try_clone())
+        .collect::<io::Result<Vec<_>>>()?;
+    for i in 1..commands.len() {
+        let (read, write) = streams::pipe()?;
+        stage_streams[i - 1].stdout = Output::File(write);
+        stage_streams[i].stdin = Input::File(read);
+    }
+
+    // Children share a single pipe back to the terminal, so stdout and stderr
+    // stay interleaved in the order they were written.
+    let (terminal_read, mut terminal_write) = if shell.inherits_stdio() {
+        (None, None)
+    } else {
+        let (read, write) = streams::pipe()?;
+        (Some(read), Some(write))
+    };
+    // Anything the shell printed so far must land before the children's output.
+    terminal.flush()?;
+
+    let mut statuses: Vec<Option<i32>> = vec![None; commands.len()];
+    let mut children: Vec<(usize, Process)> = Vec::new();
+    // An interactive shell runs the children in a process group of their
+    // own, led by the first one.
+    let mut pgid = None;
+    // A single builtin, function or compound command runs in the shell itself.
+    let mut in_shell = None;
+
+    let mut stages = commands.iter().zip(stage_streams).enumerate();
+    while let Some((i, (command, mut streams))) = stages.next() {
+        if commands.len() > 1 {
+            let output = terminal_write.as_ref().map(File::try_clone).transpose()?;
+            let pid = exec::fork_shell(shell, terminal, |shell| {
+                // The child must not keep the pipes of later stages open, or a
+                // writer would never see its reader go away.
+                stages.by_ref().for_each(drop);
+                run_forked_stage(shell, command, text, streams, pgid, output)
+            })?;
+            if shell.is_interactive() {
+                jobs::set_process_group(pid, *pgid.get_or_insert(pid));
+            }
+            children.push((i, Process::new(pid)));
+            continue;
+        }
+
+        let simple = match command {
+            Command::Simple(simple) => simple,
+            Command::Compound(compound, redirects) => {
+                if let Err(message) = apply_redirects(shell, &mut streams, redirects, terminal) {
+                    streams
+                        .stderr
+                        .report(terminal, shell.inherits_stdio(), message)?;
+                    statuses[i] = Some(1);
+                    continue;
+                }
+                in_shell = Some((InProcess::Compound(compound), streams));
+                continue;
+            }
+            Command::Function(definition) => {
+                in_shell = Some((InProcess::Define(definition), streams));
+                continue;
+            }
+        };
+
+        let (fields, assignments) = match expand_simple(shell, simple, &mut streams, terminal) {
+            Ok(expanded) => expanded,
+            Err(message) => {
+                streams
+                    .stderr
+                    .report(terminal, shell.inherits_stdio(), message)?;
+                statuses[i] = Some(1);
+                continue;
+            }
+        };
+
+        let Some((name, args)) = fields.split_first() else {
+            // Without a command name, assignments set shell variables, and the
+            // status is that of the last command substitution, if any.
+            let mut status = shell.substitution_status.unwrap_or(0);
+            for (name, value) in assignments {
+                if let Err(e) = shell.variables.set(&name, value) {
+                    streams.stderr.report(terminal, shell.inherits_stdio(), e)?;
+                    status = 1;
+                }
+            }
+            statuses[i] = Some(status);
+            continue;
+        };
+
+        if let Some((readonly, _)) = assignments
+            .iter()
+            .find(|(name, _)| shell.variables.variable(name).is_some_and(|v| v.readonly))
+        {
+            let message = format!("{}: readonly variable", readonly);
+            streams
+                .stderr
+                .report(terminal, shell.inherits_stdio(), message)?;
+            statuses[i] = Some(1);
+            continue;
+        }
+
+        if let Some(function) = shell.functions.get(name) {
+            let call = InProcess::Function(Rc::clone(function), args.to_vec(), assignments);
+            in_shell = Some((call, streams));
+            continue;
+        }
+
+        if builtins::is_builtin(name) {
+            // Builtins never read stdin; closing it lets an upstream writer see EPIPE.
+            streams.stdin = Input::Null;
+            let builtin = InProcess::Builtin(name.clone(), args.to_vec(), assignments);
+            in_shell = Some((builtin, streams));
+            continue;
+        }
+
+        let search_path = shell.variables.get("PATH").unwrap_or_default();
+        let Some(full_path) = helpers::find_in_path(name, search_path) else {
+            let message = format!("{}: not found", name);
+            streams
+                .stderr
+                .report(terminal, shell.inherits_stdio(), message)?;
+            statuses[i] = Some(127);
+            continue;
+        };
+
+        // The stage's stderr, for reporting a command that cannot be run.
+        let stderr = streams.stderr.try_clone()?;
+        let command_name = name.rsplit('/').next().unwrap_or(name);
+        let mut process = ChildCommand::new(&full_path);
+        process
+            .arg0(command_name) // Set argv[0] to just the command name
+            .args(args)
+            .env_clear()
+            .envs(shell.variables.exported())
+            .envs(assignments)
+            .stdin(input_stdio(streams.stdin, shell))
+            .stdout(output_stdio(streams.stdout, terminal_write.as_ref())?)
+            .stderr(output_stdio(streams.stderr, terminal_write.as_ref())?);
+        if shell.is_interactive() {
+            process.process_group(pgid.unwrap_or(0));
+            // SAFETY: `signals::reset` only makes async-signal-safe calls.
+            unsafe { process.pre_exec(signals::reset) };
+        }
+
+        if exec_last {
+            // Only returns if the command could not be run.
+            let e = process.exec();
+            let message = format!("{}: {}", name, helpers::describe_io_error(&e));
+            stderr.report(terminal, shell.inherits_stdio(), message)?;
+            statuses[i] = Some(126);
+            continue;
+        }
+
+        let child = match process.spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                let message = format!("{}: {}", name, helpers::describe_io_error(&e));
+                stderr.report(terminal, shell.inherits_stdio(), message)?;
+                statuses[i] = Some(126);
+                continue;
+            }
+        };
+
+        let pid = child.id() as i32;
+        if shell.is_interactive() {
+            jobs::set_process_group(pid, *pgid.get_or_insert(pid));
+        }
+        children.push((i, Process::new(pid)));
+    }
+
+    // Only the children may hold the write end now, so the reader sees EOF once they are done.
+    terminal_write.take();
+    let mut terminal_output = terminal_read.map(Relay::start).transpose()?;
+    if shell.owns_terminal && pgid.is_some() {
+        jobs::give_terminal(pgid);
+    }
+
+    if let Some((stage, streams)) = in_shell {
+        statuses[0] = Some(run_in_process(shell, stage, streams, terminal)?);
+    }
+
+    let mut processes: Vec<Process> = children.iter().map(|(_, process)| *process).collect();
+    jobs::wait(&mut processes, terminal_output.as_mut(), terminal)?;
+    if shell.owns_terminal && pgid.is_some() {
+        jobs::give_terminal(None);
+    }
+    for (&(i, _), process) in children.iter().zip(&processes) {
+        statuses[i] = Some(process.state.status());
+    }
+
+    let state = JobState::of(&processes);
+    if let (Some(pgid), JobState::Stopped(_)) = (pgid, state) {
+        // Whatever the stopped children still write is relayed once they resume.
+        let output = terminal_output.map(|output| Arc::new(Mutex::new(output)));
+        let id = shell.jobs.add(pgid, processes, text, output);
+        writeln!(terminal)?;
+        writeln!(terminal, "{}", shell.jobs.format(id, false))?;
+        terminal.flush()?;
+        shell.control = Some(Control::Suspend);
+    } else if !processes.is_empty() {
+        jobs::finish_foreground(shell, state, terminal)?;
+    }
+
+    Ok(statuses.last().copied().flatten().unwrap_or(0))
+}
+
+/// Runs an in-process stage in `shell`, returning its status.
+fn run_in_process(
+    shell: &mut Shell,
+    stage: InProcess,
+    streams: Streams,
+    terminal: &mut dyn Write,
+) -> io::Result<i32> {
+    Ok(match stage {
+        InProcess::Builtin(name, args, assignments) => {
+            with_assignments(shell, &assignments, |shell| {
+                let inherits_stdio = shell.inherits_stdio();
+                let mut io = Io::new(&mut *terminal, streams, inherits_stdio);
+                match builtins::run(shell, &name, &args, &mut io) {
+                    Ok(status) => Ok(status),
+                    Err(e) => {
+                        writeln!(io.err(), "{}: write error: {}", name, e)?;
+                        Ok::<_, io::Error>(1)
+                    }
+                }
+            })?
+        }
+        InProcess::Compound(compound) => exec::run_compound(shell, compound, &streams, terminal)?,
+        InProcess::Define(definition) => {
+            let name = definition.name.clone();
+            shell.functions.insert(name, Rc::clone(definition));
+            0
+        }
+        InProcess::Function(function, args, assignments) => {
+            let mut streams = streams;
+            match apply_redirects(shell, &mut streams, &function.redirects, terminal) {
+                Ok(()) => with_assignments(shell, &assignments, |shell| {
+                    exec::run_function(shell, &function, args, &streams, terminal)
+                })?,
+                Err(message) => {
+                    let inherits_stdio = shell.inherits_stdio();
+                    streams.stderr.report(terminal, inherits_stdio, message)?;
+                    1
+                }
+            }
+        }
+    })
+}
+
+/// Runs a stage of a multi-stage pipeline in the forked child, returning its
+/// exit code.
+///
+/// An interactive shell puts the child in the pipeline's process group,
+/// which it leads if it is the first process of the pipeline. The stage runs
+/// like a pipeline of its own, so an external command replaces the child.
+fn run_forked_stage(
+    shell: &mut Shell,
+    command: &Command,
+    text: &str,
+    streams: Streams,
+    pgid: Option<i32>,
+    output: Option<File>,
+) -> i32 {
+    if shell.is_interactive() {
+        let pid = std::process::id() as i32;
+        jobs::set_process_group(pid, pgid.unwrap_or(pid));
+        signals::leave_interactive();
+    }
+    shell.begin_forked_copy();
+    // A stage whose reader has gone away stops, like an external command would.
+    signals::default_pipe();
+    let mut streams = streams;
+    if let Some(pipe) = output {
+        if shell.inherit_output(&pipe).is_err() {
+            return 1;
+        }
+        if let Input::Terminal = streams.stdin {
+            streams.stdin = Input::Null;
+        }
+    }
+    shell.exec_last = true;
+    let mut terminal = io::stdout();
+    let result = run_stages(
+        shell,
+        slice::from_ref(command),
+        text,
+        &streams,
+        &mut terminal,
+    );
+    let _ = terminal.flush();
+    match (result, shell.control) {
+        (Err(_), _) => 1,
+        (Ok(_), Some(Control::Exit(code) | Control::Return(code))) => code,
+        (
+            Ok(status),
+            Some(Control::Interrupt | Control::Suspend | Control::Break(_) | Control::Continue(_))
+            | None,
+        ) => status,
+    }
+}
+
+/// Runs `run` with the prefix `assignments` of a builtin or function call
+/// set, which only last for the duration of the call.
+fn with_assignments<T>(
+    shell: &mut Shell,
+    assignments: &[(String, String)],
+    run: impl FnOnce(&mut Shell) -> T,
+) -> T {
+    let saved: Vec<_> = assignments
+        .iter()
+        .map(|(name, _)| (name, shell.variables.variable(name).cloned()))
+        .collect();
+    for (name, value) in assignments {
+        // Readonly variables were rejected before the call.
+        let _ = shell.variables.set(name, value.as_str());
+    }
+    let result = run(shell);
+    for (name, variable) in saved.into_iter().rev() {
+        shell.variables.restore(name, variable);
+    }
+    result
+}
+
+/// The fields and prefix assignments of an expanded simple command.
+type ExpandedCommand = (Vec<String>, Vec<(String, String)>);
+
+/// Expands a simple command's words, applies its redirections to `streams`
+/// and expands its assignment values, in that order.
+///
+/// Returns the fields and assignments, or the message to report when an
+/// expansion or redirection fails.
+fn expand_simple(
+    shell: &mut Shell,
+    simple: &SimpleCommand,
+    streams: &mut Streams,
+    terminal: &mut dyn Write,
+) -> Result<ExpandedCommand, String> {
+    shell.substitution_status = None;
+    let fields = expand::expand_words(shell, &simple.words, terminal).map_err(|e| e.to_string())?;
+    apply_redirects(shell, streams, &simple.redirects, terminal)?;
+    let mut assignments = Vec::new();
+    for assignment in &simple.assignments {
+        let value = expand::expand_assignment(shell, &assignment.value, terminal)
+            .map_err(|e| e.to_string())?;
+        assignments.push((assignment.name.clone(), value));
+    }
+    Ok((fields, assignments))
+}
+
+/// Expands each redirection's target and applies it to `streams`, left to right.
+///
+/// A target must expand to exactly one field; any other count is an
+/// ambiguous redirect.
+fn apply_redirects(
+    shell: &mut Shell,
+    streams: &mut Streams,
+    redirects: &[Redirect],
+    terminal: &mut dyn Write,
+) -> Result<(), String> {
+    for redirect in redirects {
+        let fields =
+            expand::expand_word(shell, &redirect.target, terminal).map_err(|e| e.to_string())?;
+        let [target] = fields.as_slice() else {
+            return Err(format!("{}: ambiguous redirect", redirect.target.text));
+        };
+        streams.apply_redirect(redirect.fd, redirect.kind, target)?;
+    }
+    Ok(())
+}
+
+fn input_stdio(input: Input, shell: &Shell) -> Stdio {
+    match input {
+        Input::Terminal if shell.inherits_stdio() => Stdio::inherit(),
+        Input::Terminal | Input::Null => Stdio::null(),
+        Input::File(file) => Stdio::from(file),
+    }
+}
+
+/// Maps `output` to a child's stdio. Without a pipe to the terminal, the
+/// shell's own streams are duplicated, so a child's stderr bound to the
+/// shell's stdout writes to fd 1 and the other way round.
+fn output_stdio(output: Output, terminal: Option<&File>) -> io::Result<Stdio> {
+    Ok(match (output, terminal) {
+        (Output::Stdout | Output::Stderr, Some(pipe)) => Stdio::from(pipe.try_clone()?),
+        (Output::Stdout, None) => Stdio::from(io::stdout().as_fd().try_clone_to_owned()?),
+        (Output::Stderr, None) => Stdio::from(io::stderr().as_fd().try_clone_to_owned()?),
+        (Output::File(file), _) => Stdio::from(file),
+    })
+}
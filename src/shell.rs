@@ -0,0 +1,499 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::mem;
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::ast::{FunctionDefinition, List};
+use crate::complete::{self, CompletionSpec};
+use crate::editor::LineEditor;
+use crate::exec;
+use crate::helpers;
+use crate::history::{self, History};
+use crate::jobs::{self, Jobs};
+use crate::lexer;
+use crate::parser::{self, ParseError};
+use crate::signals;
+use crate::streams::{Output, Streams};
+use crate::trap::{self, Condition, Traps};
+use crate::variables::Variables;
+
+/// Exit status used for syntax errors, as in other POSIX shells.
+const SYNTAX_ERROR_STATUS: i32 = 2;
+
+const PROMPT: &str = "$ ";
+
+/// The prompt for continuation lines when `$PS2` is unset.
+const CONTINUATION_PROMPT: &str = "> ";
+
+/// Where an interactive or scripted shell reads its lines from.
+enum LineSource<R> {
+    Reader(R),
+    /// The terminal on stdin, through the line editor.
+    Editor(LineEditor),
+}
+
+/// A request to stop running commands, raised by a builtin and honored by the
+/// enclosing lists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Control {
+    /// `exit N`: leave the shell.
+    Exit(i32),
+    /// A foreground job was interrupted with Ctrl-C: abandon the rest of the
+    /// command line.
+    Interrupt,
+    /// A foreground job was stopped with Ctrl-Z: abandon the rest of the
+    /// command line.
+    Suspend,
+    /// `break N`: leave the N innermost loops.
+    Break(usize),
+    /// `continue N`: go on with the next iteration of the Nth innermost loop.
+    Continue(usize),
+    /// `return N`: leave the running function with status N.
+    Return(i32),
+}
+
+/// Options set with `shopt`.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct ShellOptions {
+    /// Wildcards match names starting with `.`.
+    pub dotglob: bool,
+    /// A pattern matching nothing is an error.
+    pub failglob: bool,
+    /// A pattern matching nothing expands to no fields.
+    pub nullglob: bool,
+}
+
+impl ShellOptions {
+    pub const NAMES: [&'static str; 3] = ["dotglob", "failglob", "nullglob"];
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "dotglob" => Some(&mut self.dotglob),
+            "failglob" => Some(&mut self.failglob),
+            "nullglob" => Some(&mut self.nullglob),
+            _ => None,
+        }
+    }
+}
+
+/// A shell session: its settings and the state carried between commands.
+///
+/// Cloning a shell gives the independent copy a subshell runs in.
+#[derive(Debug, Default, Clone)]
+pub struct Shell {
+    inherit_stdio: bool,
+    interactive: bool,
+    /// The shell or script name, exposed as `$0`.
+    pub(crate) name: String,
+    /// The positional parameters `$1`, `$2`, ...
+    pub(crate) positional: Vec<String>,
+    pub(crate) variables: Variables,
+    pub(crate) options: ShellOptions,
+    /// Status of the last command substitution in the command being expanded.
+    pub(crate) substitution_status: Option<i32>,
+    /// Process ID of the most recent background job, exposed as `$!`.
+    pub(crate) last_background_pid: Option<u32>,
+    /// Exit status of the most recent command, exposed as `$?`.
+    pub(crate) last_status: i32,
+    pub(crate) control: Option<Control>,
+    /// Lines entered interactively.
+    pub(crate) history: History,
+    /// Functions defined so far, by name.
+    pub(crate) functions: BTreeMap<String, Rc<FunctionDefinition>>,
+    /// Completion specifications set with `complete`, by command name.
+    pub(crate) completions: BTreeMap<String, CompletionSpec>,
+    /// Background and stopped jobs.
+    pub(crate) jobs: Jobs,
+    /// Actions set with `trap`.
+    pub(crate) traps: Traps,
+    /// Whether the shell controls the terminal on stdin, handing it to each
+    /// foreground job and taking it back afterwards.
+    pub(crate) owns_terminal: bool,
+    /// The number of loops around the running command, which `break` and
+    /// `continue` may leave.
+    pub(crate) loop_depth: usize,
+    /// The number of `if`, `while` and `until` conditions around the running
+    /// command, whose failures do not trigger the `ERR` trap.
+    pub(crate) condition_depth: usize,
+    /// The number of function calls around the running command.
+    pub(crate) function_depth: usize,
+    /// Whether the shell is a forked copy that exits after the pipeline it
+    /// is about to run, so an external command can replace the process
+    /// instead of running as its child.
+    pub(crate) exec_last: bool,
+}
+
+impl Shell {
+    /// Creates an interactive shell named `shell` with no positional
+    /// parameters, whose variables start as a copy of the process environment.
+    pub fn new() -> Self {
+        let mut variables = Variables::from_env();
+        if let Ok(cwd) = env::current_dir() {
+            let _ = variables.set("PWD", cwd.to_string_lossy().into_owned());
+            variables.export("PWD");
+        }
+        Shell {
+            interactive: true,
+            name: "shell".to_string(),
+            variables,
+            ..Shell::default()
+        }
+    }
+
+    /// Sets whether the shell prompts for input. Scripts and `-c` strings run
+    /// non-interactively.
+    pub fn interactive(mut self, interactive: bool) -> Self {
+        self.interactive = interactive;
+        self
+    }
+
+    /// Sets `$0` and the positional parameters `$1`, `$2`, ...
+    pub fn arguments(mut self, name: impl Into<String>, positional: Vec<String>) -> Self {
+        self.name = name.into();
+        self.positional = positional;
+        self
+    }
+
+    /// Lets child processes use the shell's own stdin, stdout and stderr
+    /// directly instead of streaming their output through the REPL writer.
+    ///
+    /// Use this when the writer passed to [`Shell::run`] is the process's stdout.
+    pub fn inherit_stdio(mut self, inherit: bool) -> Self {
+        self.inherit_stdio = inherit;
+        self
+    }
+
+    pub(crate) fn inherits_stdio(&self) -> bool {
+        self.inherit_stdio
+    }
+
+    pub(crate) fn is_interactive(&self) -> bool {
+        self.interactive
+    }
+
+    /// Prepares a forked copy of the shell that runs a background job or a
+    /// pipeline stage, which has no jobs or job control of its own.
+    pub(crate) fn begin_forked_copy(&mut self) {
+        self.jobs = Jobs::default();
+        for signal in self.traps.caught_signals() {
+            signals::restore(signal);
+        }
+        self.traps = self.traps.for_subshell();
+        self.interactive = false;
+        self.owns_terminal = false;
+    }
+
+    /// Points fds 1 and 2 of a forked copy of the shell at `output`, and
+    /// lets its children inherit them.
+    pub(crate) fn inherit_output(&mut self, output: &File) -> io::Result<()> {
+        for fd in [libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+            // SAFETY: both descriptors are open for the duration of the call.
+            if unsafe { libc::dup2(output.as_raw_fd(), fd) } == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        self.inherit_stdio = true;
+        Ok(())
+    }
+
+    /// Exit status of the most recently run command.
+    pub fn last_status(&self) -> i32 {
+        self.last_status
+    }
+
+    /// Reads and runs commands from `reader` until `exit` or end of input,
+    /// returning the exit code.
+    ///
+    /// At end of input the shell exits with the status of the last command.
+    pub fn run<R: BufRead, W: Write>(&mut self, reader: R, writer: W) -> io::Result<i32> {
+        self.run_lines(LineSource::Reader(reader), writer)
+    }
+
+    /// Like [`Shell::run`], but reads commands from the terminal on stdin with
+    /// line editing, history and job control.
+    ///
+    /// The history is loaded from `$HISTFILE` at the start and saved there
+    /// when the shell exits.
+    pub fn run_terminal<W: Write>(&mut self, mut writer: W) -> io::Result<i32> {
+        self.owns_terminal = jobs::take_terminal();
+        if let Some(path) = self.history_file() {
+            // A missing history file just means an empty history.
+            let _ = self.history.read_file(&path, self.history_size());
+        }
+        let code = self.run_lines(
+            LineSource::<io::Empty>::Editor(LineEditor::new()),
+            &mut writer,
+        )?;
+        if let Some(path) = self.history_file() {
+            if let Err(e) = self.history.write_file(&path) {
+                writeln!(
+                    writer,
+                    "history: {}: {}",
+                    path.display(),
+                    helpers::describe_io_error(&e)
+                )?;
+                writer.flush()?;
+            }
+        }
+        Ok(code)
+    }
+
+    /// The file the history is saved in: `$HISTFILE`, by default
+    /// `~/.shell_history`. An empty `$HISTFILE` disables saving.
+    pub(crate) fn history_file(&self) -> Option<PathBuf> {
+        match self.variables.get("HISTFILE") {
+            Some("") => None,
+            Some(path) => Some(PathBuf::from(path)),
+            None => {
+                let home = self
+                    .variables
+                    .get("HOME")
+                    .map(str::to_string)
+                    .or_else(helpers::current_user_home)?;
+                Some(Path::new(&home).join(".shell_history"))
+            }
+        }
+    }
+
+    /// The number of history entries to keep, from `$HISTSIZE`.
+    pub(crate) fn history_size(&self) -> usize {
+        self.variables
+            .get("HISTSIZE")
+            .and_then(|size| size.parse().ok())
+            .unwrap_or(history::DEFAULT_SIZE)
+    }
+
+    fn run_lines<R: BufRead, W: Write>(
+        &mut self,
+        mut source: LineSource<R>,
+        mut writer: W,
+    ) -> io::Result<i32> {
+        // The lines read so far of a command that is not complete yet, such
+        // as an `if` still missing its `fi`, and the same command joined
+        // into a single line for the history.
+        let mut pending = String::new();
+        let mut history_entry = String::new();
+        loop {
+            if pending.is_empty() {
+                if self.interactive {
+                    self.jobs.notify(&mut writer)?;
+                }
+                // Signals may arrive while the shell waits for input.
+                trap::run_pending(self, &mut writer)?;
+                if let Some(Control::Exit(code)) = self.control.take() {
+                    return self.finish(code, &mut writer);
+                }
+            }
+            let prompt = if pending.is_empty() {
+                PROMPT.to_string()
+            } else {
+                self.continuation_prompt()
+            };
+            let line = match &mut source {
+                LineSource::Reader(reader) => {
+                    if self.interactive {
+                        write!(writer, "{}", prompt)?;
+                        writer.flush()?;
+                    }
+                    let mut input = String::new();
+                    (reader.read_line(&mut input)? > 0).then_some(input)
+                }
+                LineSource::Editor(editor) => {
+                    // Completion functions may change the shell, history included.
+                    let history = self.history.entries().to_vec();
+                    let line = editor.read_line(
+                        &prompt,
+                        &history,
+                        &mut |line, cursor| complete::complete(self, line, cursor),
+                        &mut writer,
+                    )?;
+                    if editor.cancelled() {
+                        // Ctrl-C abandons the whole command, not just this line.
+                        pending.clear();
+                        history_entry.clear();
+                        continue;
+                    }
+                    line
+                }
+            };
+            let Some(input) = line else {
+                if self.interactive {
+                    // Move past the prompt, as after Ctrl-D on a terminal.
+                    writeln!(writer)?;
+                    writer.flush()?;
+                }
+                if !pending.is_empty() {
+                    // Reports the construct the input ended in.
+                    if let Some(code) = self.run_line(&pending, &mut writer)? {
+                        return self.finish(code, &mut writer);
+                    }
+                }
+                return self.finish(self.last_status, &mut writer);
+            };
+            let input = input.strip_suffix('\n').unwrap_or(&input);
+
+            if input.trim().is_empty() && pending.is_empty() {
+                continue;
+            }
+            let expanded;
+            let input = if self.interactive {
+                expanded = match self.history.expand(input) {
+                    Ok(expanded) => expanded,
+                    Err(e) => {
+                        Output::Stderr.report(&mut writer, self.inherit_stdio, e)?;
+                        pending.clear();
+                        history_entry.clear();
+                        continue;
+                    }
+                };
+                if expanded != input {
+                    // Show the command that history expansion produced.
+                    writeln!(writer, "{}", expanded)?;
+                }
+                expanded.as_str()
+            } else {
+                input
+            };
+
+            if pending.is_empty() {
+                history_entry.push_str(input);
+            } else {
+                join_history_line(&mut history_entry, input);
+            }
+            pending.push_str(input);
+            pending.push('\n');
+            let parsed = match parser::parse(&pending) {
+                Err(e) if e.incomplete => continue,
+                parsed => parsed,
+            };
+            let entry = mem::take(&mut history_entry);
+            if self.interactive {
+                self.history.push(&entry, self.history_size());
+            }
+            pending.clear();
+
+            if let Some(code) = self.run_parsed(parsed, &mut writer)? {
+                return self.finish(code, &mut writer);
+            }
+        }
+    }
+
+    /// The prompt for the next line of an incomplete command: `$PS2`, by
+    /// default `> `.
+    fn continuation_prompt(&self) -> String {
+        self.variables
+            .get("PS2")
+            .unwrap_or(CONTINUATION_PROMPT)
+            .to_string()
+    }
+
+    /// Runs the script at `path` line by line, returning the exit code.
+    ///
+    /// A script that cannot be opened is reported on `writer` and gives 127 if
+    /// it does not exist, or 126 otherwise.
+    pub fn run_file<W: Write>(&mut self, path: &Path, mut writer: W) -> io::Result<i32> {
+        match File::open(path) {
+            Ok(file) => self.run(BufReader::new(file), writer),
+            Err(e) => {
+                let message = format!("{}: {}", path.display(), helpers::describe_io_error(&e));
+                Output::Stderr.report(&mut writer, self.inherit_stdio, message)?;
+                writer.flush()?;
+                Ok(if e.kind() == io::ErrorKind::NotFound {
+                    127
+                } else {
+                    126
+                })
+            }
+        }
+    }
+
+    /// Runs `source` as a whole, as for `-c`, returning the exit code.
+    pub fn run_string<W: Write>(&mut self, source: &str, mut writer: W) -> io::Result<i32> {
+        let code = self.run_line(source, &mut writer)?;
+        let code = self.finish(code.unwrap_or(self.last_status), &mut writer)?;
+        writer.flush()?;
+        Ok(code)
+    }
+
+    /// Runs the `EXIT` trap before the shell exits with `code`, returning the
+    /// exit code, which the trap may change with `exit`.
+    fn finish<W: Write>(&mut self, code: i32, writer: &mut W) -> io::Result<i32> {
+        self.last_status = code;
+        trap::run(self, Condition::Exit, writer)?;
+        self.traps.reset(Condition::Exit);
+        writer.flush()?;
+        Ok(match self.control.take() {
+            Some(Control::Exit(code)) => code,
+            Some(
+                Control::Interrupt
+                | Control::Suspend
+                | Control::Break(_)
+                | Control::Continue(_)
+                | Control::Return(_),
+            )
+            | None => code,
+        })
+    }
+
+    /// Runs one line of input, returning the exit code if the shell should exit.
+    fn run_line<W: Write>(&mut self, input: &str, writer: &mut W) -> io::Result<Option<i32>> {
+        self.run_parsed(parser::parse(input), writer)
+    }
+
+    /// Runs a parsed command, or reports why it could not be parsed.
+    ///
+    /// A syntax error makes a non-interactive shell exit.
+    fn run_parsed<W: Write>(
+        &mut self,
+        parsed: Result<List, ParseError>,
+        writer: &mut W,
+    ) -> io::Result<Option<i32>> {
+        let list = match parsed {
+            Ok(list) => list,
+            Err(e) => {
+                Output::Stderr.report(writer, self.inherit_stdio, e)?;
+                self.last_status = SYNTAX_ERROR_STATUS;
+                return Ok((!self.interactive).then_some(SYNTAX_ERROR_STATUS));
+            }
+        };
+
+        exec::run_list(self, &list, &Streams::default(), writer)?;
+        match self.control.take() {
+            Some(Control::Exit(code)) => Ok(Some(code)),
+            Some(
+                Control::Interrupt
+                | Control::Suspend
+                | Control::Break(_)
+                | Control::Continue(_)
+                | Control::Return(_),
+            )
+            | None => Ok(None),
+        }
+    }
+}
+
+/// Appends a continuation `line` to the single-line history `entry` of a
+/// command, so the command can be recalled and run again as one line.
+///
+/// A line break that ended a command becomes `; ` and one between words a
+/// space, while one inside a quoted word stays and an escaped one is dropped.
+fn join_history_line(entry: &mut String, line: &str) {
+    if lexer::tokenize(entry).is_err() {
+        if entry.ends_with('\\') {
+            entry.pop();
+        } else {
+            entry.push('\n');
+        }
+        entry.push_str(line);
+        return;
+    }
+    match parser::parse(&format!("{};", entry)) {
+        Err(e) if !e.incomplete => entry.push(' '),
+        _ => entry.push_str("; "),
+    }
+    entry.push_str(line.trim_start());
+}
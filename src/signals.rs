@@ -0,0 +1,209 @@
+//! Signal dispositions of the shell and its children.
+//!
+//! Keyboard signals from the terminal are meant for the foreground job, so an
+//! interactive shell ignores them itself. Ignored signals stay ignored across
+//! `exec`, so children put back the default behavior before running their
+//! program, except for those `trap ''` ignores on purpose.
+//!
+//! Signals with a `trap` action are caught by a handler that only records
+//! them; the shell runs the actions later, between commands.
+
+use std::ffi::CStr;
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// Signal names without their `SIG` prefix, which `trap` accepts either way.
+const NAMES: [(&str, libc::c_int); 29] = [
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("ILL", libc::SIGILL),
+    ("TRAP", libc::SIGTRAP),
+    ("ABRT", libc::SIGABRT),
+    ("BUS", libc::SIGBUS),
+    ("FPE", libc::SIGFPE),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("SEGV", libc::SIGSEGV),
+    ("USR2", libc::SIGUSR2),
+    ("PIPE", libc::SIGPIPE),
+    ("ALRM", libc::SIGALRM),
+    ("TERM", libc::SIGTERM),
+    ("CHLD", libc::SIGCHLD),
+    ("CONT", libc::SIGCONT),
+    ("STOP", libc::SIGSTOP),
+    ("TSTP", libc::SIGTSTP),
+    ("TTIN", libc::SIGTTIN),
+    ("TTOU", libc::SIGTTOU),
+    ("URG", libc::SIGURG),
+    ("XCPU", libc::SIGXCPU),
+    ("XFSZ", libc::SIGXFSZ),
+    ("VTALRM", libc::SIGVTALRM),
+    ("PROF", libc::SIGPROF),
+    ("WINCH", libc::SIGWINCH),
+    ("IO", libc::SIGIO),
+    ("SYS", libc::SIGSYS),
+];
+
+/// Whether the shell ignores the keyboard signals as an interactive shell.
+static INTERACTIVE: AtomicBool = AtomicBool::new(false);
+/// Signals ignored with `trap ''`, one bit per signal number.
+static IGNORED: AtomicU64 = AtomicU64::new(0);
+/// Signals caught but not yet acted on, one bit per signal number.
+static PENDING: AtomicU64 = AtomicU64::new(0);
+
+/// Signals an interactive shell ignores: Ctrl-C, Ctrl-\ and Ctrl-Z, and the
+/// ones sent when it uses the terminal while a job is in the foreground.
+const INTERACTIVE_IGNORED: [libc::c_int; 5] = [
+    libc::SIGINT,
+    libc::SIGQUIT,
+    libc::SIGTSTP,
+    libc::SIGTTIN,
+    libc::SIGTTOU,
+];
+
+/// Makes the shell ignore the keyboard and terminal signals.
+pub(crate) fn ignore_interactive() {
+    INTERACTIVE.store(true, Ordering::SeqCst);
+    for signal in INTERACTIVE_IGNORED {
+        // SAFETY: SIG_IGN is a valid disposition for these signals.
+        unsafe { libc::signal(signal, libc::SIG_IGN) };
+    }
+}
+
+/// Restores the default behavior of the signals [`ignore_interactive`]
+/// ignores, unless `trap ''` ignores them too.
+///
+/// Runs in children between `fork` and `exec`, so it only makes
+/// async-signal-safe calls.
+pub(crate) fn reset() -> io::Result<()> {
+    let ignored = IGNORED.load(Ordering::SeqCst);
+    for signal in INTERACTIVE_IGNORED {
+        if ignored & bit(signal) == 0 {
+            // SAFETY: `signal` is async-signal-safe and SIG_DFL is a valid disposition.
+            unsafe { libc::signal(signal, libc::SIG_DFL) };
+        }
+    }
+    Ok(())
+}
+
+/// Makes a forked copy of an interactive shell behave like a non-interactive one.
+pub(crate) fn leave_interactive() {
+    INTERACTIVE.store(false, Ordering::SeqCst);
+    let _ = reset();
+}
+
+/// Lets `SIGPIPE` end a forked copy of the shell, as it ends external
+/// commands, unless `trap ''` ignores it. The Rust runtime ignores it.
+pub(crate) fn default_pipe() {
+    if IGNORED.load(Ordering::SeqCst) & bit(libc::SIGPIPE) == 0 {
+        // SAFETY: SIG_DFL is a valid disposition for SIGPIPE.
+        unsafe { libc::signal(libc::SIGPIPE, libc::SIG_DFL) };
+    }
+}
+
+/// Catches `signal`, recording it for [`take_pending`].
+pub(crate) fn catch(signal: i32) {
+    IGNORED.fetch_and(!bit(signal), Ordering::SeqCst);
+    // SAFETY: `record` only touches an atomic, so it is safe to run as a
+    // handler; the zeroed `sigaction` is a valid starting point.
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = record as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(signal, &action, std::ptr::null_mut());
+    }
+}
+
+/// Ignores `signal`, in children too.
+pub(crate) fn ignore(signal: i32) {
+    IGNORED.fetch_or(bit(signal), Ordering::SeqCst);
+    // SAFETY: SIG_IGN is a valid disposition; the call fails harmlessly for
+    // signals that cannot be ignored.
+    unsafe { libc::signal(signal, libc::SIG_IGN) };
+}
+
+/// Gives `signal` back the behavior it has without a trap: ignored for the
+/// keyboard signals of an interactive shell, the default otherwise.
+pub(crate) fn restore(signal: i32) {
+    IGNORED.fetch_and(!bit(signal), Ordering::SeqCst);
+    let disposition = if INTERACTIVE.load(Ordering::SeqCst) && INTERACTIVE_IGNORED.contains(&signal)
+    {
+        libc::SIG_IGN
+    } else {
+        libc::SIG_DFL
+    };
+    // SAFETY: both dispositions are valid for any signal that can be caught.
+    unsafe { libc::signal(signal, disposition) };
+}
+
+/// Takes the caught signals among `signals` that are waiting to be acted on,
+/// leaving the others pending.
+pub(crate) fn take_pending(signals: impl IntoIterator<Item = i32>) -> Vec<i32> {
+    let mask = signals
+        .into_iter()
+        .fold(0, |mask, signal| mask | bit(signal));
+    let pending = PENDING.fetch_and(!mask, Ordering::SeqCst) & mask;
+    (1..64)
+        .filter(|&signal| pending & bit(signal) != 0)
+        .collect()
+}
+
+/// Records `signal` as caught, as if it had arrived at the shell itself.
+pub(crate) fn mark_pending(signal: i32) {
+    PENDING.fetch_or(bit(signal), Ordering::SeqCst);
+}
+
+extern "C" fn record(signal: libc::c_int) {
+    PENDING.fetch_or(bit(signal), Ordering::SeqCst);
+}
+
+fn bit(signal: i32) -> u64 {
+    1u64.checked_shl(signal as u32).unwrap_or(0)
+}
+
+/// The number of the signal named `name`, with or without `SIG` in any
+/// case, or given by its number.
+pub(crate) fn number(name: &str) -> Option<i32> {
+    if let Ok(number) = name.parse::<i32>() {
+        return NAMES
+            .iter()
+            .any(|&(_, signal)| signal == number)
+            .then_some(number);
+    }
+    let upper = name.to_ascii_uppercase();
+    let bare = upper.strip_prefix("SIG").unwrap_or(&upper);
+    NAMES
+        .iter()
+        .find(|(signal_name, _)| *signal_name == bare)
+        .map(|&(_, signal)| signal)
+}
+
+/// The name of `signal` with its `SIG` prefix, such as `SIGINT`.
+pub(crate) fn name(signal: i32) -> String {
+    match NAMES.iter().find(|&&(_, number)| number == signal) {
+        Some((name, _)) => format!("SIG{}", name),
+        None => format!("SIG{}", signal),
+    }
+}
+
+/// All signal numbers with names, in ascending order.
+pub(crate) fn all() -> Vec<i32> {
+    let mut numbers: Vec<i32> = NAMES.iter().map(|&(_, number)| number).collect();
+    numbers.sort_unstable();
+    numbers
+}
+
+/// The description of `signal`, such as `Terminated` or `Killed`.
+pub(crate) fn describe(signal: i32) -> String {
+    // SAFETY: `strsignal` returns null or a valid C string, which is copied
+    // out before any other call could overwrite it.
+    unsafe {
+        let name = libc::strsignal(signal);
+        if name.is_null() {
+            return format!("Signal {}", signal);
+        }
+        CStr::from_ptr(name).to_string_lossy().into_owned()
+    }
+}
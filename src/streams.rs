@@ -0,0 +1,223 @@
+use std::fmt::Display;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::fd::FromRawFd;
+
+use crate::ast::RedirectKind;
+use crate::helpers;
+
+/// Where a command reads its standard input from.
+#[derive(Debug)]
+pub enum Input {
+    /// The shell's own stdin when children inherit it, otherwise no input.
+    Terminal,
+    /// No input; reads see end of file immediately.
+    Null,
+    File(File),
+}
+
+/// Where one of a command's output streams goes.
+#[derive(Debug)]
+pub enum Output {
+    /// The shell's own stdout: the REPL writer, or the inherited fd 1.
+    Stdout,
+    /// The shell's own stderr: the REPL writer, or the inherited fd 2.
+    Stderr,
+    File(File),
+}
+
+impl Input {
+    fn try_clone(&self) -> io::Result<Input> {
+        match self {
+            Input::Terminal => Ok(Input::Terminal),
+            Input::Null => Ok(Input::Null),
+            Input::File(file) => Ok(Input::File(file.try_clone()?)),
+        }
+    }
+}
+
+impl Output {
+    pub fn try_clone(&self) -> io::Result<Output> {
+        match self {
+            Output::Stdout => Ok(Output::Stdout),
+            Output::Stderr => Ok(Output::Stderr),
+            Output::File(file) => Ok(Output::File(file.try_clone()?)),
+        }
+    }
+
+    /// Writes the diagnostic `message` as a line to this stream, with the
+    /// shell's own streams resolved as for a builtin's [`Io`].
+    pub fn report(
+        &self,
+        terminal: &mut dyn Write,
+        inherits_stdio: bool,
+        message: impl Display,
+    ) -> io::Result<()> {
+        match self {
+            Output::Stderr if inherits_stdio => {
+                // Keep the message after anything the shell printed before it.
+                terminal.flush()?;
+                writeln!(io::stderr(), "{}", message)
+            }
+            Output::Stdout | Output::Stderr => writeln!(terminal, "{}", message),
+            Output::File(file) => writeln!(&*file, "{}", message),
+        }
+    }
+}
+
+/// The standard streams of a command about to run.
+#[derive(Debug)]
+pub struct Streams {
+    pub stdin: Input,
+    pub stdout: Output,
+    pub stderr: Output,
+}
+
+impl Default for Streams {
+    fn default() -> Self {
+        Streams {
+            stdin: Input::Terminal,
+            stdout: Output::Stdout,
+            stderr: Output::Stderr,
+        }
+    }
+}
+
+impl Streams {
+    pub fn try_clone(&self) -> io::Result<Streams> {
+        Ok(Streams {
+            stdin: self.stdin.try_clone()?,
+            stdout: self.stdout.try_clone()?,
+            stderr: self.stderr.try_clone()?,
+        })
+    }
+
+    /// Applies one redirection whose target has already been expanded.
+    ///
+    /// Redirections must be applied left to right, so `> file 2>&1` and
+    /// `2>&1 > file` differ as in POSIX. Returns the message to report when
+    /// the target cannot be used.
+    pub fn apply_redirect(
+        &mut self,
+        fd: u32,
+        kind: RedirectKind,
+        target: &str,
+    ) -> Result<(), String> {
+        match kind {
+            RedirectKind::Read if fd == 0 => {
+                let file = File::open(target).map_err(|e| open_error(target, &e))?;
+                self.stdin = Input::File(file);
+            }
+            RedirectKind::Read => return Err(format!("{}: bad file descriptor", fd)),
+            RedirectKind::Write | RedirectKind::Append => {
+                let file = open_for_writing(target, kind == RedirectKind::Append)?;
+                *self.output_mut(fd)? = Output::File(file);
+            }
+            RedirectKind::WriteBoth | RedirectKind::AppendBoth => {
+                let file = open_for_writing(target, kind == RedirectKind::AppendBoth)?;
+                let copy = file.try_clone().map_err(|e| open_error(target, &e))?;
+                self.stdout = Output::File(file);
+                self.stderr = Output::File(copy);
+            }
+            RedirectKind::Duplicate => {
+                // Copy the target's binding as it is now, so redirecting the
+                // target afterwards leaves `fd` where the target used to go.
+                let duplicate = match target.parse::<u32>() {
+                    Ok(1) => self.stdout.try_clone(),
+                    Ok(2) => self.stderr.try_clone(),
+                    _ => return Err(format!("{}: bad file descriptor", target)),
+                };
+                let duplicate = duplicate.map_err(|e| format!("{}: {}", target, e))?;
+                *self.output_mut(fd)? = duplicate;
+            }
+        }
+        Ok(())
+    }
+
+    fn output_mut(&mut self, fd: u32) -> Result<&mut Output, String> {
+        match fd {
+            1 => Ok(&mut self.stdout),
+            2 => Ok(&mut self.stderr),
+            _ => Err(format!("{}: bad file descriptor", fd)),
+        }
+    }
+}
+
+fn open_for_writing(path: &str, append: bool) -> Result<File, String> {
+    let mut options = OpenOptions::new();
+    options.create(true);
+    if append {
+        options.append(true);
+    } else {
+        options.write(true).truncate(true);
+    }
+    options.open(path).map_err(|e| open_error(path, &e))
+}
+
+fn open_error(path: &str, e: &io::Error) -> String {
+    format!("{}: {}", path, helpers::describe_io_error(e))
+}
+
+/// Output handles for a builtin, with the shell's own streams resolved.
+///
+/// `Output::Stdout` is the shell's writer. `Output::Stderr` is the real stderr
+/// when the shell inherits its stdio, and otherwise the writer as well.
+pub struct Io<'a> {
+    terminal: &'a mut dyn Write,
+    stderr_terminal: Option<io::Stderr>,
+    stdout: Output,
+    stderr: Output,
+}
+
+impl<'a> Io<'a> {
+    pub fn new(terminal: &'a mut dyn Write, streams: Streams, inherits_stdio: bool) -> Self {
+        Io {
+            terminal,
+            stderr_terminal: inherits_stdio.then(io::stderr),
+            stdout: streams.stdout,
+            stderr: streams.stderr,
+        }
+    }
+
+    pub fn out(&mut self) -> &mut dyn Write {
+        Self::resolve(
+            &mut self.stdout,
+            &mut *self.terminal,
+            &mut self.stderr_terminal,
+        )
+    }
+
+    pub fn err(&mut self) -> &mut dyn Write {
+        Self::resolve(
+            &mut self.stderr,
+            &mut *self.terminal,
+            &mut self.stderr_terminal,
+        )
+    }
+
+    fn resolve<'b>(
+        output: &'b mut Output,
+        terminal: &'b mut dyn Write,
+        stderr_terminal: &'b mut Option<io::Stderr>,
+    ) -> &'b mut dyn Write {
+        match (output, stderr_terminal) {
+            (Output::Stderr, Some(stderr)) => stderr,
+            (Output::Stdout | Output::Stderr, _) => terminal,
+            (Output::File(file), _) => file,
+        }
+    }
+}
+
+/// Creates an OS pipe, returning its `(read, write)` ends with close-on-exec set.
+///
+/// The flag is set as the pipe is created, so no fork can slip in before it
+/// and leak the ends into a child.
+pub fn pipe() -> io::Result<(File, File)> {
+    let mut fds = [0; 2];
+    // SAFETY: `fds` has room for the two descriptors `pipe2` writes.
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: `pipe2` succeeded, so both descriptors are open and owned by nobody else.
+    Ok(unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) })
+}
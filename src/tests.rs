@@ -1,5 +1,122 @@
 use super::*;
+use std::env;
+use std::ffi::OsString;
+use std::fs::File;
 use std::io::Cursor;
+use std::os::fd::{AsRawFd, RawFd};
+use std::path::PathBuf;
+
+/// Sets `PATH` to the system directories, restoring the previous value on
+/// drop so tests don't leak it into each other.
+struct PathGuard(Option<OsString>);
+
+impl PathGuard {
+    fn system() -> Self {
+        let saved = env::var_os("PATH");
+        env::set_var("PATH", "/bin:/usr/bin");
+        PathGuard(saved)
+    }
+}
+
+impl Drop for PathGuard {
+    fn drop(&mut self) {
+        match &self.0 {
+            Some(path) => env::set_var("PATH", path),
+            None => env::remove_var("PATH"),
+        }
+    }
+}
+
+/// Restores the working directory on drop so later tests don't start in a removed temp dir.
+struct CwdGuard(PathBuf);
+
+impl CwdGuard {
+    fn new() -> Self {
+        CwdGuard(env::current_dir().expect("Failed to read current dir"))
+    }
+}
+
+impl Drop for CwdGuard {
+    fn drop(&mut self) {
+        let _ = env::set_current_dir(&self.0);
+    }
+}
+
+/// Runs `input` through the REPL, returning its status and output.
+fn run(input: &str) -> (i32, String) {
+    let _path = PathGuard::system();
+    let mut output = Vec::new();
+    let status = run_repl(Cursor::new(input.to_string()), &mut output).expect("Failed to run REPL");
+    (status, String::from_utf8(output).unwrap())
+}
+
+/// Runs `script` in a non-interactive shell with `positional` as `$1`...,
+/// returning its status and output.
+fn run_script(script: &str, positional: &[&str]) -> (i32, String) {
+    let _path = PathGuard::system();
+    let positional = positional.iter().map(|arg| arg.to_string()).collect();
+    let mut output = Vec::new();
+    let status = Shell::new()
+        .interactive(false)
+        .arguments("script", positional)
+        .run_string(script, &mut output)
+        .expect("Failed to run script");
+    (status, String::from_utf8(output).unwrap())
+}
+
+/// Points fds 1 and 2 back at what they were before on drop, so a failing
+/// test still leaves the test harness with its own stdout and stderr.
+struct StdioGuard([RawFd; 2]);
+
+impl StdioGuard {
+    /// Points fds 1 and 2 at `stdout` and `stderr` until the guard is dropped.
+    fn redirect(stdout: &File, stderr: &File) -> Self {
+        // SAFETY: all descriptors involved are open; the duplicates of fds 1
+        // and 2 are owned by the guard.
+        unsafe {
+            let saved = [libc::dup(1), libc::dup(2)];
+            libc::dup2(stdout.as_raw_fd(), 1);
+            libc::dup2(stderr.as_raw_fd(), 2);
+            StdioGuard(saved)
+        }
+    }
+}
+
+impl Drop for StdioGuard {
+    fn drop(&mut self) {
+        let _ = io::stdout().flush();
+        for (fd, saved) in [1, 2].into_iter().zip(self.0) {
+            // SAFETY: `saved` was duplicated from `fd` and is owned by the guard.
+            unsafe {
+                libc::dup2(saved, fd);
+                libc::close(saved);
+            }
+        }
+    }
+}
+
+/// Runs `script` in a shell that inherits the process's stdio, as the
+/// binary does, returning its status and what it wrote to fds 1 and 2.
+fn run_with_inherited_stdio(script: &str) -> (i32, String, String) {
+    let _path = PathGuard::system();
+    let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let stdout_path = temp_dir.path().join("stdout");
+    let stderr_path = temp_dir.path().join("stderr");
+    let stdout = File::create(&stdout_path).expect("Failed to create stdout file");
+    let stderr = File::create(&stderr_path).expect("Failed to create stderr file");
+
+    let status = {
+        let _stdio = StdioGuard::redirect(&stdout, &stderr);
+        Shell::new()
+            .inherit_stdio(true)
+            .interactive(false)
+            .run_string(script, io::stdout())
+            .expect("Failed to run script")
+    };
+
+    let read = |path| std::fs::read_to_string(path).expect("Failed to read output");
+    (status, read(&stdout_path), read(&stderr_path))
+}
 
 #[cfg(test)]
 mod prompt_tests {
@@ -11,7 +128,7 @@ mod prompt_tests {
 
         let result = run_repl(input, &mut output);
         assert!(result.is_ok());
-        assert_eq!(result.unwrap(), 0);
+        assert_eq!(result.unwrap(), 127);
 
         let output_str = String::from_utf8(output).unwrap();
         assert!(output_str.contains("$ "));
@@ -51,17 +168,65 @@ mod exit_command_tests {
     }
 
     #[test]
-    fn test_repl_rejects_exit_with_non_zero_arg() {
+    fn test_repl_exits_with_given_status() {
         let input = Cursor::new("exit 42\nexit\n");
         let mut output = Vec::new();
 
         let result = run_repl(input, &mut output);
         assert!(result.is_ok());
-        assert_eq!(result.unwrap(), 0);
+        assert_eq!(result.unwrap(), 42);
 
         let output_str = String::from_utf8(output).unwrap();
         assert!(output_str.contains("$ "));
-        assert!(output_str.contains("exit 42: command not found"));
+        assert!(!output_str.contains("command not found"));
+    }
+
+    #[test]
+    fn test_repl_exit_defaults_to_last_status() {
+        std::env::set_var("PATH", "/bin:/usr/bin");
+        let input = Cursor::new("false\nexit\n");
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 1);
+    }
+
+    #[test]
+    fn test_repl_exit_truncates_status_to_eight_bits() {
+        let input = Cursor::new("exit 257\n");
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 1);
+    }
+
+    #[test]
+    fn test_repl_rejects_non_numeric_exit_argument() {
+        let input = Cursor::new("exit abc\n");
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 2);
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.contains("exit: abc: numeric argument required"));
+    }
+
+    #[test]
+    fn test_repl_exit_with_too_many_arguments_keeps_running() {
+        let input = Cursor::new("exit 1 2\necho still here\nexit\n");
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0);
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.contains("exit: too many arguments"));
+        assert!(output_str.contains("still here"));
     }
 }
 
@@ -110,7 +275,7 @@ mod echo_command_tests {
 
         let result = run_repl(input, &mut output);
         assert!(result.is_ok());
-        assert_eq!(result.unwrap(), 0);
+        assert_eq!(result.unwrap(), 1);
 
         let output_str = String::from_utf8(output).unwrap();
         assert!(output_str.contains("$ "));
@@ -278,7 +443,7 @@ mod type_command_tests {
 
         let result = run_repl(input, &mut output);
         assert!(result.is_ok());
-        assert_eq!(result.unwrap(), 0);
+        assert_eq!(result.unwrap(), 1);
 
         let output_str = String::from_utf8(output).unwrap();
         assert!(output_str.contains("$ "));
@@ -292,7 +457,7 @@ mod type_command_tests {
 
         let result = run_repl(input, &mut output);
         assert!(result.is_ok());
-        assert_eq!(result.unwrap(), 0);
+        assert_eq!(result.unwrap(), 1);
 
         let output_str = String::from_utf8(output).unwrap();
         assert!(output_str.contains("$ "));
@@ -342,7 +507,7 @@ mod type_path_scan_tests {
 
         let result = run_repl(input, &mut output);
         assert!(result.is_ok());
-        assert_eq!(result.unwrap(), 0);
+        assert_eq!(result.unwrap(), 1);
 
         let output_str = String::from_utf8(output).unwrap();
         assert!(output_str.contains("$ "));
@@ -393,7 +558,7 @@ mod executable_tests {
 
         let result = run_repl(input, &mut output);
         assert!(result.is_ok());
-        assert_eq!(result.unwrap(), 0);
+        assert_eq!(result.unwrap(), 127);
 
         let output_str = String::from_utf8(output).unwrap();
         assert!(output_str.contains("$ "));
@@ -483,6 +648,7 @@ mod cd_command_tests {
 
     #[test]
     fn test_repl_handles_cd_absolute_path() {
+        let _cwd = CwdGuard::new();
         let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
         let temp_path = temp_dir.path().to_str().unwrap();
 
@@ -500,6 +666,7 @@ mod cd_command_tests {
 
     #[test]
     fn test_repl_handles_cd_relative_path() {
+        let _cwd = CwdGuard::new();
         let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
         let subdir = temp_dir.path().join("subdir");
         fs::create_dir(&subdir).expect("Failed to create subdir");
@@ -523,16 +690,18 @@ mod cd_command_tests {
 
     #[test]
     fn test_repl_handles_cd_no_args() {
-        let input = Cursor::new("cd\nexit\n");
+        let _cwd = CwdGuard::new();
+        let home_dir = env::var("HOME").expect("HOME not set");
+        let input = Cursor::new("cd\npwd\nunset HOME\ncd\nexit\n");
         let mut output = Vec::new();
 
         let result = run_repl(input, &mut output);
         assert!(result.is_ok());
-        assert_eq!(result.unwrap(), 0);
+        assert_eq!(result.unwrap(), 1);
 
         let output_str = String::from_utf8(output).unwrap();
-        assert!(output_str.contains("$ "));
-        assert!(output_str.contains("cd: no arguments provided"));
+        assert!(output_str.contains(&format!("$ {}\n", home_dir)));
+        assert!(output_str.contains("cd: HOME not set"));
     }
 
     #[test]
@@ -542,7 +711,7 @@ mod cd_command_tests {
 
         let result = run_repl(input, &mut output);
         assert!(result.is_ok());
-        assert_eq!(result.unwrap(), 0);
+        assert_eq!(result.unwrap(), 1);
 
         let output_str = String::from_utf8(output).unwrap();
         assert!(output_str.contains("$ "));
@@ -556,7 +725,7 @@ mod cd_command_tests {
 
         let result = run_repl(input, &mut output);
         assert!(result.is_ok());
-        assert_eq!(result.unwrap(), 0);
+        assert_eq!(result.unwrap(), 1);
 
         let output_str = String::from_utf8(output).unwrap();
         assert!(output_str.contains("$ "));
@@ -565,6 +734,7 @@ mod cd_command_tests {
 
     #[test]
     fn test_repl_handles_cd_home_directory() {
+        let _cwd = CwdGuard::new();
         let home_dir = env::var("HOME").expect("HOME not set");
         let input = Cursor::new("cd ~\npwd\nexit\n");
         let mut output = Vec::new();
@@ -590,7 +760,7 @@ mod parsing_tests {
 
         let result = run_repl(input, &mut output);
         assert!(result.is_ok());
-        assert_eq!(result.unwrap(), 0);
+        assert_eq!(result.unwrap(), 2);
 
         let output_str = String::from_utf8(output).unwrap();
         assert!(output_str.contains("$ "));
@@ -620,7 +790,7 @@ mod parsing_tests {
 
         let result = run_repl(input, &mut output);
         assert!(result.is_ok());
-        assert_eq!(result.unwrap(), 0);
+        assert_eq!(result.unwrap(), 2);
 
         let output_str = String::from_utf8(output).unwrap();
         assert!(output_str.contains("$ "));
@@ -634,10 +804,2585 @@ mod parsing_tests {
 
         let result = run_repl(input, &mut output);
         assert!(result.is_ok());
-        assert_eq!(result.unwrap(), 0);
+        assert_eq!(result.unwrap(), 2);
 
         let output_str = String::from_utf8(output).unwrap();
         assert!(output_str.contains("$ "));
         assert!(output_str.contains("parse error: invalid escape sequence"));
     }
 }
+
+#[cfg(test)]
+mod pipeline_tests {
+    use super::*;
+
+    #[test]
+    fn test_repl_cd_in_pipeline_stage_leaves_shell_directory() {
+        let _cwd = CwdGuard::new();
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let dir = temp_dir.path().canonicalize().unwrap();
+
+        let (status, output) = run_script(
+            &format!("cd {}\necho x | cd /\npwd\necho $PWD\n", dir.display()),
+            &[],
+        );
+        assert_eq!(status, 0);
+        assert_eq!(output, format!("{0}\n{0}\n", dir.display()));
+        assert_eq!(env::current_dir().unwrap(), dir);
+    }
+
+    #[test]
+    fn test_repl_pipes_builtin_into_external() {
+        std::env::set_var("PATH", "/bin:/usr/bin");
+        let input = Cursor::new("echo hello world | tr a-z A-Z\nexit\n");
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0);
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.contains("HELLO WORLD"));
+        assert!(!output_str.contains("hello world"));
+    }
+
+    #[test]
+    fn test_repl_runs_multi_stage_external_pipeline() {
+        std::env::set_var("PATH", "/bin:/usr/bin");
+        let input = Cursor::new("printf 'pear\\napple\\nfig\\n' | sort | head -n 1\nexit\n");
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0);
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.contains("apple"));
+        assert!(!output_str.contains("pear"));
+        assert!(!output_str.contains("fig"));
+    }
+
+    #[test]
+    fn test_repl_pipeline_ending_in_builtin() {
+        std::env::set_var("PATH", "/bin:/usr/bin");
+        let input = Cursor::new("ls / | echo done\nexit\n");
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0);
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.contains("done"));
+        assert!(!output_str.contains("usr"));
+    }
+
+    #[test]
+    fn test_repl_pipeline_status_is_last_stage() {
+        std::env::set_var("PATH", "/bin:/usr/bin");
+        let input =
+            Cursor::new("true | false\necho status $?\nfalse | true\necho status $?\nexit\n");
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0);
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.contains("status 1\n$ "));
+        assert!(output_str.contains("status 0\n$ "));
+    }
+
+    #[test]
+    fn test_repl_pipeline_state_comes_from_last_stage_after_builtin() {
+        let (_, output) = run("echo a | sh -c 'kill -TERM $$'; echo status $?\n");
+        assert!(output.contains("Terminated\nstatus 143\n"));
+    }
+
+    #[test]
+    fn test_repl_pipeline_stages_expand_outside_the_shell() {
+        let (_, output) = run_script(
+            "i=0\n\
+             echo ${x:=v} | cat\n\
+             echo $((i++)) | cat\n\
+             a=1 | cat\n\
+             echo \"[$x] $i [$a]\"\n\
+             echo ${y:=w}; echo $((i++)) >/dev/null; echo \"$y $i\"\n",
+            &[],
+        );
+        assert_eq!(output, "v\n0\n[] 0 []\nw\nw 1\n");
+    }
+
+    #[test]
+    fn test_repl_quoted_pipe_is_literal() {
+        let input = Cursor::new("echo 'a | b' \"c|d\"\nexit\n");
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0);
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.contains("a | b c|d"));
+    }
+
+    #[test]
+    fn test_repl_rejects_empty_pipeline_stage() {
+        // An unfinished pipeline continues on the next line, so it is only
+        // an error at the end of input.
+        let input = Cursor::new("| echo hi\necho hi |\n");
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 2);
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.contains("parse error: unexpected '|'"));
+        assert!(output_str.contains("parse error: unexpected end of pipeline"));
+    }
+}
+
+#[cfg(test)]
+mod redirection_tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_repl_redirects_builtin_stdout_to_file() {
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let file = temp_dir.path().join("out.txt");
+
+        let input = Cursor::new(format!(
+            "echo first > {0}\necho second >> {0}\nexit\n",
+            file.display()
+        ));
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0);
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(!output_str.contains("first"));
+        assert_eq!(fs::read_to_string(&file).unwrap(), "first\nsecond\n");
+    }
+
+    #[test]
+    fn test_repl_truncates_on_plain_redirect() {
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let file = temp_dir.path().join("out.txt");
+        fs::write(&file, "old contents\n").expect("Failed to write file");
+
+        let input = Cursor::new(format!("echo new > {}\nexit\n", file.display()));
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+
+        assert_eq!(fs::read_to_string(&file).unwrap(), "new\n");
+    }
+
+    #[test]
+    fn test_repl_redirects_external_stdin_and_stdout() {
+        std::env::set_var("PATH", "/bin:/usr/bin");
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let source = temp_dir.path().join("in.txt");
+        let dest = temp_dir.path().join("out.txt");
+        fs::write(&source, "shout\n").expect("Failed to write file");
+
+        let input = Cursor::new(format!(
+            "tr a-z A-Z < {} > {}\nexit\n",
+            source.display(),
+            dest.display()
+        ));
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(!output_str.contains("SHOUT"));
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "SHOUT\n");
+    }
+
+    #[test]
+    fn test_repl_redirects_stderr() {
+        std::env::set_var("PATH", "/bin:/usr/bin");
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let errors = temp_dir.path().join("err.txt");
+
+        let input = Cursor::new(format!(
+            "cd /nonexistent 2> {0}\nls /nonexistent 2>> {0}\nexit\n",
+            errors.display()
+        ));
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(!output_str.contains("nonexistent"));
+        let contents = fs::read_to_string(&errors).unwrap();
+        assert!(contents.starts_with("cd: /nonexistent: No such file or directory\n"));
+        assert_eq!(contents.lines().count(), 2);
+    }
+
+    #[test]
+    fn test_repl_duplicates_stderr_into_pipe() {
+        std::env::set_var("PATH", "/bin:/usr/bin");
+        let input = Cursor::new("ls /nonexistent 2>&1 | tr a-z A-Z\nexit\n");
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.contains("/NONEXISTENT"));
+    }
+
+    #[test]
+    fn test_repl_duplicates_stdout_binding_before_redirecting_it() {
+        let (status, stdout, stderr) = run_with_inherited_stdio("ls /nonexistent 2>&1 >/dev/null");
+        assert_ne!(status, 0);
+        assert!(stdout.contains("/nonexistent"));
+        assert_eq!(stderr, "");
+
+        let (_, stdout, stderr) = run_with_inherited_stdio("cd /nonexistent 2>&1 >/dev/null");
+        assert_eq!(stdout, "cd: /nonexistent: No such file or directory\n");
+        assert_eq!(stderr, "");
+    }
+
+    #[test]
+    fn test_repl_duplicates_stdout_binding_after_redirecting_it() {
+        let (status, stdout, stderr) = run_with_inherited_stdio("ls /nonexistent >/dev/null 2>&1");
+        assert_ne!(status, 0);
+        assert_eq!(stdout, "");
+        assert_eq!(stderr, "");
+
+        let (_, stdout, stderr) = run_with_inherited_stdio("cd /nonexistent >/dev/null 2>&1");
+        assert_eq!(stdout, "");
+        assert_eq!(stderr, "");
+    }
+
+    #[test]
+    fn test_repl_duplicates_stderr_binding_onto_stdout() {
+        let (_, stdout, stderr) = run_with_inherited_stdio("ls /nonexistent 2>&1; echo hi >&2");
+        assert!(stdout.contains("/nonexistent"));
+        assert_eq!(stderr, "hi\n");
+    }
+
+    #[test]
+    fn test_repl_redirects_both_streams() {
+        std::env::set_var("PATH", "/bin:/usr/bin");
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let file = temp_dir.path().join("all.txt");
+
+        let input = Cursor::new(format!("ls / /nonexistent &> {}\nexit\n", file.display()));
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(!output_str.contains("nonexistent"));
+        let contents = fs::read_to_string(&file).unwrap();
+        assert!(contents.contains("/nonexistent"));
+        assert!(contents.contains("usr"));
+    }
+
+    #[test]
+    fn test_repl_reports_unwritable_redirect_target() {
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let missing = temp_dir.path().join("missing").join("out.txt");
+
+        let input = Cursor::new(format!(
+            "echo hi > {}\necho status $?\necho hi > {}\nexit\n",
+            missing.display(),
+            temp_dir.path().display()
+        ));
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.contains(&format!("{}: No such file or directory", missing.display())));
+        assert!(output_str.contains("status 1"));
+        assert!(output_str.contains(&format!("{}: Is a directory", temp_dir.path().display())));
+    }
+
+    #[test]
+    fn test_repl_reports_ambiguous_redirect_for_empty_target() {
+        let (status, output) = run_script("echo x >$nothing\necho status $?\n", &[]);
+        assert_eq!(status, 0);
+        assert_eq!(output, "$nothing: ambiguous redirect\nstatus 1\n");
+    }
+
+    #[test]
+    fn test_repl_reports_ambiguous_redirect_for_split_target() {
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let dir = temp_dir.path().display();
+
+        let (_, output) = run_script(
+            &format!(
+                "f='{}/a b'\necho x > $f\necho status $?\necho y > \"$f\"\n",
+                dir
+            ),
+            &[],
+        );
+        assert_eq!(output, "$f: ambiguous redirect\nstatus 1\n");
+        assert!(!temp_dir.path().join("a").exists());
+        assert_eq!(
+            fs::read_to_string(temp_dir.path().join("a b")).unwrap(),
+            "y\n"
+        );
+    }
+
+    #[test]
+    fn test_repl_rejects_missing_redirect_target() {
+        let input = Cursor::new("echo hi >\nexit\n");
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.contains("parse error: missing redirection target"));
+    }
+}
+
+#[cfg(test)]
+mod streaming_output_tests {
+    use super::*;
+
+    #[test]
+    fn test_repl_keeps_stdout_and_stderr_interleaved() {
+        std::env::set_var("PATH", "/bin:/usr/bin");
+        let input = Cursor::new("sh -c 'echo one; echo two >&2; echo three'\nexit\n");
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0);
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.contains("one\ntwo\nthree\n"));
+    }
+
+    #[test]
+    fn test_repl_starts_prompt_on_new_line_after_partial_output() {
+        std::env::set_var("PATH", "/bin:/usr/bin");
+        let input = Cursor::new("printf no-newline\nexit\n");
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0);
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.contains("no-newline\n$ "));
+    }
+
+    #[test]
+    fn test_repl_streams_large_output_without_blocking() {
+        std::env::set_var("PATH", "/bin:/usr/bin");
+        let input = Cursor::new("seq 1 50000\nexit\n");
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0);
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.contains("\n49999\n50000\n$ "));
+    }
+
+    #[test]
+    fn test_repl_does_not_wait_for_output_of_grandchildren() {
+        let start = std::time::Instant::now();
+        let (_, output) = run("sh -c 'sleep 3 & echo hi'\necho next\n");
+        assert!(start.elapsed() < std::time::Duration::from_secs(2));
+        assert!(output.contains("hi\n$ next\n"));
+    }
+
+    #[test]
+    fn test_repl_relays_all_output_written_before_exit() {
+        let (_, output) = run("sh -c 'sleep 3 & seq 1 100000'\necho next\n");
+        assert!(output.contains("\n99999\n100000\n$ next\n"));
+        assert_eq!(output.matches('\n').count(), 100002);
+    }
+
+    #[test]
+    fn test_repl_children_do_not_read_repl_input() {
+        std::env::set_var("PATH", "/bin:/usr/bin");
+        let input = Cursor::new("cat\necho after cat\nexit\n");
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0);
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.contains("after cat"));
+    }
+
+    #[test]
+    fn test_repl_children_inherit_separate_stdout_and_stderr() {
+        let (status, stdout, stderr) = run_with_inherited_stdio("ls /nonexistent; echo done");
+        assert_eq!(status, 0);
+        assert_eq!(stdout, "done\n");
+        assert!(stderr.contains("/nonexistent"));
+    }
+
+    #[test]
+    fn test_repl_writes_builtin_errors_to_inherited_stderr() {
+        let (status, stdout, stderr) = run_with_inherited_stdio("cd /nonexistent");
+        assert_eq!(status, 1);
+        assert_eq!(stdout, "");
+        assert_eq!(stderr, "cd: /nonexistent: No such file or directory\n");
+    }
+
+    #[test]
+    fn test_repl_writes_shell_errors_to_stage_stderr() {
+        let (status, stdout, stderr) = run_with_inherited_stdio(
+            "nosuchcmd\nnosuchcmd 2>/dev/null\necho $((1/0)) 2>/dev/null\n(( 1/0 ))\necho x >$nothing\n",
+        );
+        assert_eq!(status, 1);
+        assert_eq!(stdout, "");
+        let lines: Vec<&str> = stderr.lines().collect();
+        // Words are expanded before redirections apply, as in other shells.
+        assert_eq!(lines.len(), 4, "{}", stderr);
+        assert_eq!(lines[0], "nosuchcmd: not found");
+        assert_eq!(lines[3], "$nothing: ambiguous redirect");
+
+        let (status, stdout, stderr) = run_with_inherited_stdio("echo a |;");
+        assert_eq!(status, 2);
+        assert_eq!(stdout, "");
+        assert!(stderr.contains("parse error"));
+
+        let (_, output) = run("nosuchcmd 2>&1 >/dev/null | tr a-z A-Z\n");
+        assert!(output.contains("NOSUCHCMD: NOT FOUND\n"));
+    }
+
+    #[test]
+    fn test_repl_redirects_builtin_output_to_inherited_stderr() {
+        let (status, stdout, stderr) = run_with_inherited_stdio("echo hi >&2");
+        assert_eq!(status, 0);
+        assert_eq!(stdout, "");
+        assert_eq!(stderr, "hi\n");
+    }
+}
+
+#[cfg(test)]
+mod exit_status_tests {
+    use super::*;
+
+    #[test]
+    fn test_repl_expands_last_status() {
+        std::env::set_var("PATH", "/bin:/usr/bin");
+        let input = Cursor::new("sh -c 'exit 3'\necho \"status $?\"\necho again $?\nexit\n");
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0);
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.contains("status 3"));
+        assert!(output_str.contains("again 0"));
+    }
+
+    #[test]
+    fn test_repl_reports_signal_termination_as_128_plus_signal() {
+        std::env::set_var("PATH", "/bin:/usr/bin");
+        let input = Cursor::new("sh -c 'kill -TERM $$'\necho status $?\nexit\n");
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0);
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.contains("status 143"));
+    }
+
+    #[test]
+    fn test_repl_sets_status_for_missing_command_and_syntax_error() {
+        let input = Cursor::new("no_such_command\necho $?\necho a )\necho $?\nexit\n");
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0);
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.contains("127\n"));
+        assert!(output_str.contains("2\n"));
+    }
+}
+
+#[cfg(test)]
+mod command_list_tests {
+    use super::*;
+
+    #[test]
+    fn test_repl_runs_sequential_list() {
+        let input = Cursor::new("echo first; echo second ;echo third\nexit\n");
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0);
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.contains("first\nsecond\nthird\n"));
+    }
+
+    #[test]
+    fn test_repl_short_circuits_and_or_chains() {
+        std::env::set_var("PATH", "/bin:/usr/bin");
+        let input = Cursor::new(
+            "cd /nonexistent && echo unreachable || echo recovered\ntrue || echo skipped && echo chained\nexit\n",
+        );
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0);
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(!output_str.contains("unreachable"));
+        assert!(output_str.contains("recovered"));
+        assert!(!output_str.contains("skipped"));
+        assert!(output_str.contains("chained"));
+    }
+
+    #[test]
+    fn test_repl_list_expands_status_of_previous_command() {
+        std::env::set_var("PATH", "/bin:/usr/bin");
+        let input = Cursor::new("false; echo status $?\nfalse || true; echo status $?\nexit\n");
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0);
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.contains("status 1\n"));
+        assert!(output_str.contains("status 0\n"));
+    }
+
+    #[test]
+    fn test_repl_exit_stops_list() {
+        let input = Cursor::new("echo before; exit 3; echo after\n");
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 3);
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.contains("before"));
+        assert!(!output_str.contains("after"));
+    }
+
+    #[test]
+    fn test_repl_quoted_list_operators_are_literal() {
+        let input = Cursor::new("echo 'a; b' \"c && d\" 'e || f'\nexit\n");
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0);
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.contains("a; b c && d e || f"));
+    }
+
+    #[test]
+    fn test_repl_rejects_malformed_lists() {
+        let input = Cursor::new("echo a ; ; echo b\n&& echo a\necho a &&\n");
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 2);
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.contains("parse error: unexpected ';'"));
+        assert!(output_str.contains("parse error: unexpected end of input"));
+        assert!(output_str.contains("parse error: unexpected '&&'"));
+        assert!(!output_str.contains("a\n"));
+    }
+}
+
+#[cfg(test)]
+mod parser_tests {
+    use super::*;
+    use crate::ast::{Command, CompoundCommand, Connector, RedirectKind};
+    use crate::parser::parse;
+
+    #[test]
+    fn test_parse_keeps_source_text_of_jobs() {
+        let list = parse("a  2>err | b && ! c; d &").unwrap();
+        let and_or = &list.items[0].and_or;
+        assert_eq!(and_or.text, "a  2>err | b && ! c");
+        assert_eq!(and_or.first.text, "a  2>err | b");
+        assert_eq!(and_or.rest[0].1.text, "! c");
+        assert_eq!(list.items[1].and_or.text, "d");
+    }
+
+    #[test]
+    fn test_parse_builds_list_of_pipelines() {
+        let list = parse("a 2>err | b && ! c; d &").unwrap();
+        assert_eq!(list.items.len(), 2);
+        assert!(list.items[1].background);
+
+        let and_or = &list.items[0].and_or;
+        assert_eq!(and_or.first.commands.len(), 2);
+        assert_eq!(and_or.rest.len(), 1);
+        assert_eq!(and_or.rest[0].0, Connector::And);
+        assert!(and_or.rest[0].1.negated);
+
+        let Command::Simple(first) = &and_or.first.commands[0] else {
+            panic!("expected a simple command");
+        };
+        assert_eq!(first.words[0].text, "a");
+        assert_eq!(first.redirects[0].fd, 2);
+        assert_eq!(first.redirects[0].kind, RedirectKind::Write);
+        assert_eq!(first.redirects[0].target.text, "err");
+    }
+
+    #[test]
+    fn test_parse_keeps_word_source_text_and_assignments() {
+        let list = parse("FOO=bar BAZ='x y' echo \"$FOO\" A=b").unwrap();
+        let Command::Simple(command) = &list.items[0].and_or.first.commands[0] else {
+            panic!("expected a simple command");
+        };
+        assert_eq!(command.assignments.len(), 2);
+        assert_eq!(command.assignments[1].name, "BAZ");
+        assert_eq!(command.assignments[1].value.text, "'x y'");
+        let words: Vec<&str> = command.words.iter().map(|w| w.text.as_str()).collect();
+        assert_eq!(words, ["echo", "\"$FOO\"", "A=b"]);
+        assert_eq!(command.words[1].span.start, 23);
+    }
+
+    #[test]
+    fn test_parse_control_flow_commands() {
+        let list = parse("if a; then b; elif c\nthen d; else e; fi").unwrap();
+        let Command::Compound(CompoundCommand::If(command), _) =
+            &list.items[0].and_or.first.commands[0]
+        else {
+            panic!("expected an if command");
+        };
+        assert_eq!(command.branches.len(), 2);
+        assert!(command.else_body.is_some());
+
+        let list = parse("for x in a 'b c'; do echo $x; done\nfor y\ndo :; done").unwrap();
+        let Command::Compound(CompoundCommand::For(first), _) =
+            &list.items[0].and_or.first.commands[0]
+        else {
+            panic!("expected a for loop");
+        };
+        assert_eq!(first.name, "x");
+        assert_eq!(first.words.as_ref().unwrap().len(), 2);
+        let Command::Compound(CompoundCommand::For(second), _) =
+            &list.items[1].and_or.first.commands[0]
+        else {
+            panic!("expected a for loop");
+        };
+        assert!(second.words.is_none());
+
+        let list = parse("case $x in (a|b) one;; *) ;;\nesac").unwrap();
+        let Command::Compound(CompoundCommand::Case(command), _) =
+            &list.items[0].and_or.first.commands[0]
+        else {
+            panic!("expected a case command");
+        };
+        assert_eq!(command.items.len(), 2);
+        assert_eq!(command.items[0].patterns.len(), 2);
+        assert!(command.items[1].body.items.is_empty());
+
+        assert!(matches!(
+            parse("until a; do b; done").unwrap().items[0]
+                .and_or
+                .first
+                .commands[0],
+            Command::Compound(CompoundCommand::Until(_), _)
+        ));
+    }
+
+    #[test]
+    fn test_parse_incomplete_control_flow() {
+        for source in [
+            "if a",
+            "if a; then b",
+            "while a; do",
+            "for x in a b",
+            "case x in a) b;;",
+            "echo a \\\n",
+        ] {
+            assert!(parse(source).unwrap_err().incomplete, "{}", source);
+        }
+        for source in [
+            "if a; fi",
+            "for 1 in a; do b; done",
+            "done",
+            "case x in a) b;; ;;",
+        ] {
+            assert!(!parse(source).unwrap_err().incomplete, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_parse_function_definitions() {
+        let list = parse("f() { echo $1; } > out\nfunction g\n{ :; }\nfunction h() (:)").unwrap();
+        let definitions: Vec<_> = list
+            .items
+            .iter()
+            .map(|item| match &item.and_or.first.commands[0] {
+                Command::Function(definition) => definition,
+                _ => panic!("expected a function definition"),
+            })
+            .collect();
+        assert_eq!(definitions[0].name, "f");
+        assert_eq!(definitions[0].text, "f() { echo $1; } > out");
+        assert_eq!(definitions[0].redirects.len(), 1);
+        assert!(matches!(
+            definitions[1].body,
+            CompoundCommand::BraceGroup(_)
+        ));
+        assert!(matches!(definitions[2].body, CompoundCommand::Subshell(_)));
+
+        assert!(parse("f()").unwrap_err().incomplete);
+        assert!(!parse("f() echo").unwrap_err().incomplete);
+        assert!(!parse("function 1 { :; }").unwrap_err().incomplete);
+    }
+
+    #[test]
+    fn test_parse_compound_commands() {
+        let list = parse("{ a; b; } > out\n(c)").unwrap();
+        assert_eq!(list.items.len(), 2);
+        let Command::Compound(CompoundCommand::BraceGroup(body), redirects) =
+            &list.items[0].and_or.first.commands[0]
+        else {
+            panic!("expected a brace group");
+        };
+        assert_eq!(body.items.len(), 2);
+        assert_eq!(redirects.len(), 1);
+        assert!(matches!(
+            list.items[1].and_or.first.commands[0],
+            Command::Compound(CompoundCommand::Subshell(_), _)
+        ));
+    }
+
+    #[test]
+    fn test_parse_errors_report_position() {
+        let error = parse("echo a |").unwrap_err();
+        assert!(error.incomplete);
+        assert_eq!(
+            error.to_string(),
+            "parse error: unexpected end of pipeline (column 9)"
+        );
+
+        let error = parse("echo a\necho b )").unwrap_err();
+        assert!(!error.incomplete);
+        assert_eq!(
+            error.to_string(),
+            "parse error: unexpected ')' (line 2, column 8)"
+        );
+
+        let error = parse("{ echo a").unwrap_err();
+        assert!(error.incomplete);
+        assert!(error.to_string().contains("expected '}'"));
+    }
+
+    #[test]
+    fn test_repl_runs_brace_group_with_redirect() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("out.txt");
+        let input = Cursor::new(format!(
+            "{{ echo one; echo two; }} > {}\nexit\n",
+            file.display()
+        ));
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0);
+
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "one\ntwo\n");
+    }
+
+    #[test]
+    fn test_repl_subshell_changes_do_not_leak() {
+        let dir = tempfile::tempdir().unwrap();
+        let cwd = env::current_dir().unwrap();
+        let input = Cursor::new(format!(
+            "(cd {}; exit 3); echo status $?\npwd\nexit\n",
+            dir.path().display()
+        ));
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0);
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.contains("status 3\n"));
+        assert!(output_str.contains(&format!("{}\n", cwd.display())));
+    }
+
+    #[test]
+    fn test_repl_negation_and_comments() {
+        let input = Cursor::new(
+            "! echo a # trailing comment\necho status $?\n# only a comment\n! exit 1\n",
+        );
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 1);
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.contains("a\n"));
+        assert!(output_str.contains("status 1\n"));
+        assert!(!output_str.contains("trailing"));
+    }
+}
+
+#[cfg(test)]
+mod end_of_input_tests {
+    use super::*;
+
+    #[test]
+    fn test_repl_exits_at_end_of_input() {
+        let input = Cursor::new("echo first\necho second\n");
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0);
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.contains("first\n"));
+        assert!(output_str.contains("second\n"));
+    }
+
+    #[test]
+    fn test_repl_empty_input_exits_successfully() {
+        let input = Cursor::new("");
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0);
+    }
+
+    #[test]
+    fn test_repl_end_of_input_keeps_last_status() {
+        let input = Cursor::new("echo ok\nnonexistent_command_xyz\n");
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 127);
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.contains("nonexistent_command_xyz: not found"));
+    }
+
+    #[test]
+    fn test_repl_runs_last_line_without_newline() {
+        std::env::set_var("PATH", "/bin:/usr/bin");
+        let input = Cursor::new("echo before\nfalse");
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 1);
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.contains("before\n"));
+    }
+
+    #[test]
+    fn test_repl_end_of_input_after_parse_error() {
+        let input = Cursor::new("echo 'unclosed\n");
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 2);
+    }
+}
+
+#[cfg(test)]
+mod script_tests {
+    use super::*;
+
+    fn script_shell() -> Shell {
+        Shell::new().interactive(false).arguments(
+            "script.sh",
+            vec!["one".to_string(), "two words".to_string()],
+        )
+    }
+
+    #[test]
+    fn test_non_interactive_shell_does_not_prompt() {
+        let input = Cursor::new("echo hello\n");
+        let mut output = Vec::new();
+
+        let result = script_shell().run(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0);
+
+        assert_eq!(String::from_utf8(output).unwrap(), "hello\n");
+    }
+
+    #[test]
+    fn test_positional_parameters() {
+        let input = Cursor::new("echo $0 $# [$1] [$2] [$3]\necho all $@\necho star $*\n");
+        let mut output = Vec::new();
+
+        let result = script_shell().run(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0);
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.contains("script.sh 2 [one] [two words] []\n"));
+        assert!(output_str.contains("all one two words\n"));
+        assert!(output_str.contains("star one two words\n"));
+    }
+
+    #[test]
+    fn test_run_string_runs_all_lines() {
+        let mut output = Vec::new();
+
+        let result =
+            script_shell().run_string("echo a; echo $1\necho b\nexit 5\necho c", &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 5);
+
+        assert_eq!(String::from_utf8(output).unwrap(), "a\none\nb\n");
+    }
+
+    #[test]
+    fn test_run_string_returns_last_status() {
+        std::env::set_var("PATH", "/bin:/usr/bin");
+        let mut output = Vec::new();
+
+        let result = script_shell().run_string("true && false", &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 1);
+    }
+
+    #[test]
+    fn test_syntax_error_exits_script() {
+        let input = Cursor::new("echo before\necho a |;\necho after\n");
+        let mut output = Vec::new();
+
+        let result = script_shell().run(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 2);
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.starts_with("before\n"));
+        assert!(output_str.contains("parse error"));
+        assert!(!output_str.contains("after"));
+    }
+
+    #[test]
+    fn test_run_file_skips_shebang_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("script.sh");
+        let mut file = std::fs::File::create(&path).unwrap();
+        writeln!(file, "#!/usr/bin/env shell\necho from script $1\nexit 3").unwrap();
+        let mut output = Vec::new();
+
+        let result = script_shell().run_file(&path, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 3);
+
+        assert_eq!(String::from_utf8(output).unwrap(), "from script one\n");
+    }
+
+    #[test]
+    fn test_run_file_reports_missing_script() {
+        let mut output = Vec::new();
+
+        let result =
+            script_shell().run_file(std::path::Path::new("/nonexistent/script.sh"), &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 127);
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.contains("/nonexistent/script.sh: No such file or directory"));
+    }
+}
+
+#[cfg(test)]
+mod variable_tests {
+    use super::*;
+
+    #[test]
+    fn test_repl_assignment_sets_shell_variable() {
+        let input =
+            Cursor::new("GREETING='hello world'\necho $GREETING\nA=1 B=2\necho $A$B\nexit\n");
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0);
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.contains("hello world\n"));
+        assert!(output_str.contains("12\n"));
+        assert!(env::var("GREETING").is_err());
+    }
+
+    #[test]
+    fn test_repl_only_exported_variables_reach_children() {
+        std::env::set_var("PATH", "/bin:/usr/bin");
+        let input = Cursor::new(
+            "LOCAL_VAR=local\nsh -c 'echo child [$LOCAL_VAR]'\nexport LOCAL_VAR\nsh -c 'echo child [$LOCAL_VAR]'\nexport NEW_VAR=new\nsh -c 'echo child [$NEW_VAR]'\nexit\n",
+        );
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0);
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.contains("child []\n"));
+        assert!(output_str.contains("child [local]\n"));
+        assert!(output_str.contains("child [new]\n"));
+    }
+
+    #[test]
+    fn test_repl_prefix_assignment_is_temporary() {
+        std::env::set_var("PATH", "/bin:/usr/bin");
+        let input =
+            Cursor::new("PREFIXED=1 sh -c 'echo child $PREFIXED'\necho shell [$PREFIXED]\nexit\n");
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0);
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.contains("child 1\n"));
+        assert!(output_str.contains("shell []\n"));
+    }
+
+    #[test]
+    fn test_repl_unset_removes_variable() {
+        std::env::set_var("PATH", "/bin:/usr/bin");
+        let input = Cursor::new(
+            "export GONE=here\nunset GONE\necho shell [$GONE]\nsh -c 'echo child [$GONE]'\nexit\n",
+        );
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0);
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.contains("shell []\n"));
+        assert!(output_str.contains("child []\n"));
+    }
+
+    #[test]
+    fn test_repl_readonly_variable_cannot_change() {
+        let input = Cursor::new(
+            "readonly FIXED=1\nFIXED=2\necho status $?\nunset FIXED\necho $FIXED\nreadonly -p\n",
+        );
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0);
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.contains("FIXED: readonly variable"));
+        assert!(output_str.contains("status 1\n"));
+        assert!(output_str.contains("unset: FIXED: cannot unset: readonly variable"));
+        assert!(output_str.contains("1\n"));
+        assert!(output_str.contains("readonly FIXED='1'\n"));
+    }
+
+    #[test]
+    fn test_repl_export_lists_and_validates() {
+        let input = Cursor::new("export LISTED=\"it's\"\nexport -p\nexport 1BAD=x\n");
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 1);
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.contains("export LISTED='it'\\''s'\n"));
+        assert!(output_str.contains("export: `1BAD=x': not a valid identifier"));
+    }
+
+    #[test]
+    fn test_repl_subshell_variables_do_not_leak() {
+        let input =
+            Cursor::new("INNER=outer\n(INNER=inner; echo in $INNER)\necho out $INNER\nexit\n");
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0);
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.contains("in inner\n"));
+        assert!(output_str.contains("out outer\n"));
+    }
+}
+
+#[cfg(test)]
+mod parameter_expansion_tests {
+    use super::*;
+    use crate::pattern;
+
+    #[test]
+    fn test_repl_matches_patterns() {
+        assert!(pattern::matches("*.txt", "notes.txt"));
+        assert!(!pattern::matches("*.txt", "notes.txt.bak"));
+        assert!(pattern::matches("a?c", "abc"));
+        assert!(pattern::matches("[a-c]x[!0-9]", "bxy"));
+        assert!(!pattern::matches("[a-c]x[!0-9]", "bx1"));
+        assert!(pattern::matches("[[:digit:]]*", "42abc"));
+        assert!(pattern::matches("\\*", "*"));
+        assert!(!pattern::matches("\\*", "x"));
+        assert!(pattern::matches("[]]", "]"));
+        assert!(pattern::matches("*a*b*", "xxaxxbxx"));
+    }
+
+    #[test]
+    fn test_repl_expands_braced_and_positional_parameters() {
+        let (status, output) = run_script(
+            "NAME=world; echo ${NAME}wide $1 ${2} ${10} ${#} $0",
+            &["a", "b", "c", "d", "e", "f", "g", "h", "i", "tenth"],
+        );
+        assert_eq!(status, 0);
+        assert_eq!(output, "worldwide a b tenth 10 script\n");
+    }
+
+    #[test]
+    fn test_repl_expands_default_assign_and_alternate_values() {
+        let (status, output) = run_script(
+            "EMPTY=; echo [${UNSET-a}] [${EMPTY-b}] [${EMPTY:-c}] [${EMPTY+d}] [${UNSET:+e}]\necho ${NEW:=assigned} $NEW\necho \"${UNSET:-two  spaces}\"",
+            &[],
+        );
+        assert_eq!(status, 0);
+        assert_eq!(
+            output,
+            "[a] [] [c] [d] []\nassigned assigned\ntwo  spaces\n"
+        );
+    }
+
+    #[test]
+    fn test_repl_required_parameter_error_exits_script() {
+        let (status, output) =
+            run_script("echo before\necho ${MISSING:?must be set}\necho after", &[]);
+        assert_eq!(status, 1);
+        assert_eq!(output, "before\nMISSING: must be set\n");
+
+        let (_, output) = run_script("EMPTY=\necho ${EMPTY:?}", &[]);
+        assert_eq!(output, "EMPTY: parameter null or not set\n");
+    }
+
+    #[test]
+    fn test_repl_expands_length_and_pattern_removal() {
+        let (status, output) = run_script(
+            "F=/usr/lib/archive.tar.gz\necho ${#F}\necho ${F#*/} ${F##*/}\necho ${F%.*} ${F%%.*}\necho ${F%\"*\"} ${F#/usr}",
+            &[],
+        );
+        assert_eq!(status, 0);
+        assert_eq!(
+            output,
+            "23\nusr/lib/archive.tar.gz archive.tar.gz\n/usr/lib/archive.tar /usr/lib/archive\n/usr/lib/archive.tar.gz /lib/archive.tar.gz\n"
+        );
+    }
+
+    #[test]
+    fn test_repl_expands_special_parameters() {
+        let (status, output) = run_script("echo $$ [$!] $# $@ $*", &["x", "y"]);
+        assert_eq!(status, 0);
+        assert_eq!(output, format!("{} [] 2 x y x y\n", std::process::id()));
+    }
+
+    #[test]
+    fn test_repl_bad_substitution_aborts_command() {
+        let input = Cursor::new("echo ${%x}\necho status $?\necho ${A B}\n");
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 1);
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.contains("${%x}: bad substitution"));
+        assert!(output_str.contains("status 1\n"));
+    }
+}
+
+#[cfg(test)]
+mod command_substitution_tests {
+    use super::*;
+
+    #[test]
+    fn test_repl_dollar_paren_substitution() {
+        let input = Cursor::new("echo [$(echo hello)] \"[$(echo quoted)]\"\nexit\n");
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0);
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.contains("[hello] [quoted]\n"));
+    }
+
+    #[test]
+    fn test_repl_backquote_and_nested_substitution() {
+        let input =
+            Cursor::new("echo `echo back` $(echo $(echo nested)) `echo \\`echo inner\\``\nexit\n");
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0);
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.contains("back nested inner\n"));
+    }
+
+    #[test]
+    fn test_repl_substitution_containing_case_patterns() {
+        let (status, output) = run_script(
+            "echo $(case x in x) echo yes;; esac) \"$(case y in (y) echo paren;; esac)\"\n\
+             echo $(case z in\n  z) echo split\nesac)\n",
+            &[],
+        );
+        assert_eq!(status, 0);
+        assert_eq!(output, "yes paren\nsplit\n");
+    }
+
+    #[test]
+    fn test_repl_substitution_finds_case_patterns_in_nested_commands() {
+        let (_, output) = run_script(
+            "echo $(if true; then case a in a) echo if;; esac; fi)\n\
+             echo $(f() { case b in b|c) echo function;; esac; }; f)\n\
+             echo $(echo esac; case c in (c) echo case;; esac)\n",
+            &[],
+        );
+        assert_eq!(output, "if\nfunction\nesac case\n");
+
+        // Each level is scanned once, however deeply substitutions nest.
+        let depth = 40;
+        let script = format!(
+            "echo {}deep{}\n",
+            "$(case x in x) echo ".repeat(depth),
+            ";; esac)".repeat(depth)
+        );
+        let (status, output) = run_script(&script, &[]);
+        assert_eq!(status, 0);
+        assert_eq!(output, "deep\n");
+    }
+
+    #[test]
+    fn test_repl_substitution_strips_trailing_newlines() {
+        std::env::set_var("PATH", "/bin:/usr/bin");
+        let input = Cursor::new("X=\"$(printf 'a\\nb\\n\\n\\n')\"\necho \"[$X]\"\nexit\n");
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0);
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.contains("[a\nb]\n"));
+    }
+
+    #[test]
+    fn test_repl_substitution_runs_in_subshell() {
+        let cwd = env::current_dir().unwrap();
+        let input = Cursor::new(
+            "INNER=outer\necho \"$(cd /; INNER=inner; pwd; echo $INNER)\"\necho $INNER\npwd\nexit\n",
+        );
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0);
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.contains("/\ninner\n"));
+        assert!(output_str.contains("outer\n"));
+        assert!(output_str.contains(&format!("{}\n", cwd.display())));
+    }
+
+    #[test]
+    fn test_repl_assignment_takes_substitution_status() {
+        std::env::set_var("PATH", "/bin:/usr/bin");
+        let input =
+            Cursor::new("X=$(false)\necho status $?\nX=$(true)\necho status $?\nX=$(exit 7)\n");
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 7);
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.contains("status 1\n"));
+        assert!(output_str.contains("status 0\n"));
+    }
+
+    #[test]
+    fn test_repl_substitution_captures_large_output() {
+        std::env::set_var("PATH", "/bin:/usr/bin");
+        let input = Cursor::new("X=$(seq 1 50000)\necho ${#X}\nexit\n");
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0);
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.contains("288893\n"));
+    }
+}
+
+#[cfg(test)]
+mod arithmetic_tests {
+    use super::*;
+    use crate::arith;
+    use crate::variables::Variables;
+
+    fn eval(expression: &str) -> Result<i64, String> {
+        arith::evaluate(expression, &mut Variables::default()).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn test_operators_and_precedence() {
+        assert_eq!(eval("1 + 2 * 3"), Ok(7));
+        assert_eq!(eval("(1 + 2) * 3"), Ok(9));
+        assert_eq!(eval("2 ** 3 ** 2"), Ok(512));
+        assert_eq!(eval("-7 / 2"), Ok(-3));
+        assert_eq!(eval("-7 % 3"), Ok(-1));
+        assert_eq!(eval("1 << 4 | 1"), Ok(17));
+        assert_eq!(eval("3 > 2 && 2 >= 2 || 0"), Ok(1));
+        assert_eq!(eval("!5 + ~0"), Ok(-1));
+        assert_eq!(eval("0 ? 1 : 2 ? 3 : 4"), Ok(3));
+        assert_eq!(eval("0x1F + 010"), Ok(39));
+        assert_eq!(eval(""), Ok(0));
+    }
+
+    #[test]
+    fn test_variables_and_assignment() {
+        let mut variables = Variables::default();
+        variables.set("n", "4").unwrap();
+        variables.set("expr", "n * 2").unwrap();
+        assert_eq!(arith::evaluate("expr + unset", &mut variables), Ok(8));
+        assert_eq!(arith::evaluate("n++ + ++n", &mut variables), Ok(10));
+        assert_eq!(variables.get("n"), Some("6"));
+        assert_eq!(arith::evaluate("a = b = 2, a *= 5", &mut variables), Ok(10));
+        assert_eq!(variables.get("a"), Some("10"));
+        assert_eq!(variables.get("b"), Some("2"));
+    }
+
+    #[test]
+    fn test_short_circuit_skips_side_effects() {
+        let mut variables = Variables::default();
+        assert_eq!(arith::evaluate("0 && (x = 1)", &mut variables), Ok(0));
+        assert_eq!(arith::evaluate("1 || 1 / 0", &mut variables), Ok(1));
+        assert_eq!(arith::evaluate("1 ? 2 : (x = 3)", &mut variables), Ok(2));
+        assert_eq!(variables.get("x"), None);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(eval("1 / 0"), Err("division by 0".to_string()));
+        assert_eq!(
+            eval("1 +"),
+            Err("syntax error: operand expected".to_string())
+        );
+        assert_eq!(
+            eval("2 = 3"),
+            Err("attempted assignment to non-variable".to_string())
+        );
+        assert!(eval("1 2")
+            .unwrap_err()
+            .contains("invalid arithmetic operator"));
+        assert!(eval("09").unwrap_err().contains("value too great for base"));
+    }
+
+    #[test]
+    fn test_repl_arithmetic_expansion() {
+        let input = Cursor::new("i=5\necho $((i * 2)) $(( $i + 1 )) \"$((i++))\" $i\necho $((1 / 0))\necho status $?\nexit\n");
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0);
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.contains("10 6 5 6\n"));
+        assert!(output_str.contains("1 / 0: division by 0"));
+        assert!(output_str.contains("status 1\n"));
+    }
+
+    #[test]
+    fn test_repl_arithmetic_command_status() {
+        let input = Cursor::new(
+            "((3 > 2)); echo a $?\n((3 < 2)); echo b $?\n((count += 4)) && echo c $count\n((0))\n",
+        );
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 1);
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.contains("a 0\n"));
+        assert!(output_str.contains("b 1\n"));
+        assert!(output_str.contains("c 4\n"));
+    }
+
+    #[test]
+    fn test_repl_double_paren_subshells_are_not_arithmetic() {
+        let input = Cursor::new("((echo nested) )\necho $((echo sub) )\nexit\n");
+        let mut output = Vec::new();
+
+        let result = run_repl(input, &mut output);
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0);
+
+        let output_str = String::from_utf8(output).unwrap();
+        assert!(output_str.contains("nested\n"));
+        assert!(output_str.contains("sub\n"));
+    }
+}
+
+#[cfg(test)]
+mod glob_tests {
+    use super::*;
+    use std::fs;
+
+    fn sample_dir() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for name in [
+            "b.txt",
+            "a.txt",
+            "c.log",
+            ".hidden.txt",
+            "sub/x.txt",
+            "sub2/y.txt",
+        ] {
+            let path = dir.path().join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        dir
+    }
+
+    fn run_in(dir: &tempfile::TempDir, script: &str) -> (i32, String) {
+        let script = script.replace("DIR", &dir.path().display().to_string());
+        let mut output = Vec::new();
+        let result = run_repl(Cursor::new(script), &mut output);
+        assert!(result.is_ok());
+        let output = String::from_utf8(output).unwrap();
+        (
+            result.unwrap(),
+            output.replace(&dir.path().display().to_string(), "DIR"),
+        )
+    }
+
+    #[test]
+    fn test_repl_glob_sorts_matches_and_skips_dotfiles() {
+        let dir = sample_dir();
+        let (status, output) = run_in(&dir, "echo DIR/*.txt\necho DIR/?.log DIR/[!a].txt\n");
+        assert_eq!(status, 0);
+        assert!(output.contains("DIR/a.txt DIR/b.txt\n"));
+        assert!(output.contains("DIR/c.log DIR/b.txt\n"));
+
+        let (_, output) = run_in(&dir, "echo DIR/.*\n");
+        assert!(output.contains("DIR/.hidden.txt\n"));
+    }
+
+    #[test]
+    fn test_repl_glob_matches_directories_component_wise() {
+        let dir = sample_dir();
+        let (status, output) = run_in(&dir, "echo DIR/sub*/*.txt\necho DIR/s*/\n");
+        assert_eq!(status, 0);
+        assert!(output.contains("DIR/sub/x.txt DIR/sub2/y.txt\n"));
+        assert!(output.contains("DIR/sub/ DIR/sub2/\n"));
+    }
+
+    #[test]
+    fn test_repl_quoting_suppresses_glob() {
+        let dir = sample_dir();
+        let (status, output) = run_in(
+            &dir,
+            "echo \"DIR/*.txt\" 'DIR/*.log' DIR/\\*.txt\nP='DIR/*.log'\necho $P \"$P\"\n",
+        );
+        assert_eq!(status, 0);
+        assert!(output.contains("DIR/*.txt DIR/*.log DIR/*.txt\n"));
+        assert!(output.contains("DIR/c.log DIR/*.log\n"));
+    }
+
+    #[test]
+    fn test_repl_unmatched_pattern_is_kept() {
+        let dir = sample_dir();
+        let (status, output) = run_in(&dir, "echo DIR/*.none\n");
+        assert_eq!(status, 0);
+        assert!(output.contains("DIR/*.none\n"));
+    }
+
+    #[test]
+    fn test_repl_nullglob_and_failglob() {
+        let dir = sample_dir();
+        let (status, output) = run_in(
+            &dir,
+            "shopt -s nullglob\necho start DIR/*.none end\nshopt -u nullglob\nshopt -s failglob\necho DIR/*.none\necho status $?\nshopt\n",
+        );
+        assert_eq!(status, 0);
+        assert!(output.contains("start end\n"));
+        assert!(output.contains("no match: DIR/*.none"));
+        assert!(output.contains("status 1\n"));
+        assert!(output.contains("failglob\ton\nnullglob\toff\n"));
+    }
+
+    #[test]
+    fn test_repl_dotglob_matches_hidden_files() {
+        let dir = sample_dir();
+        let (status, output) = run_in(
+            &dir,
+            "shopt -s dotglob\necho DIR/*.txt\nshopt -s nosuchoption\n",
+        );
+        assert_eq!(status, 1);
+        assert!(output.contains("DIR/.hidden.txt DIR/a.txt DIR/b.txt\n"));
+        assert!(output.contains("shopt: nosuchoption: invalid shell option name"));
+    }
+}
+
+#[cfg(test)]
+mod tilde_expansion_tests {
+    use super::*;
+
+    #[test]
+    fn test_repl_expands_tilde_word_prefix() {
+        let (status, output) = run("HOME=/home/tester\necho ~ ~/src a~ x/~\nexit\n");
+        assert_eq!(status, 0);
+        assert!(output.contains("/home/tester /home/tester/src a~ x/~\n"));
+    }
+
+    #[test]
+    fn test_repl_quoted_tilde_is_literal() {
+        let (status, output) = run("HOME=/home/tester\necho \"~\" '~/x' \\~ ~\"x\"\nexit\n");
+        assert_eq!(status, 0);
+        assert!(output.contains("~ ~/x ~ ~x\n"));
+    }
+
+    #[test]
+    fn test_repl_expands_tilde_after_colons_in_assignment() {
+        let (status, output) =
+            run("HOME=/home/tester\nP=~/bin:~/lib:x~\necho $P\necho a:~/b\nexit\n");
+        assert_eq!(status, 0);
+        assert!(output.contains("/home/tester/bin:/home/tester/lib:x~\n"));
+        assert!(output.contains("a:~/b\n"));
+    }
+
+    #[test]
+    fn test_repl_expands_tilde_user() {
+        let (status, output) = run("echo ~root/x ~no_such_user_here/x\nexit\n");
+        assert_eq!(status, 0);
+        assert!(output.contains("/root/x ~no_such_user_here/x\n"));
+    }
+
+    #[test]
+    fn test_repl_expands_tilde_plus_and_minus() {
+        let (status, output) = run("(cd /tmp; cd /; echo ~+ ~-; cd -; echo $PWD $OLDPWD)\nexit\n");
+        assert_eq!(status, 0);
+        assert!(output.contains("/ /tmp\n"));
+        assert!(output.contains("/tmp\n/tmp /\n"));
+    }
+
+    #[test]
+    fn test_repl_cd_accepts_tilde_paths() {
+        let (status, output) = run("HOME=/usr\n(cd ~/bin; pwd)\nexit\n");
+        assert_eq!(status, 0);
+        assert!(output.contains("/usr/bin\n"));
+    }
+}
+
+#[cfg(test)]
+mod brace_expansion_tests {
+    use super::*;
+
+    #[test]
+    fn test_repl_expands_comma_lists() {
+        let (status, output) = run("echo src/{bin,lib,tests} a{,b}c\nexit\n");
+        assert_eq!(status, 0);
+        assert!(output.contains("src/bin src/lib src/tests ac abc\n"));
+    }
+
+    #[test]
+    fn test_repl_expands_numeric_sequences() {
+        let (status, output) = run("echo {1..5} {5..1..2} {-1..1}\necho {01..10..3}\nexit\n");
+        assert_eq!(status, 0);
+        assert!(output.contains("1 2 3 4 5 5 3 1 -1 0 1\n"));
+        assert!(output.contains("01 04 07 10\n"));
+    }
+
+    #[test]
+    fn test_repl_expands_character_sequences() {
+        let (status, output) = run("echo {a..e} {z..t..3}\nexit\n");
+        assert_eq!(status, 0);
+        assert!(output.contains("a b c d e z w t\n"));
+    }
+
+    #[test]
+    fn test_repl_expands_nested_and_adjacent_braces() {
+        let (status, output) = run("echo x{a,{1..3},b}y {a,b}{1,2}\nexit\n");
+        assert_eq!(status, 0);
+        assert!(output.contains("xay x1y x2y x3y xby a1 a2 b1 b2\n"));
+    }
+
+    #[test]
+    fn test_repl_leaves_quoted_and_invalid_braces() {
+        let (status, output) = run(
+            "echo \"{a,b}\" '{1..3}' \\{a,b\\} {a} {} {1..b} {a,b\nV=x\necho ${V:-{a,b}}\nexit\n",
+        );
+        assert_eq!(status, 0);
+        assert!(output.contains("{a,b} {1..3} {a,b} {a} {} {1..b} {a,b\n"));
+        assert!(output.contains("x\n"));
+    }
+
+    #[test]
+    fn test_repl_brace_expansion_happens_before_parameter_expansion() {
+        let (status, output) = run("A=1\nB=2\necho ${A}{x,y} {\"$A\",$B}\nexit\n");
+        assert_eq!(status, 0);
+        assert!(output.contains("1x 1y 1 2\n"));
+    }
+}
+
+#[cfg(test)]
+mod field_splitting_tests {
+    use super::*;
+
+    #[test]
+    fn test_repl_unquoted_expansion_is_split_on_whitespace() {
+        let (status, output) = run_script(
+            "FILES=\"  a b\tc  \"\nprintf '<%s>' $FILES \"$FILES\"; printf '\\n'\n",
+            &[],
+        );
+        assert_eq!(status, 0);
+        assert!(output.contains("<a><b><c><  a b\tc  >\n"));
+    }
+
+    #[test]
+    fn test_repl_non_whitespace_ifs_delimits_empty_fields() {
+        let (status, output) = run_script(
+            "IFS=:\nV=a::b:\nprintf '<%s>' $V; printf '\\n'\nIFS=' :'\nV=' a : b  c :'\nprintf '<%s>' $V; printf '\\n'\n",
+            &[],
+        );
+        assert_eq!(status, 0);
+        assert!(output.contains("<a><><b>\n"));
+        assert!(output.contains("<a><b><c>\n"));
+    }
+
+    #[test]
+    fn test_repl_empty_ifs_disables_splitting() {
+        let (status, output) = run_script("IFS=\nV='a b'\nprintf '<%s>' $V; printf '\\n'\n", &[]);
+        assert_eq!(status, 0);
+        assert!(output.contains("<a b>\n"));
+    }
+
+    #[test]
+    fn test_repl_command_and_arithmetic_results_are_split() {
+        let (status, output) = run_script(
+            "printf '<%s>' $(echo 1 2) \"$(echo 3 4)\" x$((2 + 3)); printf '\\n'\n",
+            &[],
+        );
+        assert_eq!(status, 0);
+        assert!(output.contains("<1><2><3 4><x5>\n"));
+    }
+
+    #[test]
+    fn test_repl_quoted_at_expands_to_separate_words() {
+        let (status, output) = run_script(
+            "printf '<%s>' \"$@\"; printf '\\n'\nprintf '<%s>' x\"$@\"y; printf '\\n'\nprintf '<%s>' $@; printf '\\n'\nprintf '<%s>' \"$*\"; printf '\\n'\n",
+            &["a b", "", "c"],
+        );
+        assert_eq!(status, 0);
+        assert!(output.contains("<a b><><c>\n"));
+        assert!(output.contains("<xa b><><cy>\n"));
+        assert!(output.contains("<a><b><c>\n"));
+        assert!(output.contains("<a b  c>\n"));
+    }
+
+    #[test]
+    fn test_repl_quoted_at_without_parameters_is_no_word() {
+        let (status, output) = run_script(
+            "printf '<%s>' \"$@\" x\"$@\"y \"$*\" '' $EMPTY; printf '\\n'\n",
+            &[],
+        );
+        assert_eq!(status, 0);
+        assert!(output.contains("<xy><><>\n"));
+    }
+}
+
+#[cfg(test)]
+mod line_editor_tests {
+    use super::*;
+    use crate::complete::Completion;
+    use crate::editor::LineEditor;
+
+    /// Feeds `keys` to a fresh editor, returning the line it produced.
+    fn edit(keys: &[u8], history: &[&str]) -> Option<String> {
+        let history: Vec<String> = history.iter().map(|line| line.to_string()).collect();
+        let mut output = Vec::new();
+        LineEditor::new()
+            .edit(
+                "$ ",
+                &history,
+                &mut |_, _| Completion::default(),
+                &mut Cursor::new(keys),
+                &mut output,
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn test_editor_moves_cursor_and_inserts() {
+        assert_eq!(
+            edit(b"echo helo\x1b[D\x1b[Dl\r", &[]).unwrap(),
+            "echo hello"
+        );
+        assert_eq!(edit(b"cho x\x01e\x05y\r", &[]).unwrap(), "echo xy");
+        assert_eq!(edit(b"ab\x1b[Hx\x1b[Fy\r", &[]).unwrap(), "xaby");
+    }
+
+    #[test]
+    fn test_editor_deletes_characters() {
+        assert_eq!(
+            edit(b"echo abcd\x7f\x02\x02\x04\r", &[]).unwrap(),
+            "echo ac"
+        );
+        assert_eq!(edit(b"abc\x01\x1b[3~\r", &[]).unwrap(), "bc");
+    }
+
+    #[test]
+    fn test_editor_moves_and_kills_by_word() {
+        assert_eq!(
+            edit(b"one two three\x1bbX\r", &[]).unwrap(),
+            "one two Xthree"
+        );
+        assert_eq!(edit(b"one two\x01\x1b[1;5CX\r", &[]).unwrap(), "oneX two");
+        assert_eq!(edit(b"echo a/b c\x17\r", &[]).unwrap(), "echo a/b ");
+        assert_eq!(edit(b"echo a/b\x1b\x7f\r", &[]).unwrap(), "echo a/");
+        assert_eq!(edit(b"one two\x01\x1bd\r", &[]).unwrap(), " two");
+    }
+
+    #[test]
+    fn test_editor_kills_and_yanks() {
+        assert_eq!(
+            edit(
+                b"hello world\x01\x06\x06\x06\x06\x06\x0b\x05 again\x19\r",
+                &[]
+            )
+            .unwrap(),
+            "hello again world"
+        );
+        assert_eq!(edit(b"abc def\x15x\x19\r", &[]).unwrap(), "xabc def");
+    }
+
+    #[test]
+    fn test_editor_navigates_history() {
+        let history = ["first", "second"];
+        assert_eq!(edit(b"\x1b[A\r", &history).unwrap(), "second");
+        assert_eq!(edit(b"\x1b[A\x1b[A\x1b[A\r", &history).unwrap(), "first");
+        assert_eq!(edit(b"draft\x10\x10\x0e\x0e\r", &history).unwrap(), "draft");
+        assert_eq!(edit(b"\x1b[A\x1b[A\x1b[B!\r", &history).unwrap(), "second!");
+    }
+
+    #[test]
+    fn test_editor_cancels_line_and_ends_input() {
+        assert_eq!(edit(b"echo abc\x03", &[]).unwrap(), "");
+        assert_eq!(edit(b"\x04", &[]), None);
+        assert_eq!(edit(b"", &[]), None);
+        assert_eq!(edit(b"partial", &[]).unwrap(), "partial");
+    }
+
+    #[test]
+    fn test_editor_accepts_utf8_input() {
+        assert_eq!(
+            edit("echo héllo\x1b[D\x1b[D\x1b[D\x7fe\r".as_bytes(), &[]).unwrap(),
+            "echo hello"
+        );
+    }
+}
+
+#[cfg(test)]
+mod history_tests {
+    use super::*;
+    use crate::history::History;
+
+    #[test]
+    fn test_repl_history_lists_numbered_entries() {
+        let (status, output) = run("echo one\necho two\nhistory\nhistory 1\nexit\n");
+        assert_eq!(status, 0);
+        assert!(output.contains("    1  echo one\n    2  echo two\n    3  history\n"));
+        assert!(output.contains("    4  history 1\n"));
+        assert!(!output.contains("    1  echo one\n    2  echo two\n    3  history\n    4"));
+    }
+
+    #[test]
+    fn test_repl_history_clear_and_delete() {
+        let (status, output) = run(
+            "echo one\necho two\nhistory -d 1\nhistory\nhistory -c\nhistory\nhistory -d 9\nexit\n",
+        );
+        assert_eq!(status, 1);
+        assert!(output.contains("    1  echo two\n    2  history -d 1\n    3  history\n"));
+        assert!(output.contains("$     1  history\n"));
+        assert!(output.contains("history: 9: history position out of range"));
+    }
+
+    #[test]
+    fn test_repl_histsize_limits_entries() {
+        let (status, output) = run("HISTSIZE=2\necho a\necho b\nhistory\nexit\n");
+        assert_eq!(status, 0);
+        assert!(output.contains("    3  echo b\n    4  history\n"));
+        assert!(!output.contains("  echo a\n"));
+    }
+
+    #[test]
+    fn test_repl_history_writes_and_reads_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("saved");
+        let (status, output) = run(&format!(
+            "echo saved\nhistory -w {0}\nhistory -c\nhistory -r {0}\nhistory\nhistory -r {0}.missing\nexit\n",
+            file.display()
+        ));
+        assert_eq!(status, 1);
+        assert!(output.contains(&format!(
+            "    2  echo saved\n    3  history -w {}\n    4  history\n",
+            file.display()
+        )));
+        assert!(output.contains("No such file or directory"));
+        assert_eq!(
+            std::fs::read_to_string(&file).unwrap(),
+            format!("echo saved\nhistory -w {}\n", file.display())
+        );
+    }
+
+    #[test]
+    fn test_history_file_keeps_multi_line_entries() {
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let file = temp_dir.path().join("history");
+        let entries = [
+            "for i in 1 2; do echo 'a\n\nb'; done",
+            "echo 'ends in \\\n' x\\\\",
+            "echo one",
+        ];
+
+        let mut history = History::default();
+        for entry in entries {
+            history.push(entry, 10);
+        }
+        history.write_file(&file).unwrap();
+        let mut restored = History::default();
+        restored.read_file(&file, 10).unwrap();
+        assert_eq!(restored.entries(), entries);
+    }
+
+    #[test]
+    fn test_repl_expands_history_references() {
+        let (status, output) =
+            run("echo one\necho two\n!!\n!-3\n!1\n!echo\necho !! 'a!!' done\nexit\n");
+        assert_eq!(status, 0);
+        assert!(
+            output.contains("$ echo two\ntwo\n$ echo one\none\n$ echo one\none\n$ echo one\none\n")
+        );
+        assert!(output.contains("$ echo echo one 'a!!' done\necho one a!! done\n"));
+    }
+
+    #[test]
+    fn test_repl_quick_substitution() {
+        let (status, output) = run("echo hello world\n^world^there\n^nope^x\nexit\n");
+        assert_eq!(status, 0);
+        assert!(output.contains("$ echo hello there\nhello there\n"));
+        assert!(output.contains("^nope^x: substitution failed"));
+    }
+
+    #[test]
+    fn test_repl_leaves_plain_exclamation_marks() {
+        let (status, output) = run("echo hi! ! = [!a]\n!missing\n! false\necho $?\nexit\n");
+        assert_eq!(status, 0);
+        assert!(output.contains("hi! ! = [!a]\n"));
+        assert!(output.contains("!missing: event not found"));
+        assert!(output.contains("0\n"));
+    }
+}
+
+#[cfg(test)]
+mod completion_tests {
+    use super::*;
+    use crate::complete::{self, Completion};
+    use crate::editor::LineEditor;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn sample_dir() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("src")).unwrap();
+        for name in ["main.rs", "my file.txt", "mytool", ".hidden"] {
+            fs::write(dir.path().join(name), "").unwrap();
+        }
+        fs::set_permissions(dir.path().join("mytool"), fs::Permissions::from_mode(0o755)).unwrap();
+        dir
+    }
+
+    fn complete_in(dir: &tempfile::TempDir, line: &str) -> Completion {
+        let line = line.replace("DIR", &dir.path().display().to_string());
+        let mut shell = Shell::new();
+        shell
+            .variables
+            .set("PATH", dir.path().display().to_string())
+            .unwrap();
+        let mut completion = complete::complete(&mut shell, &line, line.chars().count());
+        for candidate in &mut completion.candidates {
+            *candidate = candidate.replace(&dir.path().display().to_string(), "DIR");
+        }
+        completion
+    }
+
+    #[test]
+    fn test_completes_builtins_and_path_commands() {
+        let dir = sample_dir();
+        assert_eq!(complete_in(&dir, "ec").candidates, ["echo"]);
+        assert_eq!(complete_in(&dir, "my").candidates, ["mytool"]);
+        assert_eq!(complete_in(&dir, "true; my").candidates, ["mytool"]);
+        assert_eq!(complete_in(&dir, "X=1 my").candidates, ["mytool"]);
+        assert_eq!(complete_in(&dir, "ex").candidates, ["exit", "export"]);
+    }
+
+    #[test]
+    fn test_completes_later_words_as_paths() {
+        let dir = sample_dir();
+        assert_eq!(
+            complete_in(&dir, "cat DIR/m").candidates,
+            ["DIR/main.rs", "DIR/my\\ file.txt", "DIR/mytool"]
+        );
+        assert_eq!(complete_in(&dir, "cd DIR/s").candidates, ["DIR/src/"]);
+        assert_eq!(complete_in(&dir, "ls DIR/.h").candidates, ["DIR/.hidden"]);
+        assert_eq!(
+            complete_in(&dir, "DIR/m").candidates,
+            ["DIR/mytool"],
+            "a command path completes to executables only"
+        );
+    }
+
+    #[test]
+    fn test_completion_respects_quotes() {
+        let dir = sample_dir();
+        let completion = complete_in(&dir, "cat \"DIR/my f");
+        assert_eq!(completion.start, 4);
+        assert_eq!(completion.candidates, ["\"DIR/my file.txt\""]);
+        assert_eq!(
+            complete_in(&dir, "cat DIR/my\\ f").candidates,
+            ["DIR/my\\ file.txt"]
+        );
+        fs::write(dir.path().join("it's"), "").unwrap();
+        assert_eq!(
+            complete_in(&dir, "cat 'DIR/it").candidates,
+            ["'DIR/it'\\''s'"]
+        );
+    }
+
+    #[test]
+    fn test_completes_variable_names() {
+        let mut shell = Shell::new();
+        shell.variables.set("MY_VARIABLE", "1").unwrap();
+        shell.variables.set("MY_OTHER", "2").unwrap();
+        let completion = complete::complete(&mut shell, "echo x$MY_V", 11);
+        assert_eq!(completion.start, 5);
+        assert_eq!(completion.candidates, ["x$MY_VARIABLE"]);
+        let completion = complete::complete(&mut shell, "echo ${MY_", 10);
+        assert_eq!(completion.candidates, ["${MY_OTHER}", "${MY_VARIABLE}"]);
+    }
+
+    /// Edits `keys` with a completer offering `candidates` for the last word.
+    fn edit_with(keys: &[u8], candidates: &[&str]) -> (String, String) {
+        let mut output = Vec::new();
+        let line = LineEditor::new()
+            .edit(
+                "$ ",
+                &[],
+                &mut |line, _| Completion {
+                    start: line.rfind(' ').map_or(0, |space| space + 1),
+                    candidates: candidates.iter().map(|c| c.to_string()).collect(),
+                },
+                &mut Cursor::new(keys),
+                &mut output,
+            )
+            .unwrap()
+            .unwrap();
+        (line, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn test_editor_inserts_unique_completion() {
+        assert_eq!(edit_with(b"ec\t\r", &["echo"]).0, "echo ");
+        assert_eq!(edit_with(b"cd s\t\r", &["src/"]).0, "cd src/");
+    }
+
+    #[test]
+    fn test_editor_inserts_common_prefix_and_lists_on_double_tab() {
+        let (line, output) = edit_with(b"ex\t\r", &["exit", "export"]);
+        assert_eq!(line, "ex");
+        assert!(output.contains('\x07'));
+        let (line, output) = edit_with(b"cat m\t\r", &["main.rs", "makefile"]);
+        assert_eq!(line, "cat ma");
+        assert!(!output.contains("main.rs"));
+        let (line, output) = edit_with(b"cat ma\t\t\r", &["main.rs", "makefile"]);
+        assert_eq!(line, "cat ma");
+        assert!(output.contains("\nmain.rs   makefile\n"));
+        let (_, output) = edit_with(b"ls d/\t\t\r", &["d/one", "d/two/"]);
+        assert!(output.contains("\none   two/\n"));
+    }
+}
+
+#[cfg(test)]
+mod complete_builtin_tests {
+    use super::*;
+    use crate::complete;
+
+    fn run_in(shell: &mut Shell, input: &str) -> (i32, String) {
+        let mut output = Vec::new();
+        let status = shell
+            .run(Cursor::new(input.to_string()), &mut output)
+            .unwrap();
+        (status, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn test_complete_word_list_spec() {
+        let mut shell = Shell::new();
+        let (status, _) = run_in(&mut shell, "complete -W 'start stop status' svc\n");
+        assert_eq!(status, 0);
+        let completion = complete::complete(&mut shell, "svc st", 6);
+        assert_eq!(completion.start, 4);
+        assert_eq!(completion.candidates, ["start", "status", "stop"]);
+        assert_eq!(
+            complete::complete(&mut shell, "X=1 svc sta", 11).candidates,
+            ["start", "status"]
+        );
+    }
+
+    #[test]
+    fn test_complete_function_spec_sets_compreply() {
+        let mut shell = Shell::new().interactive(false);
+        run_in(
+            &mut shell,
+            "_svc() { if [ \"$3\" = svc ]; then COMPREPLY=\"start stop $2x\"; fi; }\ncomplete -F _svc svc\n",
+        );
+        assert_eq!(
+            complete::complete(&mut shell, "svc s", 5).candidates,
+            ["start", "stop", "sx"]
+        );
+        assert!(complete::complete(&mut shell, "svc a b", 7)
+            .candidates
+            .is_empty());
+        assert_eq!(shell.variables.get("COMPREPLY"), None);
+    }
+
+    #[test]
+    fn test_complete_prints_and_removes_specs() {
+        let mut shell = Shell::new();
+        let (status, output) = run_in(
+            &mut shell,
+            "complete -W 'a b' -F _svc svc\ncomplete -W x other\ncomplete\ncomplete -p svc\ncomplete -r other\ncomplete -p other\n",
+        );
+        assert_eq!(status, 1);
+        assert!(output.contains("complete -W 'x' other\ncomplete -W 'a b' -F _svc svc\n"));
+        assert!(output.contains("$ complete -W 'a b' -F _svc svc\n$ "));
+        assert!(output.contains("complete: other: no completion specification"));
+        assert_eq!(shell.completions.len(), 1);
+    }
+
+    #[test]
+    fn test_complete_rejects_bad_usage() {
+        let mut shell = Shell::new();
+        let (status, output) = run_in(&mut shell, "complete -W\n");
+        assert_eq!(status, 2);
+        assert!(output.contains("complete: -W: option requires an argument"));
+        let (status, output) = run_in(&mut shell, "complete -x svc\n");
+        assert_eq!(status, 2);
+        assert!(output.contains("complete: -x: invalid option"));
+        assert!(output.contains("complete: usage: complete"));
+    }
+}
+
+#[cfg(test)]
+mod job_control_tests {
+    use super::*;
+
+    #[test]
+    fn test_repl_runs_background_job_and_waits_for_it() {
+        let (status, output) = run("(exit 3) &\necho pid $!\nwait $!\necho status $?\n");
+        assert_eq!(status, 0);
+        let pid = output
+            .split("pid ")
+            .nth(1)
+            .and_then(|rest| rest.lines().next())
+            .unwrap();
+        assert!(output.contains(&format!("$ [1] {}\n", pid)));
+        assert!(output.contains("status 3\n"));
+    }
+
+    #[test]
+    fn test_repl_background_pid_is_the_command_itself() {
+        for (_, output) in [
+            run("sh -c 'echo pid $$' &\nwait\necho bang $!\n"),
+            run_script("true && sh -c 'echo pid $$' &\nwait\necho bang $!\n", &[]),
+        ] {
+            let pid = output.split("pid ").nth(1).unwrap().lines().next().unwrap();
+            assert!(output.contains(&format!("bang {}\n", pid)), "{}", output);
+        }
+    }
+
+    #[test]
+    fn test_repl_relays_background_output_and_reports_done() {
+        let (_, output) = run("echo from-job &\nsleep 0.2\nfalse &\nsleep 0.2\n");
+        // The output may be relayed before the job has exited, but never after
+        // the job is reported done.
+        let done = output
+            .find("[1]+  Done                    echo from-job\n$ ")
+            .unwrap();
+        assert!(output[..done].contains("from-job\n"));
+        assert!(output.contains("[1]+  Exit 1                  false\n$ "));
+    }
+
+    #[test]
+    fn test_repl_lists_and_disowns_jobs() {
+        let (status, output) =
+            run("sleep 0.3 &\nsleep 0.3 | cat &\njobs\ndisown %1\njobs %1\njobs -p %2\nwait\n");
+        assert_eq!(status, 0);
+        assert!(output.contains(
+            "[1]-  Running                 sleep 0.3 &\n[2]+  Running                 sleep 0.3 | cat &\n"
+        ));
+        assert!(output.contains("jobs: %1: no such job\n"));
+        let pid = output.split("[2] ").nth(1).unwrap().lines().next().unwrap();
+        assert!(output.contains(&format!("$ {}\n", pid)));
+    }
+
+    #[test]
+    fn test_repl_stops_foreground_job_and_resumes_it() {
+        let (status, output) = run(
+            "sh -c 'kill -STOP $$; echo resumed; exit 4'\necho stopped $?\njobs\nfg\necho fg $?\n",
+        );
+        assert_eq!(status, 0);
+        let job = "sh -c 'kill -STOP $$; echo resumed; exit 4'";
+        assert!(output.contains(&format!("\n[1]+  Stopped                 {}\n", job)));
+        assert!(output.contains("stopped 147\n"));
+        assert!(output.contains(&format!("$ {}\nresumed\n$ fg 4\n", job)));
+    }
+
+    #[test]
+    fn test_repl_stopped_foreground_job_abandons_rest_of_line() {
+        let (_, output) = run(
+            "for i in 1 2; do sh -c 'kill -STOP $$'; echo iter $i; done; echo after\necho next $?\nfg\n",
+        );
+        assert!(output.contains("\n[1]+  Stopped                 sh -c 'kill -STOP $$'\n"));
+        assert!(!output.contains("iter"));
+        assert!(!output.contains("after"));
+        assert!(output.contains("next 147\n"));
+    }
+
+    #[test]
+    fn test_repl_reaps_background_jobs_of_subshells() {
+        let (_, output) = run("( sleep 0.1 & )\nsleep 0.3\njobs\n");
+        assert!(output.contains("[1]+  Done                    sleep 0.1\n"));
+        assert!(!output.contains("Running"));
+    }
+
+    #[test]
+    fn test_repl_resumes_stopped_job_in_background() {
+        let (_, output) = run("sh -c 'kill -STOP $$; echo resumed'\nbg %sh; wait %1\nbg\n");
+        assert!(output.contains("[1]+ sh -c 'kill -STOP $$; echo resumed' &\nresumed\n"));
+        assert!(output.contains("bg: current: no such job\n"));
+    }
+
+    #[test]
+    fn test_repl_reports_job_errors() {
+        let (status, output) = run("fg %3\nwait %2\nwait 1\n");
+        assert_eq!(status, 127);
+        assert!(output.contains("fg: %3: no such job\n"));
+        assert!(output.contains("wait: %2: no such job\n"));
+        assert!(output.contains("wait: pid 1 is not a child of this shell\n"));
+
+        let mut output = Vec::new();
+        let status = Shell::new()
+            .interactive(false)
+            .run(Cursor::new("fg\n"), &mut output)
+            .unwrap();
+        assert_eq!(status, 1);
+        assert_eq!(String::from_utf8(output).unwrap(), "fg: no job control\n");
+    }
+}
+
+#[cfg(test)]
+mod signal_tests {
+    use super::*;
+
+    #[test]
+    fn test_repl_reports_child_terminated_by_signal() {
+        let (_, output) = run("sh -c 'kill -TERM $$'; echo status $?\nsh -c 'kill -KILL $$'\n");
+        assert!(output.contains("Terminated\nstatus 143\n"));
+        assert!(output.contains("Killed\n"));
+    }
+
+    #[test]
+    fn test_repl_abandons_line_when_child_is_interrupted() {
+        let (status, output) = run("sh -c 'kill -INT $$'; echo after\necho next $?\n");
+        assert_eq!(status, 0);
+        assert!(!output.contains("after"));
+        assert!(output.contains("next 130\n"));
+
+        let mut output = Vec::new();
+        let status = Shell::new()
+            .interactive(false)
+            .run(
+                Cursor::new("sh -c 'kill -INT $$'; echo after\n"),
+                &mut output,
+            )
+            .unwrap();
+        assert_eq!(status, 0);
+        assert_eq!(String::from_utf8(output).unwrap(), "\nafter\n");
+    }
+
+    #[test]
+    fn test_repl_abandons_line_when_last_pipeline_stage_is_interrupted() {
+        let (_, output) = run("echo a | sh -c 'kill -INT $$'; echo next\necho status $?\n");
+        assert!(!output.contains("next"));
+        assert!(output.contains("status 130\n"));
+    }
+
+    #[test]
+    fn test_repl_does_not_report_broken_pipe() {
+        let (_, output) = run("sh -c 'kill -PIPE $$'\necho $?\n");
+        assert!(output.contains("$ 141\n"));
+    }
+
+    #[test]
+    fn test_repl_children_get_default_signal_handling() {
+        // SAFETY: SIG_IGN and SIG_DFL are valid dispositions for SIGINT.
+        unsafe { libc::signal(libc::SIGINT, libc::SIG_IGN) };
+        let (_, output) = run("sh -c 'kill -INT $$; echo survived'\n");
+        unsafe { libc::signal(libc::SIGINT, libc::SIG_DFL) };
+        assert!(!output.contains("survived"));
+    }
+}
+
+#[cfg(test)]
+mod trap_tests {
+    use super::*;
+
+    #[test]
+    fn test_repl_trap_runs_exit_action_last() {
+        let (status, output) = run_script("trap 'echo bye $?' EXIT\necho hi\nexit 3\n", &[]);
+        assert_eq!(status, 3);
+        assert_eq!(output, "hi\nbye 3\n");
+
+        let (status, output) = run_script("trap 'echo cleanup; exit 5' 0\ntrue\n", &[]);
+        assert_eq!(status, 5);
+        assert_eq!(output, "cleanup\n");
+    }
+
+    #[test]
+    fn test_repl_trap_runs_err_action_for_failing_commands() {
+        let (status, output) = run_script(
+            "trap 'echo err $?' ERR\nfalse\nfalse && true\ntrue && false\n! true\ntrue || false\necho $?\n",
+            &[],
+        );
+        assert_eq!(status, 0);
+        assert_eq!(output, "err 1\nerr 1\n0\n");
+    }
+
+    #[test]
+    fn test_repl_trap_runs_signal_action_after_command() {
+        let (_, output) = run_script(
+            "trap 'echo got usr1' USR1\nkill -USR1 $$; sleep 0.1; echo after $?\ntrap - USR1\n",
+            &[],
+        );
+        assert!(output.contains("got usr1\n"));
+        assert!(output.contains("after 0\n"));
+    }
+
+    #[test]
+    fn test_repl_trap_runs_int_action_when_foreground_job_is_interrupted() {
+        let (_, output) = run(
+            "trap 'echo caught; echo twice' INT\nsh -c 'kill -INT $$'; echo after\ntrap - INT\n",
+        );
+        assert!(output.contains("caught\ntwice\n"));
+        assert!(!output.contains("after"));
+    }
+
+    #[test]
+    fn test_repl_trap_ignores_signal_in_shell_and_children() {
+        let (_, output) = run_script(
+            "trap '' USR2\nkill -USR2 $$\necho alive\nsh -c 'kill -USR2 $$; echo child alive'\ntrap - USR2\n",
+            &[],
+        );
+        assert_eq!(output, "alive\nchild alive\n");
+    }
+
+    #[test]
+    fn test_repl_trap_lists_and_resets_actions() {
+        let (status, output) = run_script(
+            "trap 'echo x' EXIT TERM\ntrap '' 2\ntrap\ntrap -p TERM\ntrap - TERM\ntrap 2\ntrap EXIT\ntrap -p\ntrap x NOPE\n",
+            &[],
+        );
+        assert_eq!(status, 1);
+        assert_eq!(
+            output,
+            "trap -- 'echo x' EXIT\ntrap -- '' SIGINT\ntrap -- 'echo x' SIGTERM\n\
+             trap -- 'echo x' SIGTERM\ntrap: NOPE: invalid signal specification\n"
+        );
+    }
+
+    #[test]
+    fn test_repl_trap_actions_are_not_inherited_by_subshells() {
+        let (_, output) = run_script(
+            "trap 'echo parent exit' EXIT\n(echo sub; trap)\n(trap 'echo sub exit' EXIT; echo sub2)\n",
+            &[],
+        );
+        assert_eq!(output, "sub\nsub2\nsub exit\nparent exit\n");
+    }
+}
+
+#[cfg(test)]
+mod control_flow_tests {
+    use super::*;
+
+    #[test]
+    fn test_repl_if_runs_first_true_branch() {
+        let (status, output) = run_script(
+            "if false; then echo a; elif true; then echo b; else echo c; fi\n\
+             if false; then echo a; fi; echo $?\n\
+             if false\nthen echo a\nelse\n  echo c; false\nfi\n",
+            &[],
+        );
+        assert_eq!(status, 1);
+        assert_eq!(output, "b\n0\nc\n");
+    }
+
+    #[test]
+    fn test_repl_while_and_until_loops() {
+        let (_, output) = run_script(
+            "i=0\nwhile (( i < 3 )); do echo $i; i=$((i + 1)); done\n\
+             until (( i == 0 )); do i=$((i - 1)); done; echo done $i\n",
+            &[],
+        );
+        assert_eq!(output, "0\n1\n2\ndone 0\n");
+    }
+
+    #[test]
+    fn test_repl_for_loops_over_words_and_positional_parameters() {
+        let (_, output) = run_script("for x in a 'b c' {1..2}; do echo \"[$x]\"; done\n", &[]);
+        assert_eq!(output, "[a]\n[b c]\n[1]\n[2]\n");
+
+        let mut output = Vec::new();
+        Shell::new()
+            .interactive(false)
+            .arguments("script", vec!["one".to_string(), "two".to_string()])
+            .run(Cursor::new("for arg\ndo\n  echo $arg\ndone\n"), &mut output)
+            .unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "one\ntwo\n");
+    }
+
+    #[test]
+    fn test_repl_break_and_continue() {
+        let (status, output) = run_script(
+            "for i in 1 2 3 4; do\n  [ $i = 2 ] && continue\n  [ $i = 4 ] && break\n  echo $i\ndone\n\
+             for a in x y; do for b in 1 2 3; do\n  [ $b = 2 ] && continue 2\n  [ $a = y ] && break 2\n  echo $a$b\ndone; done\n\
+             while true; do while true; do break 5; done; echo inner; done; echo out\n\
+             break\n",
+             &[],
+        );
+        assert_eq!(status, 0);
+        assert_eq!(
+            output,
+            "1\n3\nx1\nout\nbreak: only meaningful in a `for', `while', or `until' loop\n"
+        );
+    }
+
+    #[test]
+    fn test_repl_case_matches_patterns_and_alternatives() {
+        let (_, output) = run_script(
+            "for w in apple b.txt Z '*'; do\n  case $w in\n    a*|z) echo \"$w: a\";;\n    (*.txt) echo \"$w: text\" ;;\n    \"*\") echo \"$w: star\";;\n    [[:upper:]]) echo \"$w: upper\"\n  esac\ndone\n\
+             case none in x) echo x;; esac; echo $?\n",
+             &[],
+        );
+        assert_eq!(output, "apple: a\nb.txt: text\nZ: upper\n*: star\n0\n");
+    }
+
+    #[test]
+    fn test_repl_incomplete_commands_continue_on_next_line() {
+        let mut output = Vec::new();
+        let status = run_repl(
+            Cursor::new("PS2='... '\nfor i in 1 2\ndo\n  echo $i\ndone\necho a \\\nb\nhistory\n"),
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(status, 0);
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("$ ... ... ... 1\n2\n"));
+        assert!(output.contains("$ ... a b\n"));
+        assert!(output.contains("    2  for i in 1 2; do echo $i; done\n"));
+        assert!(output.contains("    3  echo a b\n"));
+    }
+
+    #[test]
+    fn test_repl_keeps_whitespace_of_first_line() {
+        let (status, output) = run("echo \"a   \nb\"\necho a\\ \necho b\n");
+        assert_eq!(status, 0);
+        assert!(output.contains("a   \nb\n"));
+        assert!(output.contains("a \n$ b\n"));
+    }
+
+    #[test]
+    fn test_repl_unfinished_command_at_end_of_input_is_an_error() {
+        // Read line by line, the complete first line runs before the error.
+        let mut output = Vec::new();
+        let status = Shell::new()
+            .interactive(false)
+            .run(
+                Cursor::new("echo start\nwhile true; do\n  echo never\n"),
+                &mut output,
+            )
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(status, 2);
+        assert!(output.starts_with("start\nparse error: expected 'done'"));
+    }
+
+    #[test]
+    fn test_repl_in_process_stages_run_concurrently() {
+        // Each stage writes more than a pipe holds before the next one reads it.
+        let (status, output) = run_script(
+            "f() { for i in $(seq 1 10000); do echo line$i; done; }\n\
+             f | { wc -l; }\n\
+             { f; } | f | tail -n 1\n\
+             f | head -n 1\n",
+            &[],
+        );
+        assert_eq!(status, 0);
+        assert_eq!(output, "10000\nline10000\nline1\n");
+    }
+
+    #[test]
+    fn test_repl_conditions_do_not_trigger_err_trap() {
+        let (_, output) = run_script(
+            "trap 'echo err' ERR\nif false; then :; fi\nwhile false; do :; done\nuntil true; do :; done\nif true; then false; fi\n",
+            &[],
+        );
+        assert_eq!(output, "err\n");
+    }
+}
+
+#[cfg(test)]
+mod function_tests {
+    use super::*;
+
+    #[test]
+    fn test_repl_functions_get_their_own_positional_parameters() {
+        let (_, output) = run_script(
+            "show() { echo \"$# $1 $2\"; }\nshow a 'b c'\necho \"$# $1\"\n",
+            &["top"],
+        );
+        assert_eq!(output, "2 a b c\n1 top\n");
+    }
+
+    #[test]
+    fn test_repl_functions_are_found_before_builtins_and_path() {
+        let (_, output) = run_script(
+            "pwd() { echo not pwd; }\npwd\nfunction cat {\n  echo not cat\n}\necho x | cat\nunset -f pwd cat\necho x | cat\n",
+            &[],
+        );
+        assert_eq!(output, "not pwd\nnot cat\nx\n");
+    }
+
+    #[test]
+    fn test_repl_return_sets_status_and_leaves_function() {
+        let (status, output) = run_script(
+            "f() {\n  for i in 1 2 3; do\n    [ $i = 2 ] && return $((40 + i))\n    echo $i\n  done\n  echo unreachable\n}\nf; echo $?\n\
+             g() { false; return; }; g; echo $?\n\
+             return 1\n",
+             &[],
+        );
+        assert_eq!(status, 1);
+        assert_eq!(
+            output,
+            "1\n42\n1\nreturn: can only `return' from a function\n"
+        );
+    }
+
+    #[test]
+    fn test_repl_local_variables_are_dynamically_scoped() {
+        let (_, output) = run_script(
+            "x=global\n\
+             show() { echo \"show: $x\"; x=set-by-show; }\n\
+             f() { local x=f-local y; show; echo \"f: $x [$y]\"; }\n\
+             f; echo \"top: $x\"\n\
+             local x\n",
+            &[],
+        );
+        assert_eq!(
+            output,
+            "show: f-local\nf: set-by-show []\ntop: global\nlocal: can only be used in a function\n"
+        );
+    }
+
+    #[test]
+    fn test_repl_local_variables_stay_exported() {
+        let (_, output) = run_script(
+            "export LOCAL_EXPORTED=1\n\
+             f() { local LOCAL_EXPORTED=2; env | grep '^LOCAL_EXPORTED='; }\n\
+             f; env | grep '^LOCAL_EXPORTED='\n",
+            &[],
+        );
+        assert_eq!(output, "LOCAL_EXPORTED=2\nLOCAL_EXPORTED=1\n");
+    }
+
+    #[test]
+    fn test_repl_functions_recurse_and_apply_redirections() {
+        let dir = env::temp_dir().join(format!("shell-function-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("out");
+        let (_, output) = run_script(
+            &format!(
+                "countdown() {{ if (( $1 > 0 )); then echo $1; countdown $(( $1 - 1 )); fi; }}\n\
+             countdown 3\nsave() {{ echo saved $1; }} > {}\nsave it\n",
+                file.display()
+            ),
+            &[],
+        );
+        assert_eq!(output, "3\n2\n1\n");
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "saved it\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_repl_type_reports_function_with_its_body() {
+        let (status, output) = run_script("greet() { echo \"hi $1\"; }\ntype greet\n", &[]);
+        assert_eq!(status, 0);
+        assert_eq!(output, "greet is a function\ngreet() { echo \"hi $1\"; }\n");
+    }
+}
@@ -0,0 +1,173 @@
+//! Actions set with `trap`, and running them.
+//!
+//! Actions run between commands: a caught signal's once the command it
+//! arrived during has finished, `ERR`'s after a command fails and `EXIT`'s
+//! when the shell exits. `$?` is the same after an action as before it.
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+use crate::exec;
+use crate::parser;
+use crate::shell::Shell;
+use crate::signals;
+use crate::streams::{Output, Streams};
+
+/// What a trap action is attached to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Condition {
+    /// The shell exiting, also named `0`.
+    Exit,
+    /// A command failing.
+    Err,
+    Signal(i32),
+}
+
+impl Condition {
+    /// Parses `EXIT`, `ERR` or a signal name or number.
+    pub fn parse(spec: &str) -> Option<Condition> {
+        match spec.to_ascii_uppercase().as_str() {
+            "EXIT" | "0" => Some(Condition::Exit),
+            "ERR" => Some(Condition::Err),
+            _ => signals::number(spec).map(Condition::Signal),
+        }
+    }
+
+    pub fn name(self) -> String {
+        match self {
+            Condition::Exit => "EXIT".to_string(),
+            Condition::Err => "ERR".to_string(),
+            Condition::Signal(signal) => signals::name(signal),
+        }
+    }
+}
+
+/// The trap actions of a shell. An empty action ignores a signal.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Traps {
+    actions: BTreeMap<Condition, String>,
+    /// The condition whose action is running, which does not run again
+    /// until it is done.
+    running: Option<Condition>,
+}
+
+impl Traps {
+    pub fn get(&self, condition: Condition) -> Option<&str> {
+        self.actions.get(&condition).map(String::as_str)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Condition, &str)> {
+        self.actions
+            .iter()
+            .map(|(condition, action)| (*condition, action.as_str()))
+    }
+
+    /// Sets the action for `condition`, catching or ignoring its signal.
+    pub fn set(&mut self, condition: Condition, action: &str) {
+        if let Condition::Signal(signal) = condition {
+            if action.is_empty() {
+                signals::ignore(signal);
+            } else {
+                signals::catch(signal);
+            }
+        }
+        self.actions.insert(condition, action.to_string());
+    }
+
+    /// Removes the action for `condition`, giving its signal back its usual behavior.
+    pub fn reset(&mut self, condition: Condition) {
+        if let Condition::Signal(signal) = condition {
+            signals::restore(signal);
+        }
+        self.actions.remove(&condition);
+    }
+
+    /// The signals with an action to run when they arrive.
+    pub fn caught_signals(&self) -> Vec<i32> {
+        self.iter()
+            .filter_map(|(condition, action)| match condition {
+                Condition::Signal(signal) if !action.is_empty() => Some(signal),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The traps a subshell starts with: ignored signals stay ignored, but
+    /// actions are not inherited.
+    ///
+    /// Caught signals keep being recorded, so those arriving while the
+    /// subshell runs are acted on by the parent once it is back.
+    pub fn for_subshell(&self) -> Traps {
+        let actions = self
+            .actions
+            .iter()
+            .filter(|(condition, action)| {
+                matches!(condition, Condition::Signal(_)) && action.is_empty()
+            })
+            .map(|(condition, action)| (*condition, action.clone()))
+            .collect();
+        Traps {
+            actions,
+            running: None,
+        }
+    }
+
+    /// Puts back the signal dispositions of these traps after a subshell
+    /// with `subshell` traps ran in the same process.
+    pub fn reapply(&self, subshell: &Traps) {
+        for condition in subshell.actions.keys() {
+            if let Condition::Signal(signal) = *condition {
+                match self.get(*condition) {
+                    None => signals::restore(signal),
+                    Some("") => signals::ignore(signal),
+                    Some(_) => signals::catch(signal),
+                }
+            }
+        }
+    }
+}
+
+/// Runs the actions of the trapped signals that arrived since the last call.
+pub(crate) fn run_pending(shell: &mut Shell, terminal: &mut dyn Write) -> io::Result<()> {
+    for signal in signals::take_pending(shell.traps.caught_signals()) {
+        run(shell, Condition::Signal(signal), terminal)?;
+    }
+    Ok(())
+}
+
+/// Runs the action for `condition`, if there is one, keeping `$?`.
+pub(crate) fn run(
+    shell: &mut Shell,
+    condition: Condition,
+    terminal: &mut dyn Write,
+) -> io::Result<()> {
+    if shell.traps.running == Some(condition) {
+        return Ok(());
+    }
+    let Some(action) = shell
+        .traps
+        .get(condition)
+        .filter(|action| !action.is_empty())
+    else {
+        return Ok(());
+    };
+    let list = match parser::parse(action) {
+        Ok(list) => list,
+        Err(e) => {
+            Output::Stderr.report(terminal, shell.inherits_stdio(), e)?;
+            return Ok(());
+        }
+    };
+    let status = shell.last_status;
+    let outer = shell.traps.running.replace(condition);
+    // The action runs in full even when the command it follows was
+    // interrupted, and only its own `exit` takes over from that.
+    let control = shell.control.take();
+    exec::run_list(shell, &list, &Streams::default(), terminal)?;
+    shell.traps.running = outer;
+    if shell.control.is_none() {
+        shell.control = control;
+    }
+    shell.last_status = status;
+    Ok(())
+}
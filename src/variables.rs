@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+use std::env;
+
+use thiserror::Error;
+
+/// A shell variable and its attributes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Variable {
+    /// `None` for a variable that was exported or made readonly before being set.
+    pub value: Option<String>,
+    /// Passed to child processes in their environment.
+    pub exported: bool,
+    pub readonly: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("{0}: readonly variable")]
+pub struct ReadonlyError(pub String);
+
+/// The shell's variable table.
+///
+/// The process environment is only read once, to seed the table with
+/// exported variables; from then on children get [`Variables::exported`].
+#[derive(Debug, Clone, Default)]
+pub struct Variables {
+    table: HashMap<String, Variable>,
+    /// For each running function call, innermost last, the variables it made
+    /// local with what they were before.
+    scopes: Vec<Vec<(String, Option<Variable>)>>,
+}
+
+impl Variables {
+    /// Imports the process environment as exported variables.
+    pub fn from_env() -> Self {
+        let table = env::vars()
+            .map(|(name, value)| {
+                let variable = Variable {
+                    value: Some(value),
+                    exported: true,
+                    readonly: false,
+                };
+                (name, variable)
+            })
+            .collect();
+        Variables {
+            table,
+            scopes: Vec::new(),
+        }
+    }
+
+    /// The value of `name`, if it is set.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.table.get(name)?.value.as_deref()
+    }
+
+    pub fn variable(&self, name: &str) -> Option<&Variable> {
+        self.table.get(name)
+    }
+
+    /// Sets `name` to `value`, keeping its attributes.
+    pub fn set(&mut self, name: &str, value: impl Into<String>) -> Result<(), ReadonlyError> {
+        let variable = self.table.entry(name.to_string()).or_default();
+        if variable.readonly {
+            return Err(ReadonlyError(name.to_string()));
+        }
+        variable.value = Some(value.into());
+        Ok(())
+    }
+
+    /// Removes `name`; unsetting a variable that does not exist is not an error.
+    pub fn unset(&mut self, name: &str) -> Result<(), ReadonlyError> {
+        if self
+            .table
+            .get(name)
+            .is_some_and(|variable| variable.readonly)
+        {
+            return Err(ReadonlyError(name.to_string()));
+        }
+        self.table.remove(name);
+        Ok(())
+    }
+
+    pub fn export(&mut self, name: &str) {
+        self.table.entry(name.to_string()).or_default().exported = true;
+    }
+
+    pub fn set_readonly(&mut self, name: &str) {
+        self.table.entry(name.to_string()).or_default().readonly = true;
+    }
+
+    /// Puts back a variable saved with [`Variables::variable`], readonly or not.
+    pub fn restore(&mut self, name: &str, saved: Option<Variable>) {
+        match saved {
+            Some(variable) => self.table.insert(name.to_string(), variable),
+            None => self.table.remove(name),
+        };
+    }
+
+    /// Starts the scope of a function call, for [`Variables::make_local`].
+    pub fn push_scope(&mut self) {
+        self.scopes.push(Vec::new());
+    }
+
+    /// Ends the innermost function call's scope, giving the variables it
+    /// made local back what they were before.
+    pub fn pop_scope(&mut self) {
+        for (name, saved) in self.scopes.pop().unwrap_or_default().into_iter().rev() {
+            self.restore(&name, saved);
+        }
+    }
+
+    /// Makes `name` local to the innermost function call, starting unset but
+    /// keeping the variable's export attribute.
+    ///
+    /// Scoping is dynamic: until the call returns, the local variable is
+    /// the one every command sees, functions it calls included.
+    pub fn make_local(&mut self, name: &str) -> Result<(), ReadonlyError> {
+        let Some(scope) = self.scopes.last_mut() else {
+            return Ok(());
+        };
+        if scope.iter().any(|(local, _)| local == name) {
+            return Ok(());
+        }
+        let saved = self.table.get(name).cloned();
+        if saved.as_ref().is_some_and(|variable| variable.readonly) {
+            return Err(ReadonlyError(name.to_string()));
+        }
+        let exported = saved.as_ref().is_some_and(|variable| variable.exported);
+        scope.push((name.to_string(), saved));
+        if exported {
+            let local = Variable {
+                value: None,
+                exported: true,
+                readonly: false,
+            };
+            self.table.insert(name.to_string(), local);
+        } else {
+            self.table.remove(name);
+        }
+        Ok(())
+    }
+
+    /// The environment for child processes: every exported variable that has a value.
+    pub fn exported(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.table.iter().filter_map(|(name, variable)| {
+            let value = variable.value.as_deref()?;
+            variable.exported.then_some((name.as_str(), value))
+        })
+    }
+
+    /// All variables, sorted by name.
+    pub fn sorted(&self) -> Vec<(&str, &Variable)> {
+        let mut variables: Vec<_> = self
+            .table
+            .iter()
+            .map(|(name, variable)| (name.as_str(), variable))
+            .collect();
+        variables.sort_by_key(|&(name, _)| name);
+        variables
+    }
+}